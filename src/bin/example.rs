@@ -4,7 +4,6 @@
 //! It demonstrates how to set up a provider, configure pool synchronization, and execute the sync process.
 use anyhow::Result;
 use pool_sync_mantle::{Chain, PoolSync, PoolType, PoolInfo};
-use env_logger;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -3,13 +3,31 @@
 //! This module provides a builder pattern for constructing a PoolSync instance,
 //! allowing for flexible configuration of pool types and chains to be synced.
 
-use crate::pools::pool_fetchers::{UniswapV3Fetcher, MerchantMoeV2Fetcher, AgniV3Fetcher};
 
+use crate::cache::{CacheStore, FileCacheStore};
 use crate::errors::*;
+use crate::pools::pool_builder::{
+    DataSyncRevertPolicy, PopulationConfig, PopulationStrategy, RetryBudget, MAX_RETRIES,
+};
 use crate::pools::*;
+use crate::pool_sync::{
+    env_var, parse_var, CollisionPolicy, PoolFilter, ProgressCallback, VarLookup,
+};
+use crate::rpc::{CircuitBreakerConfig, RpcConfig};
 use crate::{Chain, PoolSync, PoolType};
-use std::collections::HashMap;
+use log::warn;
+use regex::Regex;
+use alloy::primitives::Address;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// Requests per second when no rate limit is configured, safe for shared public endpoints
+const DEFAULT_RATE_LIMIT: usize = 20;
+
+/// Rate limit standing in for an unlimited rpc, high enough to never be hit
+const UNLIMITED_RATE_LIMIT: u64 = 10000;
 
 /// Builder for constructing a PoolSync instance
 #[derive(Default)]
@@ -22,28 +40,81 @@ pub struct PoolSyncBuilder {
     rate_limit: Option<usize>,
     /// Optional starting block for synchronization
     start_block: Option<u64>,
+    /// Optional block to restart from, discarding the cache
+    force_start_block: Option<u64>,
+    /// Optional block window for the liquidity replay, defaults to the discovery range
+    liquidity_range: Option<(u64, u64)>,
     /// Optional ending block for synchronization  
     end_block: Option<u64>,
+    /// Blocks kept between the chain tip and the end of a sync without an end block
+    confirmations: u64,
+    /// Probe the factories for their creation event before the initial sync
+    probe_signatures: bool,
+    /// Check that every factory has contract code before syncing
+    validate_factories: bool,
+    /// Maximum number of concurrent token `symbol()` calls per population batch
+    token_concurrency: Option<usize>,
+    /// Number of times each token name call is attempted while the rpc fails
+    token_name_attempts: Option<u32>,
+    /// Optional number of pools read per call within a population batch
+    population_chunk_size: Option<usize>,
+    /// Maximum number of chunks of a population batch read at once
+    population_chunk_concurrency: Option<usize>,
+    /// Population retries allowed across a whole sync
+    retry_budget: Option<u32>,
+    /// Thresholds of the breaker pausing the sync while the rpc is degraded
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Read the protocol fee of V3 pools during population
+    protocol_fee: bool,
+    /// Read the total supply of every token during population
+    total_supply: bool,
+    /// Keep the raw data-sync tuple of every populated pool
+    debug_raw: bool,
+    /// Directory holding the per protocol cache files
+    cache_dir: Option<PathBuf>,
+    /// Optional number of blocks per cache file
+    cache_epoch_size: Option<u64>,
+    /// Optional store replacing the cache files
+    cache_store: Option<Arc<dyn CacheStore>>,
+    /// Strategy used to read the state of discovered pools
+    population_strategy: PopulationStrategy,
+    /// What to do when the data-sync contract deploy reverts
+    on_data_sync_revert: DataSyncRevertPolicy,
+    /// Optional cap on the number of ticks kept per V3 pool
+    max_ticks_per_pool: Option<usize>,
+    /// Rpc endpoints, falling back to the environment when unset
+    rpc_config: RpcConfig,
+    /// Optional endpoint for the liquidity phase, the archive node when unset
+    liquidity_url: Option<String>,
+    /// Optional http client the providers are built on
+    http_client: Option<reqwest::Client>,
+    /// Whether the creation transaction sender of new pools is fetched
+    capture_creators: bool,
+    /// Whether the tokens of new pools are probed for ERC20 compliance
+    check_erc20: bool,
+    /// Predicates every newly populated pool must pass to be kept
+    filters: Vec<PoolFilter>,
+    /// Callback told the progress of the whole sync
+    on_progress: Option<ProgressCallback>,
+    /// Pool addresses dropped right after discovery
+    excluded_addresses: HashSet<Address>,
+    /// Pattern one of the token symbols of a pool must match for it to be kept
+    symbol_regex: Option<String>,
+    /// How pools sharing an address across protocols are merged
+    collision_policy: CollisionPolicy,
+    /// Number of protocols synced at the same time
+    max_concurrent_protocols: Option<usize>,
+    /// Optional file every newly synced pool is appended to as a line of JSON
+    ndjson_sink: Option<PathBuf>,
+    /// Whether the summary table is logged at the end of a sync
+    log_summary: Option<bool>,
 }
 
 impl PoolSyncBuilder {
     /// Adds a new pool type to be synced
     /// The builder instance for method chaining
     pub fn add_pool(mut self, pool_type: PoolType) -> Self {
-        match pool_type {
-            PoolType::UniswapV3 => {
-                self.fetchers
-                    .insert(PoolType::UniswapV3, Arc::new(UniswapV3Fetcher));
-            }
-            PoolType::MerchantMoe => {
-                self.fetchers
-                    .insert(PoolType::MerchantMoe, Arc::new(MerchantMoeV2Fetcher));
-            }
-            PoolType::Agni => {
-                self.fetchers
-                    .insert(PoolType::Agni, Arc::new(AgniV3Fetcher));
-            }
-        }
+        self.fetchers.insert(pool_type, pool_type.fetcher());
         self
     }
 
@@ -62,20 +133,30 @@ impl PoolSyncBuilder {
         self
     }
 
-    /// Set the rate limit of the rpc
+    /// Set the rate limit of the rpc (defaults to 20), 0 runs without one.
+    /// `POOLSYNC_RATE_LIMIT` overrides it when set
     /// The builder instance for method chaining
     pub fn rate_limit(mut self, rate_limit: usize) -> Self {
         self.rate_limit = Some(rate_limit);
         self
     }
 
-    /// Set the starting block for synchronization
+    /// Set the minimum starting block for synchronization. A cache that has already
+    /// synced past it resumes from the cache instead
     /// The builder instance for method chaining
     pub fn start_block(mut self, start_block: u64) -> Self {
         self.start_block = Some(start_block);
         self
     }
 
+    /// Start synchronization exactly at this block, discarding any cached pools.
+    /// Takes precedence over both `start_block` and the cache
+    /// The builder instance for method chaining
+    pub fn force_start_block(mut self, block: u64) -> Self {
+        self.force_start_block = Some(block);
+        self
+    }
+
     /// Set the ending block for synchronization
     /// The builder instance for method chaining  
     pub fn end_block(mut self, end_block: u64) -> Self {
@@ -83,7 +164,26 @@ impl PoolSyncBuilder {
         self
     }
 
-    /// Set both start and end blocks for synchronization
+    /// Stop syncs to the chain tip `confirmations` blocks short of it, so pools created in
+    /// blocks that may still be reorged out are left for a later sync. Ignored when an
+    /// end block is set
+    /// The builder instance for method chaining
+    pub fn confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    /// Set both start and end blocks for synchronization. Each protocol resumes from its
+    /// cache when the cache is past `start_block`, never fetches below `start_block`, and
+    /// stops at `end_block`:
+    ///
+    /// | cache (`last_synced_block`) | blocks fetched                  |
+    /// |-----------------------------|---------------------------------|
+    /// | before `start_block`        | `start_block..=end_block`       |
+    /// | in `start_block..end_block` | `cache + 1..=end_block`         |
+    /// | at or past `end_block`      | none, the cached pools are kept |
+    ///
+    /// `force_start_block` discards the cache instead
     /// The builder instance for method chaining
     pub fn block_range(mut self, start_block: u64, end_block: u64) -> Self {
         self.start_block = Some(start_block);
@@ -91,11 +191,283 @@ impl PoolSyncBuilder {
         self
     }
 
+    /// Replay the liquidity events of new pools over this block window instead of the
+    /// discovery range, clamped to the blocks of the sync. It only seeds a pool's first
+    /// replay, cached pools are caught up over each sync's own blocks. A window skipping
+    /// events of a pool leaves its ticks incomplete
+    /// The builder instance for method chaining
+    pub fn liquidity_range(mut self, start_block: u64, end_block: u64) -> Self {
+        self.liquidity_range = Some((start_block, end_block));
+        self
+    }
+
+    /// Probe each factory with a small log query before its initial sync to confirm
+    /// that the creation event topic is actually emitted
+    /// The builder instance for method chaining
+    pub fn probe_event_signatures(mut self, probe: bool) -> Self {
+        self.probe_signatures = probe;
+        self
+    }
+
+    /// Check that every enabled fetcher's factory address has contract code with
+    /// `eth_getCode` before syncing, failing with `PoolSyncError::NoCodeAtFactory` instead
+    /// of silently discovering zero pools at a mistyped address
+    /// The builder instance for method chaining
+    pub fn validate_factories(mut self, validate: bool) -> Self {
+        self.validate_factories = validate;
+        self
+    }
+
+    /// Set how many token `symbol()` calls may run at once while populating a batch
+    /// of pools. The effective value never exceeds the rate limit
+    /// The builder instance for method chaining
+    pub fn token_fetch_concurrency(mut self, concurrency: usize) -> Self {
+        self.token_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Split each population batch into chunks of `chunk_size` pools, each read with its
+    /// own data-sync call and up to `concurrency` of them at once. Keeps every call under
+    /// the gas limit of the provider. The concurrency never exceeds the rate limit
+    /// The builder instance for method chaining
+    pub fn population_chunks(mut self, chunk_size: usize, concurrency: usize) -> Self {
+        self.population_chunk_size = Some(chunk_size);
+        self.population_chunk_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Set how many times each token name call is attempted while the rpc keeps failing.
+    /// Reverting calls move straight on to the next fallback
+    /// The builder instance for method chaining
+    pub fn token_name_attempts(mut self, attempts: u32) -> Self {
+        self.token_name_attempts = Some(attempts);
+        self
+    }
+
+    /// Cap the population retries of a whole sync. Once `retries` retries have been spent
+    /// across all batches the sync aborts with `PoolSyncError::RetryBudgetExceeded`
+    /// The builder instance for method chaining
+    pub fn retry_budget(mut self, retries: u32) -> Self {
+        self.retry_budget = Some(retries);
+        self
+    }
+
+    /// Pause the whole sync while the rpc is degraded. Once at least `error_rate` of the last
+    /// `window` requests failed, every request waits out `cooldown`, then a single probe
+    /// request decides whether requests resume or another cooldown starts
+    /// The builder instance for method chaining
+    pub fn circuit_breaker(mut self, error_rate: f64, window: usize, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some(CircuitBreakerConfig {
+            error_rate,
+            window,
+            cooldown,
+        });
+        self
+    }
+
+    /// Read each V3 pool's protocol fee from `slot0` during population, at the cost of
+    /// one extra call per pool. Needed for accurate `PoolInfo::lp_fee` values
+    /// The builder instance for method chaining
+    pub fn fetch_protocol_fee(mut self, enabled: bool) -> Self {
+        self.protocol_fee = enabled;
+        self
+    }
+
+    /// Read every token's `totalSupply()` while fetching its name, at the block the pool
+    /// state is read at, exposed through `PoolInfo::token0_total_supply` and
+    /// `token1_total_supply`
+    /// The builder instance for method chaining
+    pub fn fetch_total_supply(mut self, enabled: bool) -> Self {
+        self.total_supply = enabled;
+        self
+    }
+
+    /// Keep the data-sync tuple each new pool was decoded from in its `debug_raw` field, one
+    /// formatted value per field, to diagnose a tuple layout the decoding does not match.
+    /// Pools read with `PopulationStrategy::RpcBatch` have no tuple and keep `None`
+    /// The builder instance for method chaining
+    pub fn debug_raw_tuples(mut self, enabled: bool) -> Self {
+        self.debug_raw = enabled;
+        self
+    }
+
+    /// Set how the state of discovered pools is read. Defaults to the data-sync
+    /// contracts, `PopulationStrategy::RpcBatch` works on providers that reject
+    /// state-override deploys
+    /// The builder instance for method chaining
+    pub fn population_strategy(mut self, strategy: PopulationStrategy) -> Self {
+        self.population_strategy = strategy;
+        self
+    }
+
+    /// Set what population does when the data-sync contract deploy reverts. Defaults to
+    /// reading the batch again with rpc calls, `DataSyncRevertPolicy::Fail` fails the sync
+    /// with `PoolSyncError::DataSyncUnsupported` instead
+    /// The builder instance for method chaining
+    pub fn on_data_sync_revert(mut self, policy: DataSyncRevertPolicy) -> Self {
+        self.on_data_sync_revert = policy;
+        self
+    }
+
+    /// Cap the number of ticks kept per V3 pool. Pools above the limit keep only the
+    /// ticks nearest their current tick and are marked as truncated
+    /// The builder instance for method chaining
+    pub fn max_ticks_per_pool(mut self, max_ticks: usize) -> Self {
+        self.max_ticks_per_pool = Some(max_ticks);
+        self
+    }
+
+    /// Set the rpc endpoints to sync against instead of reading them from the environment
+    /// The builder instance for method chaining
+    pub fn rpc_config(mut self, rpc_config: RpcConfig) -> Self {
+        self.rpc_config = rpc_config;
+        self
+    }
+
+    /// Read the liquidity events from a separate endpoint, e.g. a node dedicated to log
+    /// queries. Discovery keeps using the archive node and population the full node
+    /// The builder instance for method chaining
+    pub fn liquidity_provider(mut self, url: impl Into<String>) -> Self {
+        self.liquidity_url = Some(url.into());
+        self
+    }
+
+    /// Send every rpc request through a preconfigured http client, e.g. one with
+    /// `default_headers` carrying the api key an authenticated endpoint expects. Used by
+    /// the archive, full and liquidity providers alike
+    /// The builder instance for method chaining
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Fetch the sender of every new pool's creation transaction during discovery, exposed
+    /// as `PoolInfo::creator`. Costs one batched `eth_getTransactionByHash` per pool, retried
+    /// like the log queries. Pools whose lookups keep failing are kept without a creator
+    /// The builder instance for method chaining
+    pub fn capture_creators(mut self, enabled: bool) -> Self {
+        self.capture_creators = enabled;
+        self
+    }
+
+    /// Probe the tokens of every new pool for `decimals()`, `symbol()` and `totalSupply()`,
+    /// listing the ones that fail any of them in `SyncStats::non_erc20_tokens`. Costs three
+    /// calls per distinct token
+    /// The builder instance for method chaining
+    pub fn check_erc20_compliance(mut self, enabled: bool) -> Self {
+        self.check_erc20 = enabled;
+        self
+    }
+
+    /// Drop pools failing the predicate right after population, before they are synced or
+    /// cached. Calling this again adds another predicate, a pool must pass all of them
+    /// The builder instance for method chaining
+    pub fn filter(mut self, predicate: PoolFilter) -> Self {
+        self.filters.push(predicate);
+        self
+    }
+
+    /// Call `callback` with the progress of the whole sync, summed over every protocol, at
+    /// the start of each round and whenever a protocol finishes it. Meant for a global ETA
+    /// rather than the per chunk progress bars
+    /// The builder instance for method chaining
+    pub fn on_progress(mut self, callback: ProgressCallback) -> Self {
+        self.on_progress = Some(callback);
+        self
+    }
+
+    /// Skip the given pool addresses, e.g. known scam or broken pools. They are dropped right
+    /// after discovery, so they are never populated or cached. Calling this again adds to
+    /// the excluded addresses
+    /// The builder instance for method chaining
+    pub fn exclude_addresses(mut self, addresses: HashSet<Address>) -> Self {
+        self.excluded_addresses.extend(addresses);
+        self
+    }
+
+    /// Keep only the newly populated pools where either token's symbol matches the regex,
+    /// e.g. `"(?i)pepe"`. Applied like `filter`, after the symbols are fetched
+    /// The builder instance for method chaining
+    pub fn symbol_regex(mut self, pattern: impl Into<String>) -> Self {
+        self.symbol_regex = Some(pattern.into());
+        self
+    }
+
+    /// Set how pools found at the same address under more than one protocol are merged into
+    /// the sync result (defaults to keeping all of them). Collisions are warned about either way
+    /// The builder instance for method chaining
+    pub fn collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
+    }
+
+    /// Set how many protocols are synced at the same time (defaults to 1, syncing them one
+    /// after another). The protocols share the rate limit, so the rpc never sees more than
+    /// the configured rate however many run at once
+    /// The builder instance for method chaining
+    pub fn max_concurrent_protocols(mut self, max_concurrent_protocols: usize) -> Self {
+        self.max_concurrent_protocols = Some(max_concurrent_protocols);
+        self
+    }
+
+    /// Append every newly synced pool to the file as a line of JSON while the sync runs, each
+    /// protocol's pools as soon as they are ready. The file is created if missing and never
+    /// truncated, so a pipeline can tail it instead of waiting on the result
+    /// The builder instance for method chaining
+    pub fn ndjson_sink(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ndjson_sink = Some(path.into());
+        self
+    }
+
+    /// Log a table of every protocol's pool counts, covered blocks and sync duration at
+    /// `info` when a sync ends (defaults to true)
+    /// The builder instance for method chaining
+    pub fn log_summary(mut self, enabled: bool) -> Self {
+        self.log_summary = Some(enabled);
+        self
+    }
+
+    /// Set the directory the cache files are read from and written to (defaults to `cache`)
+    /// The builder instance for method chaining
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Split each protocol's cache into one file per `blocks` blocks, by the block each pool
+    /// was created in, e.g. `Mantle_UniswapV3_cache_0-999999.json`. The main cache file
+    /// keeps the sync progress, and epoch files moved out of the cache directory are simply
+    /// not loaded, so old epochs can be archived. Zero keeps a single file
+    /// The builder instance for method chaining
+    pub fn cache_epoch_size(mut self, blocks: u64) -> Self {
+        self.cache_epoch_size = Some(blocks);
+        self
+    }
+
+    /// Read and write the caches through a custom store instead of the files in `cache_dir`,
+    /// e.g. one backed by Redis or a database so several processes share the caches.
+    /// `cache_dir` and `cache_epoch_size` only apply to the default file store
+    /// The builder instance for method chaining
+    pub fn cache_store(mut self, store: Arc<dyn CacheStore>) -> Self {
+        self.cache_store = Some(store);
+        self
+    }
+
     /// Consumes the builder and produces a constructed PoolSync
     pub fn build(self) -> Result<PoolSync, PoolSyncError> {
+        self.build_with_vars(&env_var)
+    }
+
+    // `build` reading POOLSYNC_RATE_LIMIT through `vars` instead of the process environment
+    pub(crate) fn build_with_vars(self, vars: VarLookup) -> Result<PoolSync, PoolSyncError> {
         // Ensure the chain is set
         let chain = self.chain.ok_or(PoolSyncError::ChainNotSet)?;
 
+        // Ensure there is something to sync
+        if self.fetchers.is_empty() {
+            return Err(PoolSyncError::NoPoolTypesAdded);
+        }
+
         // Ensure all the pools are supported
         for pool_type in self.fetchers.keys() {
             if !chain.supported(pool_type) {
@@ -103,9 +475,30 @@ impl PoolSyncBuilder {
             }
         }
 
-        // set rate limit to user defined if specified, otherwise set high value
-        // that will not be hit to simulate unlimited requests
-        let rate_limit = self.rate_limit.unwrap_or(10000) as u64;
+        // Ensure the chain can serve the population strategy
+        check_strategy(chain, self.population_strategy, chain.supported_strategies())?;
+
+        // Ensure every creation event signature hashes to a real topic, every factory
+        // address is set and every pool family can build its pools
+        for fetcher in self.fetchers.values() {
+            validate_event_signature(fetcher.as_ref())?;
+            validate_factory_address(fetcher.as_ref(), chain)?;
+            validate_pool_family(fetcher.as_ref())?;
+        }
+
+        // the symbol pattern is one more filter
+        let mut filters = self.filters;
+        if let Some(pattern) = &self.symbol_regex {
+            let regex =
+                Regex::new(pattern).map_err(|e| PoolSyncError::InvalidSymbolRegex(e.to_string()))?;
+            filters.push(Box::new(move |pool: &Pool| {
+                regex.is_match(&pool.token0_name()) || regex.is_match(&pool.token1_name())
+            }));
+        }
+
+        // the environment overrides the builder, so a deployment can throttle a sync
+        // against a shared endpoint without a rebuild
+        let rate_limit = resolve_rate_limit(vars, self.rate_limit)?;
 
         // Construct PoolSync
         Ok(PoolSync {
@@ -113,7 +506,79 @@ impl PoolSyncBuilder {
             rate_limit,
             chain,
             start_block: self.start_block,
+            force_start_block: self.force_start_block,
+            liquidity_range: self.liquidity_range,
             end_block: self.end_block,
+            confirmations: self.confirmations,
+            probe_signatures: self.probe_signatures,
+            validate_factories: self.validate_factories,
+            population: PopulationConfig {
+                strategy: self.population_strategy,
+                token_concurrency: self.token_concurrency.unwrap_or(10),
+                token_name_attempts: self.token_name_attempts.unwrap_or(MAX_RETRIES + 1),
+                protocol_fee: self.protocol_fee,
+                total_supply: self.total_supply,
+                block: None,
+                retry_budget: RetryBudget::default(),
+                on_data_sync_revert: self.on_data_sync_revert,
+                known_tokens: Arc::default(),
+                chunk_size: self.population_chunk_size,
+                chunk_concurrency: self.population_chunk_concurrency.unwrap_or(1),
+                debug_raw: self.debug_raw,
+                circuit_breaker: Default::default(),
+                limiter: Default::default(),
+                rate: Default::default(),
+            },
+            retry_budget: self.retry_budget,
+            circuit_breaker: self.circuit_breaker,
+            max_ticks_per_pool: self.max_ticks_per_pool,
+            rpc_config: self.rpc_config,
+            liquidity_url: self.liquidity_url,
+            http_client: self.http_client,
+            capture_creators: self.capture_creators,
+            check_erc20: self.check_erc20,
+            filters,
+            on_progress: self.on_progress,
+            excluded_addresses: self.excluded_addresses,
+            collision_policy: self.collision_policy,
+            max_concurrent_protocols: self.max_concurrent_protocols.unwrap_or(1).max(1),
+            ndjson_sink: self.ndjson_sink,
+            log_summary: self.log_summary.unwrap_or(true),
+            cache_store: self.cache_store.unwrap_or_else(|| {
+                Arc::new(FileCacheStore {
+                    dir: self.cache_dir.unwrap_or_else(|| PathBuf::from("cache")),
+                    epoch_size: self.cache_epoch_size.filter(|blocks| *blocks > 0),
+                })
+            }),
         })
     }
-}
\ No newline at end of file
+}
+
+/// Rejects a population strategy missing from the strategies supported on the chain
+pub(crate) fn check_strategy(
+    chain: Chain,
+    strategy: PopulationStrategy,
+    supported: &[PopulationStrategy],
+) -> Result<(), PoolSyncError> {
+    if supported.contains(&strategy) {
+        Ok(())
+    } else {
+        Err(PoolSyncError::UnsupportedStrategy { chain, strategy })
+    }
+}
+
+/// Picks the rate limit from POOLSYNC_RATE_LIMIT in `vars`, then the builder, then the
+/// default. A limit of 0 runs unlimited
+pub(crate) fn resolve_rate_limit(
+    vars: VarLookup,
+    configured: Option<usize>,
+) -> Result<u64, PoolSyncError> {
+    let env = parse_var(vars, "POOLSYNC_RATE_LIMIT")?;
+    Ok(match env.or(configured).unwrap_or(DEFAULT_RATE_LIMIT) {
+        0 => {
+            warn!("Running without a rate limit, a shared rpc endpoint may throttle or ban the sync");
+            UNLIMITED_RATE_LIMIT
+        }
+        rate_limit => rate_limit as u64,
+    })
+}
@@ -4,60 +4,454 @@
 //! including structures and functions for reading from and writing to cache files.
 //!
 use crate::chain::Chain;
-use crate::pools::{Pool, PoolType};
+use crate::errors::PoolSyncError;
+use crate::pools::pool_structures::tick_bitmap_codec;
+use crate::pools::{Pool, PoolCreation, PoolInfo, PoolType};
+use alloy::primitives::{Address, Bytes};
 use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// How far a discovered pool has got through population
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PopulationState {
+    /// Discovered by a sync that stopped before populating it
+    Discovered,
+    /// Populated and kept in the cache's pools
+    Populated,
+    /// Its population batch failed after all retries
+    Failed,
+}
+
+/// A discovered pool that is not populated yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingPool {
+    pub creation: PoolCreation,
+    /// Creation index reserved for the pool at discovery
+    pub creation_index: u64,
+    pub state: PopulationState,
+}
+
+/// Everything synced for one protocol on a chain, what a `CacheStore` keeps between syncs
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PoolCache {
     pub last_synced_block: u64,
     pub pool_type: PoolType,
     pub pools: Vec<Pool>,
     pub is_initial_sync: bool,
+    /// Creation index the next discovered pool will receive
+    #[serde(default)]
+    pub next_creation_index: u64,
+    /// Discovered pools the next sync populates before anything new, so discovery and
+    /// population resume independently
+    #[serde(default)]
+    pub pending: Vec<PendingPool>,
+    /// Block discovery reached in a sync that has not finished, `None` once it completes
+    #[serde(default)]
+    pub discovered_block: Option<u64>,
+}
+
+impl PoolCache {
+    /// An empty cache for a pool type that was never synced on the chain
+    pub fn new(pool_type: PoolType, chain: Chain) -> Self {
+        let last_synced_block = if matches!(chain, Chain::Mantle | Chain::Local { .. }) {
+            0
+        } else {
+            9_999_999
+        };
+        PoolCache {
+            last_synced_block,
+            pool_type,
+            pools: Vec::new(),
+            is_initial_sync: true,
+            next_creation_index: 0,
+            pending: Vec::new(),
+            discovered_block: None,
+        }
+    }
+}
+
+/// Display name and decimals of a token, resolved ahead of a sync by `PoolSync::warmup_tokens`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub name: String,
+    /// `None` when the token's `decimals()` reverted or could not be read
+    pub decimals: Option<u8>,
+}
+
+/// Token metadata of a chain by token address
+pub type TokenCache = BTreeMap<Address, TokenMetadata>;
+
+/// A file a sync would read and write the cache of a pool type in, see `PoolSync::cache_plan`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheFileInfo {
+    pub path: PathBuf,
+    pub pool_type: PoolType,
+    /// Whether the file is on disk, a missing file is created by the sync
+    pub exists: bool,
+    /// Block the pool type's cache was synced to, `None` when it does not exist yet or
+    /// cannot be read
+    pub last_synced_block: Option<u64>,
+}
+
+/// Storage the pool caches are read from and written to. The caches are files in a
+/// directory by default, implement it to keep them elsewhere, e.g. in Redis or a database
+/// shared by several sync processes
+pub trait CacheStore: Send + Sync {
+    /// The cache of a pool type on a chain, `None` when none was written yet
+    fn read(&self, pool_type: &PoolType, chain: Chain) -> Result<Option<PoolCache>, PoolSyncError>;
+
+    /// Stores the cache, replacing the one of the same pool type and chain
+    fn write(&self, cache: &PoolCache, chain: Chain) -> Result<(), PoolSyncError>;
+
+    /// Stores the discovery progress of an unfinished sync: the cache's pending pools,
+    /// discovered block and next creation index. Writes the whole cache by default, stores
+    /// that can update these alone should, as the pools are unchanged at that point
+    fn write_checkpoint(&self, cache: &PoolCache, chain: Chain) -> Result<(), PoolSyncError> {
+        self.write(cache, chain)
+    }
+
+    /// The files the cache of a pool type is kept in, for stores backed by files. Stores
+    /// keeping their caches elsewhere return none
+    fn cache_files(&self, _pool_type: &PoolType, _chain: Chain) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// The token metadata stored for a chain. Stores that keep no token cache return it empty
+    fn read_tokens(&self, _chain: Chain) -> Result<TokenCache, PoolSyncError> {
+        Ok(TokenCache::new())
+    }
+
+    /// Stores the token metadata of a chain, replacing what was stored before
+    fn write_tokens(&self, _tokens: &TokenCache, _chain: Chain) -> Result<(), PoolSyncError> {
+        Ok(())
+    }
+}
+
+/// The default cache store, one JSON file per protocol in `dir`, optionally split into
+/// per-epoch files, see `write_cache_files`
+#[derive(Debug, Clone)]
+pub struct FileCacheStore {
+    /// Directory holding the cache files
+    pub dir: PathBuf,
+    /// Optional number of blocks per epoch file
+    pub epoch_size: Option<u64>,
+}
+
+impl CacheStore for FileCacheStore {
+    fn read(&self, pool_type: &PoolType, chain: Chain) -> Result<Option<PoolCache>, PoolSyncError> {
+        if !cache_file_path(&self.dir, pool_type, chain).exists()
+            && !checkpoint_file_path(&self.dir, pool_type, chain).exists()
+        {
+            return Ok(None);
+        }
+        read_cache_file(pool_type, chain, &self.dir)
+            .map(Some)
+            .map_err(|e| PoolSyncError::CacheError(format!("{:#}", e)))
+    }
+
+    fn write(&self, cache: &PoolCache, chain: Chain) -> Result<(), PoolSyncError> {
+        std::fs::create_dir_all(&self.dir)?;
+        write_cache_files(cache, chain, &self.dir, self.epoch_size)
+            .map_err(|e| PoolSyncError::CacheError(format!("{:#}", e)))
+    }
+
+    fn write_checkpoint(&self, cache: &PoolCache, chain: Chain) -> Result<(), PoolSyncError> {
+        std::fs::create_dir_all(&self.dir)?;
+        write_cache_checkpoint(cache, chain, &self.dir)
+            .map_err(|e| PoolSyncError::CacheError(format!("{:#}", e)))
+    }
+
+    fn cache_files(&self, pool_type: &PoolType, chain: Chain) -> Vec<PathBuf> {
+        let mut files = vec![cache_file_path(&self.dir, pool_type, chain)];
+        files.extend(epoch_files(&self.dir, pool_type, chain).unwrap_or_default());
+        let checkpoint = checkpoint_file_path(&self.dir, pool_type, chain);
+        if checkpoint.exists() {
+            files.push(checkpoint);
+        }
+        files
+    }
+
+    fn read_tokens(&self, chain: Chain) -> Result<TokenCache, PoolSyncError> {
+        let path = token_cache_path(&self.dir, chain);
+        if !path.exists() {
+            return Ok(TokenCache::new());
+        }
+        let file = File::open(&path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    fn write_tokens(&self, tokens: &TokenCache, chain: Chain) -> Result<(), PoolSyncError> {
+        std::fs::create_dir_all(&self.dir)?;
+        write_json(&token_cache_path(&self.dir, chain), tokens)
+            .map_err(|e| PoolSyncError::CacheError(format!("{:#}", e)))
+    }
+}
+
+/// The cache file layout on disk, the pools kept in the main file being written as
+/// `StoredPool`s and read as `LoadedPool`s
+#[derive(Serialize, Deserialize)]
+struct PoolCacheFile<'a, P> {
+    last_synced_block: u64,
+    pool_type: PoolType,
+    pools: Vec<P>,
+    is_initial_sync: bool,
+    #[serde(default)]
+    next_creation_index: u64,
+    #[serde(default)]
+    pending: Cow<'a, [PendingPool]>,
+    #[serde(default)]
+    discovered_block: Option<u64>,
+}
+
+/// A pool written to a cache file, with the tick bitmap of a V3 pool packed by
+/// `tick_bitmap_codec`
+struct StoredPool<'a>(&'a Pool);
+
+impl Serialize for StoredPool<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(self.0).map_err(S::Error::custom)?;
+        if let (Some(v3), Value::Object(variant)) = (self.0.get_v3(), &mut value) {
+            let packed = Bytes::from(tick_bitmap_codec::encode(&v3.tick_bitmap));
+            for fields in variant.values_mut() {
+                fields["tick_bitmap"] = Value::String(packed.to_string());
+            }
+        }
+        value.serialize(serializer)
+    }
 }
 
-pub fn read_cache_file(pool_type: &PoolType, chain: Chain) -> Result<PoolCache> {
-    let pool_cache_file = format!("cache/{}_{}_cache.json", chain, pool_type);
-    if Path::new(&pool_cache_file).exists() {
+/// A pool read from a cache file, unpacking a packed tick bitmap and taking the plain map
+/// written by earlier versions as it is
+struct LoadedPool(Pool);
+
+impl<'de> Deserialize<'de> for LoadedPool {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        if let Value::Object(variant) = &mut value {
+            for fields in variant.values_mut() {
+                if let Some(Value::String(packed)) = fields.get("tick_bitmap") {
+                    let packed: Bytes = packed.parse().map_err(D::Error::custom)?;
+                    let bitmap = tick_bitmap_codec::decode(&packed).map_err(D::Error::custom)?;
+                    fields["tick_bitmap"] = serde_json::to_value(bitmap).map_err(D::Error::custom)?;
+                }
+            }
+        }
+        serde_json::from_value(value).map(LoadedPool).map_err(D::Error::custom)
+    }
+}
+
+/// The discovery progress of an unfinished sync, kept next to the cache file so that
+/// checkpointing it leaves the pools untouched. It takes precedence over the progress in
+/// the cache file and is removed when the whole cache is written
+#[derive(Serialize, Deserialize)]
+struct CacheCheckpoint {
+    next_creation_index: u64,
+    pending: Vec<PendingPool>,
+    discovered_block: Option<u64>,
+}
+
+/// Path of the cache file for a pool type on a chain
+pub fn cache_file_path(cache_dir: &Path, pool_type: &PoolType, chain: Chain) -> PathBuf {
+    cache_dir.join(format!("{}_{}_cache.json", chain, pool_type))
+}
+
+/// Path of the discovery checkpoint of a pool type on a chain
+pub fn checkpoint_file_path(cache_dir: &Path, pool_type: &PoolType, chain: Chain) -> PathBuf {
+    cache_dir.join(format!("{}_{}_checkpoint.json", chain, pool_type))
+}
+
+/// Path of the token metadata file of a chain
+pub fn token_cache_path(cache_dir: &Path, chain: Chain) -> PathBuf {
+    cache_dir.join(format!("{}_token_cache.json", chain))
+}
+
+/// Path of the cache file holding the pools of a pool type created in `first_block..=last_block`
+pub fn epoch_file_path(
+    cache_dir: &Path,
+    pool_type: &PoolType,
+    chain: Chain,
+    first_block: u64,
+    last_block: u64,
+) -> PathBuf {
+    cache_dir.join(format!(
+        "{}_{}_cache_{}-{}.json",
+        chain, pool_type, first_block, last_block
+    ))
+}
+
+/// The epoch files of a pool type in the cache directory, ordered by their first block
+pub fn epoch_files(cache_dir: &Path, pool_type: &PoolType, chain: Chain) -> Result<Vec<PathBuf>> {
+    let prefix = format!("{}_{}_cache_", chain, pool_type);
+    let mut files = BTreeMap::new();
+    if !cache_dir.exists() {
+        return Ok(Vec::new());
+    }
+    for entry in std::fs::read_dir(cache_dir)
+        .with_context(|| format!("Failed to read cache directory: {}", cache_dir.display()))?
+    {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let first_block = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".json"))
+            .and_then(|range| range.split_once('-'))
+            .and_then(|(first, _)| first.parse::<u64>().ok());
+        if let Some(first_block) = first_block {
+            files.insert(first_block, path);
+        }
+    }
+    Ok(files.into_values().collect())
+}
+
+/// Reads the cache of a pool type, appending the pools of every epoch file found next to it
+/// and applying the discovery checkpoint of an unfinished sync
+pub fn read_cache_file(pool_type: &PoolType, chain: Chain, cache_dir: &Path) -> Result<PoolCache> {
+    let pool_cache_file = cache_file_path(cache_dir, pool_type, chain);
+    let mut pool_cache = if pool_cache_file.exists() {
         let file = File::open(&pool_cache_file)
-            .with_context(|| format!("Failed to open cache file: {}", pool_cache_file))?;
+            .with_context(|| format!("Failed to open cache file: {}", pool_cache_file.display()))?;
         let reader = BufReader::new(file);
-        let pool_cache: PoolCache = serde_json::from_reader(reader).with_context(|| {
-            format!("Failed to deserialize cache from file: {}", pool_cache_file)
+        let file: PoolCacheFile<LoadedPool> = serde_json::from_reader(reader).with_context(|| {
+            format!("Failed to deserialize cache from file: {}", pool_cache_file.display())
         })?;
-        Ok(pool_cache)
+        let mut pool_cache = PoolCache {
+            last_synced_block: file.last_synced_block,
+            pool_type: file.pool_type,
+            pools: file.pools.into_iter().map(|pool| pool.0).collect(),
+            is_initial_sync: file.is_initial_sync,
+            next_creation_index: file.next_creation_index,
+            pending: file.pending.into_owned(),
+            discovered_block: file.discovered_block,
+        };
+        for epoch_file in epoch_files(cache_dir, pool_type, chain)? {
+            let file = File::open(&epoch_file)
+                .with_context(|| format!("Failed to open cache file: {}", epoch_file.display()))?;
+            let pools: Vec<LoadedPool> = serde_json::from_reader(BufReader::new(file))
+                .with_context(|| {
+                    format!("Failed to deserialize cache from file: {}", epoch_file.display())
+                })?;
+            pool_cache.pools.extend(pools.into_iter().map(|pool| pool.0));
+        }
+        pool_cache
     } else {
-        if Chain::Mantle == chain {
-            Ok(PoolCache {
-                last_synced_block: 0,
-                pool_type: *pool_type,
-                pools: Vec::new(),
-                is_initial_sync: true,
-            })
-        } else {
-            Ok(PoolCache {
-                last_synced_block: 9_999_999,
-                pool_type: *pool_type,
-                pools: Vec::new(),
-                is_initial_sync: true,
-            })
+        PoolCache::new(*pool_type, chain)
+    };
+
+    let checkpoint_file = checkpoint_file_path(cache_dir, pool_type, chain);
+    if checkpoint_file.exists() {
+        let file = File::open(&checkpoint_file)
+            .with_context(|| format!("Failed to open cache file: {}", checkpoint_file.display()))?;
+        let checkpoint: CacheCheckpoint = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| {
+                format!("Failed to deserialize cache from file: {}", checkpoint_file.display())
+            })?;
+        pool_cache.next_creation_index = checkpoint.next_creation_index;
+        pool_cache.pending = checkpoint.pending;
+        pool_cache.discovered_block = checkpoint.discovered_block;
+    }
+    Ok(pool_cache)
+}
+
+/// Writes the whole cache of a pool type to a single file
+#[cfg(test)]
+pub fn write_cache_file(pool_cache: &PoolCache, chain: Chain, cache_dir: &Path) -> Result<()> {
+    write_cache_files(pool_cache, chain, cache_dir, None)
+}
+
+/// Writes the cache of a pool type. With an `epoch_size` the pools are split by creation
+/// block into one file per `epoch_size` blocks, and only the sync progress and the pools
+/// without a known creation block stay in the main file. Epoch files left over from an
+/// earlier layout and the discovery checkpoint are removed
+pub fn write_cache_files(
+    pool_cache: &PoolCache,
+    chain: Chain,
+    cache_dir: &Path,
+    epoch_size: Option<u64>,
+) -> Result<()> {
+    let pool_type = &pool_cache.pool_type;
+    let mut main_pools = Vec::new();
+    let mut epochs: BTreeMap<u64, Vec<StoredPool>> = BTreeMap::new();
+    for pool in &pool_cache.pools {
+        match (epoch_size, pool.created_at_block()) {
+            (Some(size), Some(block)) if size > 0 => {
+                epochs.entry(block / size).or_default().push(StoredPool(pool))
+            }
+            _ => main_pools.push(StoredPool(pool)),
         }
     }
+
+    // the checkpoint goes first, a sync interrupted before the main file is written then
+    // only rediscovers the blocks it covered
+    let checkpoint = checkpoint_file_path(cache_dir, pool_type, chain);
+    if checkpoint.exists() {
+        std::fs::remove_file(&checkpoint)
+            .with_context(|| format!("Failed to remove cache file: {}", checkpoint.display()))?;
+    }
+
+    let mut written = Vec::new();
+    for (epoch, pools) in &epochs {
+        let size = epoch_size.unwrap_or_default();
+        let (first_block, last_block) = (epoch * size, (epoch + 1) * size - 1);
+        let path = epoch_file_path(cache_dir, pool_type, chain, first_block, last_block);
+        write_json(&path, pools)?;
+        written.push(path);
+    }
+
+    write_json(
+        &cache_file_path(cache_dir, pool_type, chain),
+        &PoolCacheFile {
+            last_synced_block: pool_cache.last_synced_block,
+            pool_type: *pool_type,
+            pools: main_pools,
+            is_initial_sync: pool_cache.is_initial_sync,
+            next_creation_index: pool_cache.next_creation_index,
+            pending: Cow::Borrowed(&pool_cache.pending),
+            discovered_block: pool_cache.discovered_block,
+        },
+    )?;
+
+    for stale in epoch_files(cache_dir, pool_type, chain)? {
+        if !written.contains(&stale) {
+            std::fs::remove_file(&stale)
+                .with_context(|| format!("Failed to remove cache file: {}", stale.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the discovery progress of an unfinished sync to the checkpoint file, leaving the
+/// cache file and the epoch files as they are
+pub fn write_cache_checkpoint(pool_cache: &PoolCache, chain: Chain, cache_dir: &Path) -> Result<()> {
+    write_json(
+        &checkpoint_file_path(cache_dir, &pool_cache.pool_type, chain),
+        &CacheCheckpoint {
+            next_creation_index: pool_cache.next_creation_index,
+            pending: pool_cache.pending.clone(),
+            discovered_block: pool_cache.discovered_block,
+        },
+    )
 }
 
-pub fn write_cache_file(pool_cache: &PoolCache, chain: Chain) -> Result<()> {
-    let pool_cache_file = format!("cache/{}_{}_cache.json", chain, pool_cache.pool_type);
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
     let file = OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(&pool_cache_file)
-        .with_context(|| format!("Failed to create or open cache file: {}", pool_cache_file))?;
+        .open(path)
+        .with_context(|| format!("Failed to create or open cache file: {}", path.display()))?;
     let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, &pool_cache)
-        .with_context(|| format!("Failed to serialize cache to file: {}", pool_cache_file))?;
+    serde_json::to_writer(writer, value)
+        .with_context(|| format!("Failed to serialize cache to file: {}", path.display()))?;
     Ok(())
 }
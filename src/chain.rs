@@ -3,7 +3,10 @@
 //! This module defines the supported blockchain networks (Chains) and manages
 //! the mapping of supported pool types for each chain.
 
+use crate::errors::PoolSyncError;
+use crate::pools::pool_builder::PopulationStrategy;
 use crate::PoolType;
+use alloy::primitives::{address, Address};
 use once_cell::sync::Lazy;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
@@ -13,6 +16,13 @@ use std::fmt;
 pub enum Chain {
     /// Mantle chain
     Mantle,
+    /// A local node such as an Anvil or Reth fork, e.g. for integration tests. Only the
+    /// protocols listed in `factories` can be synced, from their factory on the node
+    Local {
+        chain_id: u64,
+        /// Factory address of each protocol deployed on the node
+        factories: &'static [(PoolType, Address)],
+    },
 }
 
 /// Static mapping of supported pool types for each chain
@@ -39,18 +49,89 @@ static CHAIN_POOLS: Lazy<HashMap<Chain, HashSet<PoolType>>> = Lazy::new(|| {
 });
 
 impl Chain {
+    /// Every supported chain
+    pub const ALL: [Chain; 1] = [Chain::Mantle];
+
     /// Determines if a given pool type is supported on this chain
     pub fn supported(&self, pool_type: &PoolType) -> bool {
+        if let Chain::Local { .. } = self {
+            return self.local_factory(pool_type).is_some();
+        }
         CHAIN_POOLS
             .get(self)
             .map(|pools| pools.contains(pool_type))
             .unwrap_or(false)
     }
+
+    /// All pool types supported on this chain, in a stable order
+    pub fn supported_pools(&self) -> Vec<PoolType> {
+        let mut pools: Vec<PoolType> = match self {
+            Chain::Local { factories, .. } => factories.iter().map(|(pool_type, _)| *pool_type).collect(),
+            _ => CHAIN_POOLS
+                .get(self)
+                .map(|pools| pools.iter().copied().collect())
+                .unwrap_or_default(),
+        };
+        pools.sort();
+        pools.dedup();
+        pools
+    }
+
+    /// The population strategies this chain's rpc endpoints can serve. A chain whose
+    /// nodes reject state-override deploys leaves out `PopulationStrategy::DataSync`
+    pub fn supported_strategies(&self) -> &'static [PopulationStrategy] {
+        match self {
+            Chain::Mantle | Chain::Local { .. } => {
+                &[PopulationStrategy::DataSync, PopulationStrategy::RpcBatch]
+            }
+        }
+    }
+
+    /// The factory of the protocol on a local chain, `None` on public chains where the
+    /// fetchers know their factories
+    pub fn local_factory(&self, pool_type: &PoolType) -> Option<Address> {
+        match self {
+            Chain::Local { factories, .. } => factories
+                .iter()
+                .find(|(local_type, _)| local_type == pool_type)
+                .map(|(_, factory)| *factory),
+            _ => None,
+        }
+    }
+
+    /// The canonical quote tokens of this chain, used to normalize price reporting
+    pub fn default_base_tokens(&self) -> HashSet<Address> {
+        match self {
+            Chain::Mantle => [
+                address!("78c1b0C915c4FAA5FffA6CAbf0219DA63d7f4cb8"), // WMNT
+                address!("09Bc4E0D864854c6aFB6eB9A9cdF58aC190D0dF9"), // USDC
+                address!("201EBa5CC46D216Ce6DC03F6a759e8E766e956aE"), // USDT
+            ]
+            .into_iter()
+            .collect(),
+            Chain::Local { .. } => HashSet::new(),
+        }
+    }
 }
 
 // Display implementation for Chain, used for file naming and debugging purposes
 impl fmt::Display for Chain {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Chain::Local { chain_id, .. } => write!(f, "Local-{}", chain_id),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+// Parses the `Display` name of a chain, ignoring case
+impl TryFrom<&str> for Chain {
+    type Error = PoolSyncError;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        Chain::ALL
+            .into_iter()
+            .find(|chain| chain.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| PoolSyncError::UnknownChain(name.to_string()))
     }
 }
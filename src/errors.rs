@@ -4,6 +4,10 @@
 //! It leverages the `thiserror` crate for deriving the `Error` trait and providing
 //! formatted error messages.
 
+use crate::chain::Chain;
+use crate::pools::pool_builder::PopulationStrategy;
+use crate::pools::{PoolFamily, PoolType};
+use alloy::primitives::Address;
 use thiserror::Error;
 
 /// Enumerates the various error types that can occur during PoolSync operations
@@ -21,6 +25,10 @@ pub enum PoolSyncError {
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    /// Represents errors that occur while reading or writing a pool cache
+    #[error("Cache error: {0}")]
+    CacheError(String),
+
     /// Indicates that an unsupported pool type was encountered
     #[error("Pool not supported")]
     UnsupportedPoolType,
@@ -28,4 +36,65 @@ pub enum PoolSyncError {
     /// Indicates that the chain was not set when it was required
     #[error("Chain not set")]
     ChainNotSet,
+
+    /// Indicates that the builder was consumed without any pool types to sync
+    #[error("No pool types added, call add_pool before build")]
+    NoPoolTypesAdded,
+
+    /// Indicates that the population strategy cannot be used on the chain
+    #[error("Population strategy {strategy:?} is not supported on {chain}")]
+    UnsupportedStrategy {
+        chain: Chain,
+        strategy: PopulationStrategy,
+    },
+
+    /// Indicates that a fetcher's creation event signature is not a canonical event signature
+    #[error("Invalid event signature for {0}: {1}")]
+    InvalidEventSignature(PoolType, String),
+
+    /// Indicates that a fetcher has no factory address on the chain, it returned the zero
+    /// address
+    #[error("No {0} factory address on {1}")]
+    MissingFactoryAddress(PoolType, Chain),
+
+    /// Indicates that a fetcher declares a pool family its pool type cannot be built with
+    #[error("{0} pools cannot be built as {1:?} pools")]
+    UnsupportedPoolFamily(PoolType, PoolFamily),
+
+    /// Indicates that a swap could not be simulated against the local pool state
+    #[error("Swap simulation error: {0}")]
+    SwapSimulationError(String),
+
+    /// Indicates that the token symbol pattern is not a valid regex
+    #[error("Invalid symbol regex: {0}")]
+    InvalidSymbolRegex(String),
+
+    /// Indicates that a chain name does not match any supported chain
+    #[error("Unknown chain {0:?}, expected one of: {}", Chain::ALL.map(|chain| chain.to_string()).join(", "))]
+    UnknownChain(String),
+
+    /// Indicates that a `POOLSYNC_*` environment variable is missing or malformed
+    #[error("Invalid environment config: {0}")]
+    EnvConfigError(String),
+
+    /// Indicates that the data-sync contract deploy reverted, e.g. on a provider that caps
+    /// the gas of `eth_call` or rejects state-override deploys
+    #[error("Data-sync deploy for {0} reverted, use PopulationStrategy::RpcBatch instead: {1}")]
+    DataSyncUnsupported(PoolType, String),
+
+    /// Indicates that the retries shared across a sync ran out before it completed
+    #[error("Retry budget exceeded")]
+    RetryBudgetExceeded,
+
+    /// Indicates that a fetcher's factory address has no contract deployed on the chain,
+    /// e.g. because the address is mistyped
+    #[error("No contract code at the {pool_type} factory {address}")]
+    NoCodeAtFactory {
+        pool_type: PoolType,
+        address: Address,
+    },
+
+    /// Indicates that an RPC call still failed after all of its retries
+    #[error("RPC error: {0}")]
+    RpcError(String),
 }
@@ -0,0 +1,79 @@
+//! JSON Export of Synced Pools
+//!
+//! This module serializes pools for consumers outside of this crate. The cache keeps the
+//! struct's own snake_case layout, while exports can be rewritten to other key conventions.
+
+use serde_json::{Map, Value};
+
+use crate::pools::Pool;
+
+/// Key convention of exported pool JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyCase {
+    /// Field names as they appear in the cache, e.g. `sqrt_price`
+    #[default]
+    Snake,
+    /// JavaScript style names, e.g. `sqrtPrice`, with `token0`/`token1` exported as
+    /// `token0Address`/`token1Address`
+    Camel,
+}
+
+/// Serializes the pools to a JSON array using the requested key convention
+pub fn export_pools_json(pools: &[Pool], case: KeyCase) -> serde_json::Result<String> {
+    let value = serde_json::to_value(pools)?;
+    let value = match case {
+        KeyCase::Snake => value,
+        KeyCase::Camel => match value {
+            Value::Array(pools) => Value::Array(pools.into_iter().map(camel_case_pool).collect()),
+            value => value,
+        },
+    };
+    serde_json::to_string(&value)
+}
+
+// Rewrite the field names of a pool, serialized as `{ "<PoolType>": { <fields> } }`, to
+// camelCase. The keys of the maps it holds, e.g. `extra`, `ticks` or `tick_bitmap`, and of
+// the values within them are left as they are
+fn camel_case_pool(pool: Value) -> Value {
+    let Value::Object(variant) = pool else {
+        return pool;
+    };
+    variant
+        .into_iter()
+        .map(|(pool_type, fields)| match fields {
+            Value::Object(fields) => (
+                pool_type,
+                Value::Object(
+                    fields
+                        .into_iter()
+                        .map(|(key, value)| (camel_case_key(&key), value))
+                        .collect::<Map<_, _>>(),
+                ),
+            ),
+            fields => (pool_type, fields),
+        })
+        .collect::<Map<_, _>>()
+        .into()
+}
+
+fn camel_case_key(key: &str) -> String {
+    match key {
+        "token0" => return "token0Address".to_string(),
+        "token1" => return "token1Address".to_string(),
+        _ => {}
+    }
+
+    let mut camel = String::with_capacity(key.len());
+    let mut upper_next = false;
+    for c in key.chars() {
+        if c == '_' {
+            upper_next = !camel.is_empty();
+        } else if upper_next {
+            camel.extend(c.to_uppercase());
+            upper_next = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
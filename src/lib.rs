@@ -4,11 +4,28 @@
 //! multiple DeFi protocols (UniswapV3, Agni, MerchantMoe) on the Mantle blockchain network.
 
 // Public re-exports
+pub use cache::{
+    CacheFileInfo, CacheStore, FileCacheStore, PendingPool, PoolCache, PopulationState, TokenCache,
+    TokenMetadata,
+};
 pub use chain::Chain;
-pub use pool_sync::PoolSync;
-pub use pools::pool_structures::v3_structure::UniswapV3Pool;
-pub use pools::{Pool, PoolInfo, PoolType};
-pub use rpc::Rpc;
+pub use errors::PoolSyncError;
+pub use export::{export_pools_json, KeyCase};
+pub use pool_sync::{
+    AddressCollision, CollisionPolicy, PoolFilter, PoolSync, ProgressCallback, SampleReport,
+    SampledPool, SyncFailure, SyncProgress, SyncStats,
+};
+pub use pools::pool_builder::{DataSyncRevertPolicy, PopulationStrategy};
+pub use pools::pool_structures::v3_structure::{FieldCheck, UniswapV3Pool, VerifyReport};
+pub use pools::{
+    consolidated_price, group_by_pair, pools_by_family, pools_changed_since, pools_with_fee,
+    quote_route, Pool, PoolCreation, PoolFamily, PoolInfo, PoolType, MANTLE_BLOCK_TIME,
+};
+pub use rpc::{AdaptiveRate, CircuitBreaker, CircuitBreakerConfig, Rpc, RpcConfig};
+pub use sync_metrics::{
+    METRIC_CHUNK_DURATION, METRIC_POOLS_DISCOVERED, METRIC_POOLS_POPULATED, METRIC_RPC_CALLS,
+    METRIC_RPC_ERRORS,
+};
 
 // Internal modules
 mod builder;
@@ -16,8 +33,10 @@ mod cache;
 mod chain;
 mod errors;
 mod events;
+mod export;
 mod pool_sync;
 mod pools;
 mod rpc;
+mod sync_metrics;
 mod util;
 mod tests;
@@ -4,17 +4,261 @@
 //! blockchain networks and protocols. It includes the main `PoolSync` struct and its
 //! associated methods for configuring and executing the synchronization process.
 //!
+use alloy::primitives::Address;
 use alloy::providers::Provider;
 use alloy::providers::ProviderBuilder;
-use std::collections::HashMap;
-use std::sync::Arc;
+use alloy::providers::RootProvider;
+use alloy::rpc::client::RpcClient;
+use alloy::transports::http::{Client, Http};
+use comfy_table::Table;
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use log::{debug, info, warn};
+use rand::seq::SliceRandom;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::builder::PoolSyncBuilder;
-use crate::cache::{read_cache_file, write_cache_file, PoolCache};
+use crate::cache::{
+    CacheFileInfo, CacheStore, PendingPool, PoolCache, PopulationState, TokenCache, TokenMetadata,
+};
 use crate::chain::Chain;
 use crate::errors::*;
+use crate::pools::pool_builder::{
+    is_data_sync_unsupported, non_erc20_tokens, refresh_pool_prices, resolve_token_decimals,
+    resolve_token_name, PopulationConfig, RetryBudget, UNKNOWN_TOKEN_NAME,
+};
 use crate::pools::*;
-use crate::rpc::Rpc;
+use crate::pools::pool_structures::v3_structure::VerifyReport;
+use crate::rpc::{
+    is_retry_budget_exceeded, AdaptiveRate, CircuitBreaker, CircuitBreakerConfig, Rpc, RpcConfig,
+};
+use crate::sync_metrics;
+
+/// Number of blocks the creation event probe inspects at the start of an initial sync
+const SIGNATURE_PROBE_RANGE: u64 = 500;
+
+/// A batch of pools that could not be populated after all retries
+#[derive(Debug, Clone)]
+pub struct SyncFailure {
+    /// Protocol of the pools
+    pub pool_type: PoolType,
+    /// Discovery range the pools were found in
+    pub block_range: (u64, u64),
+    /// Addresses of the pools missing from the result
+    pub addresses: Vec<Address>,
+    /// The last error returned for the batch
+    pub error: String,
+}
+
+/// An address that more than one protocol reported a pool at
+#[derive(Debug, Clone)]
+pub struct AddressCollision {
+    /// The shared pool address
+    pub address: Address,
+    /// Every protocol with a pool at the address, in `PoolType` order
+    pub pool_types: Vec<PoolType>,
+}
+
+/// How pools found at the same address under more than one protocol are merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// Keep every pool, leaving the duplicates to the caller
+    #[default]
+    KeepAll,
+    /// Keep the pool of the protocol that comes first in `PoolType` order
+    KeepFirst,
+    /// Keep the pool of the given protocol, or the first in `PoolType` order when it is not
+    /// one of the colliding protocols
+    Prefer(PoolType),
+    /// Drop every pool at the address
+    DropAll,
+}
+
+/// Statistics gathered over a sync
+#[derive(Debug, Clone, Default)]
+pub struct SyncStats {
+    /// Every batch that failed after all retries, in the order they were encountered
+    pub failures: Vec<SyncFailure>,
+    /// Every address reported by more than one protocol, ordered by address
+    pub collisions: Vec<AddressCollision>,
+    /// Tokens of the newly synced pools whose symbol or decimals could not be resolved,
+    /// left with an empty or `UNKNOWN` name or unknown decimals
+    pub failed_token_metadata: HashSet<Address>,
+    /// Pools created in the blocks of this sync, as opposed to the ones already cached,
+    /// in the order they were added to the caches
+    pub new_pool_addresses: Vec<Address>,
+    /// Tokens of the newly synced pools that did not answer `decimals()`, `symbol()` and
+    /// `totalSupply()`, empty unless `check_erc20_compliance` was set
+    pub non_erc20_tokens: HashSet<Address>,
+}
+
+/// How far a sync is across all of its protocols, e.g. for a global ETA
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncProgress {
+    /// Blocks synced so far, summed over the protocols
+    pub blocks_done: u64,
+    /// Blocks to sync to reach the target block, summed over the protocols
+    pub blocks_total: u64,
+    /// `blocks_done` as a percentage of `blocks_total`, 100 when there is nothing to sync
+    pub percent: f64,
+}
+
+impl SyncProgress {
+    /// Progress of syncing `caches` up to `target_block`, where `started` holds the same
+    /// caches as they were when the sync began. Each protocol counts the blocks synced after
+    /// the block before the first one it syncs, given the sync's `start_block` and
+    /// `force_start_block`, the same figures `on_progress` is told. Blocks past the target
+    /// are not counted
+    pub fn from_caches(
+        started: &[PoolCache],
+        caches: &[PoolCache],
+        start_block: Option<u64>,
+        force_start_block: Option<u64>,
+        target_block: u64,
+    ) -> Self {
+        Self::from_blocks(
+            started
+                .iter()
+                .zip(caches)
+                .map(|(started, cache)| {
+                    (
+                        progress_origin(started, start_block, force_start_block),
+                        cache.last_synced_block,
+                    )
+                }),
+            target_block,
+        )
+    }
+
+    // Progress of protocols synced from each origin block up to its last synced block
+    fn from_blocks(blocks: impl IntoIterator<Item = (u64, u64)>, target_block: u64) -> Self {
+        let (mut blocks_done, mut blocks_total) = (0, 0);
+        for (origin, last_synced_block) in blocks {
+            blocks_done += last_synced_block.min(target_block).saturating_sub(origin);
+            blocks_total += target_block.saturating_sub(origin);
+        }
+        let percent = match blocks_total {
+            0 => 100.0,
+            total => blocks_done as f64 / total as f64 * 100.0,
+        };
+        Self {
+            blocks_done,
+            blocks_total,
+            percent,
+        }
+    }
+}
+
+/// A pool checked against its contract by `PoolSync::verify_sample`
+#[derive(Debug, Clone)]
+pub struct SampledPool {
+    pub address: Address,
+    pub pool_type: PoolType,
+    /// Block the pool was compared at, the one its protocol's cache was last synced to
+    pub block: u64,
+    /// The comparison with the contract, or why the contract could not be read
+    pub report: Result<VerifyReport, String>,
+}
+
+impl SampledPool {
+    /// Whether the contract was read and every field agrees with it
+    pub fn passed(&self) -> bool {
+        matches!(&self.report, Ok(report) if report.is_consistent())
+    }
+}
+
+/// Outcome of `PoolSync::verify_sample`
+#[derive(Debug, Clone)]
+pub struct SampleReport {
+    /// Every sampled pool, in the order they were drawn
+    pub pools: Vec<SampledPool>,
+    /// Protocols of the given pools without a synced cache, whose pools were not sampled
+    pub unsynced: Vec<PoolType>,
+}
+
+impl SampleReport {
+    /// The sampled pools that failed the check
+    pub fn failed(&self) -> Vec<&SampledPool> {
+        self.pools.iter().filter(|pool| !pool.passed()).collect()
+    }
+
+    /// Fraction of the sampled pools that passed, `None` for an empty sample
+    pub fn pass_rate(&self) -> Option<f64> {
+        if self.pools.is_empty() {
+            return None;
+        }
+        let passed = self.pools.iter().filter(|pool| pool.passed()).count();
+        Some(passed as f64 / self.pools.len() as f64)
+    }
+
+    /// Fraction of all pools that is in sync with 95% confidence, the lower bound of the
+    /// Wilson score interval around the pass rate. It approaches the pass rate as the sample
+    /// grows and is 0 for an empty sample
+    pub fn confidence(&self) -> f64 {
+        let Some(rate) = self.pass_rate() else {
+            return 0.0;
+        };
+        let (n, z) = (self.pools.len() as f64, 1.96f64);
+        let center = rate + z * z / (2.0 * n);
+        let margin = z * (rate * (1.0 - rate) / n + z * z / (4.0 * n * n)).sqrt();
+        (center - margin) / (1.0 + z * z / n)
+    }
+}
+
+/// The channel `sync_pools_batched` hands the pools out through as they are synced
+#[derive(Clone)]
+struct BatchSink {
+    sender: Arc<futures::lock::Mutex<mpsc::Sender<Vec<Pool>>>>,
+    batch_size: usize,
+}
+
+impl BatchSink {
+    // Send the pools in batches of at most `batch_size`, waiting while the consumer is
+    // behind. A consumer that dropped the stream no longer receives them
+    async fn send(&self, pools: &[Pool]) {
+        let mut sender = self.sender.lock().await;
+        for batch in pools.chunks(self.batch_size) {
+            if sender.send(batch.to_vec()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Provider the sync reads the chain through
+type SyncProvider = RootProvider<Http<Client>, alloy::network::AnyNetwork>;
+
+/// State shared by the protocols synced in one round
+struct SyncRound {
+    archive: Arc<SyncProvider>,
+    full: Arc<SyncProvider>,
+    liquidity: Arc<SyncProvider>,
+    end_block: u64,
+    retry_budget: RetryBudget,
+    circuit_breaker: CircuitBreaker,
+    rate: AdaptiveRate,
+    ndjson_sink: Option<Arc<Mutex<BufWriter<File>>>>,
+    batch_sink: Option<BatchSink>,
+    failed_token_metadata: Mutex<HashSet<Address>>,
+    new_pool_addresses: Mutex<Vec<Address>>,
+    non_erc20_tokens: Mutex<HashSet<Address>>,
+    known_tokens: Arc<TokenCache>,
+    // last synced block of each protocol, in the order of the caches
+    synced_blocks: Mutex<Vec<u64>>,
+    // block each protocol's progress counts from, captured when the sync started
+    progress_origins: Arc<Vec<u64>>,
+}
+
+/// Predicate deciding whether a populated pool is kept
+pub type PoolFilter = Box<dyn Fn(&Pool) -> bool + Send + Sync>;
+
+/// Called with the progress of the whole sync as each protocol finishes a round
+pub type ProgressCallback = Box<dyn Fn(&SyncProgress) + Send + Sync>;
 
 /// The main struct for pool synchronization
 pub struct PoolSync {
@@ -24,10 +268,56 @@ pub struct PoolSync {
     pub chain: Chain,
     /// The rate limit of the rpc
     pub rate_limit: u64,
-    /// Optional starting block for synchronization (overrides cache)
+    /// Optional minimum starting block for synchronization, ignored once the cache is past it
     pub start_block: Option<u64>,
+    /// Optional starting block that discards the cache and takes precedence over `start_block`
+    pub force_start_block: Option<u64>,
+    /// Optional block window for the first liquidity replay of new pools, clamped to the
+    /// discovery range it defaults to
+    pub liquidity_range: Option<(u64, u64)>,
     /// Optional ending block for synchronization (overrides latest block)
     pub end_block: Option<u64>,
+    /// Blocks a sync to the latest block stops short of the tip, leaving pools created in
+    /// them uncached until they are confirmed
+    pub confirmations: u64,
+    /// Probe the factories for their creation event before the initial sync
+    pub probe_signatures: bool,
+    /// Check that every factory has contract code before syncing
+    pub validate_factories: bool,
+    /// Options controlling how discovered pools are populated
+    pub population: PopulationConfig,
+    /// Optional cap on the population retries of a whole sync, unlimited when `None`
+    pub retry_budget: Option<u32>,
+    /// Optional thresholds pausing the whole sync while the rpc fails most requests
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Optional cap on the number of ticks kept per V3 pool
+    pub max_ticks_per_pool: Option<usize>,
+    /// Rpc endpoints, falling back to the environment when unset
+    pub rpc_config: RpcConfig,
+    /// Optional endpoint the liquidity events are read from instead of the archive node
+    pub liquidity_url: Option<String>,
+    /// Optional http client every provider is built on, e.g. one sending api key headers
+    pub http_client: Option<Client>,
+    /// Whether the sender of each new pool's creation transaction is fetched
+    pub capture_creators: bool,
+    /// Whether the tokens of new pools are probed for ERC20 compliance
+    pub check_erc20: bool,
+    /// Predicates every newly populated pool must pass to be kept
+    pub filters: Vec<PoolFilter>,
+    /// Optional callback told the progress of the whole sync
+    pub on_progress: Option<ProgressCallback>,
+    /// Pool addresses dropped right after discovery, never populated or cached
+    pub excluded_addresses: HashSet<Address>,
+    /// How pools sharing an address across protocols are merged into the result
+    pub collision_policy: CollisionPolicy,
+    /// Number of protocols synced at the same time, 1 syncs them one after another
+    pub max_concurrent_protocols: usize,
+    /// Optional file every newly synced pool is appended to as a line of JSON
+    pub ndjson_sink: Option<PathBuf>,
+    /// Whether a table summarizing each protocol is logged at `info` when a sync ends
+    pub log_summary: bool,
+    /// Where the per protocol caches are read from and written to
+    pub cache_store: Arc<dyn CacheStore>,
 }
 
 impl PoolSync {
@@ -36,146 +326,407 @@ impl PoolSync {
         PoolSyncBuilder::default()
     }
 
+    /// Construct a sync from the environment (and a `.env` file), for scripts and
+    /// deployments configured without code:
+    /// - `POOLSYNC_CHAIN` - chain name, e.g. `mantle`
+    /// - `POOLSYNC_POOLS` - comma separated pool types, e.g. `UniswapV3,Agni`
+    /// - `POOLSYNC_RATE_LIMIT` - optional requests per second, also read by every `build`
+    /// - `POOLSYNC_START_BLOCK` / `POOLSYNC_END_BLOCK` - optional block range
+    ///
+    /// The rpc urls are read from `ARCHIVE` and `FULL` as usual
+    pub fn from_env() -> Result<PoolSync, PoolSyncError> {
+        dotenv::dotenv().ok();
+        Self::from_vars(&env_var)
+    }
+
+    // `from_env` reading the variables through `vars` instead of the process environment
+    pub(crate) fn from_vars(vars: VarLookup) -> Result<PoolSync, PoolSyncError> {
+        let chain = match vars("POOLSYNC_CHAIN")? {
+            Some(name) => Chain::try_from(name.as_str())
+                .map_err(|e| PoolSyncError::EnvConfigError(format!("POOLSYNC_CHAIN: {}", e)))?,
+            None => return Err(PoolSyncError::EnvConfigError("POOLSYNC_CHAIN is not set".into())),
+        };
+
+        let pools = vars("POOLSYNC_POOLS")?
+            .ok_or_else(|| PoolSyncError::EnvConfigError("POOLSYNC_POOLS is not set".into()))?;
+        let pool_types = pools
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                chain
+                    .supported_pools()
+                    .into_iter()
+                    .find(|pool_type| pool_type.to_string().eq_ignore_ascii_case(name))
+                    .ok_or_else(|| {
+                        PoolSyncError::EnvConfigError(format!(
+                            "POOLSYNC_POOLS: {:?} is not a pool type supported on {}",
+                            name, chain
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<PoolType>, PoolSyncError>>()?;
+
+        // `build` reads POOLSYNC_RATE_LIMIT itself
+        let mut builder = PoolSync::builder().chain(chain).add_pools(&pool_types);
+        if let Some(start_block) = parse_var(vars, "POOLSYNC_START_BLOCK")? {
+            builder = builder.start_block(start_block);
+        }
+        if let Some(end_block) = parse_var(vars, "POOLSYNC_END_BLOCK")? {
+            builder = builder.end_block(end_block);
+        }
+        builder.build_with_vars(vars)
+    }
+
+    /// Returns the pools of every configured protocol as last written to the cache,
+    /// without touching the RPC or advancing any blocks
+    pub fn load_cached_pools(&self) -> Result<Vec<Pool>, PoolSyncError> {
+        let mut pools = Vec::new();
+        for pool_type in self.fetchers.keys() {
+            let cache = self.read_cache(pool_type)?;
+            pools.extend(cache.pools);
+        }
+        Ok(pools)
+    }
+
+    /// Lists the cache files a sync would read and write for every configured protocol,
+    /// ordered by pool type, with the block each cache was synced to. The caches are only
+    /// read, helping to troubleshoot a stale or misplaced cache before a sync. Empty for
+    /// cache stores that are not backed by files
+    pub fn cache_plan(&self) -> Vec<CacheFileInfo> {
+        let mut pool_types: Vec<PoolType> = self.fetchers.keys().copied().collect();
+        pool_types.sort();
+        pool_types
+            .into_iter()
+            .flat_map(|pool_type| {
+                let last_synced_block = self
+                    .cache_store
+                    .read(&pool_type, self.chain)
+                    .ok()
+                    .flatten()
+                    .map(|cache| cache.last_synced_block);
+                self.cache_store
+                    .cache_files(&pool_type, self.chain)
+                    .into_iter()
+                    .map(move |path| CacheFileInfo {
+                        exists: path.exists(),
+                        path,
+                        pool_type,
+                        last_synced_block,
+                    })
+            })
+            .collect()
+    }
+
+    /// Returns the cached pools with their prices read at the latest block, the fastest way
+    /// to keep a known pool set fresh. Only slot0 and liquidity of V3 pools and the reserves
+    /// of V2 pools are read, in batched calls through the full node, skipping discovery and
+    /// the event replay. V3 ticks stay as cached and the cache itself is not written, as the
+    /// next `sync_pools` replays the events since the cache over the cached state
+    pub async fn refresh_prices(&self) -> Result<Vec<Pool>, PoolSyncError> {
+        dotenv::dotenv().ok();
+        let full = self.http_provider(&self.rpc_config.full_url()?)?;
+        let block = full
+            .get_block_number()
+            .await
+            .map_err(|e| PoolSyncError::ProviderError(e.to_string()))?;
+
+        let mut pools = self.load_cached_pools()?;
+        refresh_pool_prices(&full, &mut pools, block)
+            .await
+            .map_err(|e| PoolSyncError::ProviderError(format!("{:#}", e)))?;
+        Ok(pools)
+    }
+
+    /// Resolves the name and decimals of the tokens ahead of a sync and keeps them in the
+    /// token cache of the cache store, so later syncs populate pools of these tokens without
+    /// calling them again. Tokens already cached are skipped, ones that still fail after
+    /// all attempts are logged and left to the sync
+    pub async fn warmup_tokens(&self, tokens: &[Address]) -> Result<(), PoolSyncError> {
+        dotenv::dotenv().ok();
+        let full = self.http_provider(&self.rpc_config.full_url()?)?;
+        let mut cache = self.cache_store.read_tokens(self.chain)?;
+
+        let missing: HashSet<Address> = tokens
+            .iter()
+            .filter(|token| !cache.contains_key(*token))
+            .copied()
+            .collect();
+        let attempts = self.population.token_name_attempts;
+        let concurrency = self.population.token_concurrency.min(self.rate_limit as usize);
+        let rate = AdaptiveRate::new(self.rate_limit);
+        let resolved: Vec<_> = futures::stream::iter(missing.into_iter().map(|token| {
+            let (full, rate) = (full.clone(), &rate);
+            async move {
+                let name = resolve_token_name(&full, token, attempts, rate).await;
+                let decimals = resolve_token_decimals(&full, token, attempts, rate).await;
+                (token, name, decimals)
+            }
+        }))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+        for (token, name, decimals) in resolved {
+            match name {
+                Some(name) => {
+                    cache.insert(token, TokenMetadata { name, decimals });
+                }
+                None => warn!("Could not resolve the metadata of token {}", token),
+            }
+        }
+        self.cache_store.write_tokens(&cache, self.chain)
+    }
+
+    /// Compares `sample_size` randomly drawn V3 pools against their contracts, each at the
+    /// block its protocol's cache was last synced to. A cheap check of a large sync, run
+    /// right after it. Its four reads per pool are paced at the rate limit. V2 pools are not
+    /// sampled, nor are the pools of protocols without a synced cache, which are listed in
+    /// the report instead
+    pub async fn verify_sample(
+        &self,
+        pools: &[Pool],
+        sample_size: usize,
+    ) -> Result<SampleReport, PoolSyncError> {
+        dotenv::dotenv().ok();
+        let full = self.http_provider(&self.rpc_config.full_url()?)?;
+        let pool_types: BTreeSet<PoolType> = pools
+            .iter()
+            .filter(|pool| pool.is_v3())
+            .map(|pool| pool.pool_type())
+            .collect();
+        let mut blocks = HashMap::new();
+        let mut unsynced = Vec::new();
+        for pool_type in pool_types {
+            match self.cache_store.read(&pool_type, self.chain)? {
+                Some(cache) if cache.last_synced_block > 0 => {
+                    blocks.insert(pool_type, cache.last_synced_block);
+                }
+                _ => unsynced.push(pool_type),
+            }
+        }
+
+        let candidates: Vec<&Pool> = pools
+            .iter()
+            .filter(|pool| pool.is_v3() && blocks.contains_key(&pool.pool_type()))
+            .collect();
+        let sample: Vec<&Pool> = candidates
+            .choose_multiple(&mut rand::thread_rng(), sample_size)
+            .copied()
+            .collect();
+        // every read is paced at the rate limit, as in a sync
+        let rate = AdaptiveRate::new(self.rate_limit);
+        let pools = futures::stream::iter(sample.into_iter().map(|pool| {
+            let (full, rate) = (full.clone(), &rate);
+            let block = blocks[&pool.pool_type()];
+            async move {
+                let v3 = pool.get_v3().expect("only V3 pools are sampled");
+                SampledPool {
+                    address: pool.address(),
+                    pool_type: pool.pool_type(),
+                    block,
+                    report: v3
+                        .verify_against_chain_at_rate(&full, block, rate)
+                        .await
+                        .map_err(|e| e.to_string()),
+                }
+            }
+        }))
+        .buffered(self.rate_limit.max(1) as usize)
+        .collect()
+        .await;
+
+        Ok(SampleReport { pools, unsynced })
+    }
+
+    /// Synchronizes all added pools and returns them in on-chain creation order, ordered by
+    /// creation block and then by each protocol's discovery order. Pools cached before
+    /// creation blocks were tracked have none and come first, in discovery order
+    pub async fn sync_pools_chronological(&self) -> Result<(Vec<Pool>, u64), PoolSyncError> {
+        let (mut pools, last_synced_block) = self.sync_pools().await?;
+        pools.sort_by_key(|pool| (pool.created_at_block(), pool.creation_index()));
+        Ok((pools, last_synced_block))
+    }
+
+    /// Synchronizes all added pools and hands them out in batches of at most `batch_size`,
+    /// so a consumer can bulk insert each batch and drop it before taking the next. The sync
+    /// runs as the stream is polled: the new pools of each protocol are yielded as soon as
+    /// they are populated, the pools caught up from the caches once the sync completes. The
+    /// sync waits while a batch is not taken, and an error ends the stream. Pools are handed
+    /// out before address collisions are resolved, so `collision_policy` does not apply
+    pub fn sync_pools_batched(
+        &self,
+        batch_size: usize,
+    ) -> impl Stream<Item = Result<Vec<Pool>, PoolSyncError>> + '_ {
+        let (sender, receiver) = mpsc::channel(0);
+        let batch_sink = BatchSink {
+            sender: Arc::new(futures::lock::Mutex::new(sender)),
+            batch_size: batch_size.max(1),
+        };
+        // the batches end when the sync drops the sender
+        let sync = futures::stream::once(async move {
+            self.sync_with_batches(Some(batch_sink)).await.err()
+        })
+        .filter_map(|error| futures::future::ready(error.map(Err)));
+        futures::stream::select(receiver.map(Ok), sync)
+    }
+
     /// Synchronizes all added pools for the specified chain
     pub async fn sync_pools(&self) -> Result<(Vec<Pool>, u64), PoolSyncError> {
+        let (pools, last_synced_block, _) = self.sync_pools_with_stats().await?;
+        Ok((pools, last_synced_block))
+    }
+
+    /// Synchronizes all added pools like `sync_pools`, also returning the statistics of the
+    /// sync including every batch of pools that could not be populated
+    pub async fn sync_pools_with_stats(&self) -> Result<(Vec<Pool>, u64, SyncStats), PoolSyncError> {
+        self.sync_with_batches(None).await
+    }
+
+    // Sync all added pools, handing them out through the batch sink if there is one
+    async fn sync_with_batches(
+        &self,
+        batch_sink: Option<BatchSink>,
+    ) -> Result<(Vec<Pool>, u64, SyncStats), PoolSyncError> {
+        let mut stats = SyncStats::default();
+        let retry_budget = self.retry_budget.map(RetryBudget::new).unwrap_or_default();
+        let circuit_breaker = self.circuit_breaker.map(CircuitBreaker::new).unwrap_or_default();
+        let rate = AdaptiveRate::new(self.rate_limit);
+
         // load in the dotenv
         dotenv::dotenv().ok();
 
         // setup arvhice node provider
-        let archive = Arc::new(
-            ProviderBuilder::new()
-                .network::<alloy::network::AnyNetwork>()
-                .on_http(std::env::var("ARCHIVE").unwrap().parse().unwrap()),
-        );
+        let archive = self.http_provider(&self.rpc_config.archive_url()?)?;
 
         // setup full node provider
-        let full = Arc::new(
-            ProviderBuilder::new()
-                .network::<alloy::network::AnyNetwork>()
-                .on_http(std::env::var("FULL").unwrap().parse().unwrap()),
-        );
+        let full = self.http_provider(&self.rpc_config.full_url()?)?;
+
+        // discovery reads logs from the archive node and population reads historical state
+        // from the full node. The liquidity events are logs too, read from the archive node
+        // unless a dedicated endpoint is configured
+        let liquidity = match &self.liquidity_url {
+            Some(url) => self.http_provider(url)?,
+            None => archive.clone(),
+        };
+
+        if self.validate_factories {
+            self.check_factory_code(&full).await?;
+        }
 
-        // create the cache files
-        std::fs::create_dir_all("cache").unwrap();
+        // open the ndjson sink, keeping the lines of earlier syncs
+        let ndjson_sink = match &self.ndjson_sink {
+            Some(path) => Some(Arc::new(Mutex::new(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )))),
+            None => None,
+        };
+
+        // tokens resolved by `warmup_tokens` are not called again
+        let known_tokens = Arc::new(self.cache_store.read_tokens(self.chain)?);
 
         // create all of the caches
-        let mut pool_caches: Vec<PoolCache> = self
-            .fetchers
-            .keys()
-            .map(|pool_type| read_cache_file(pool_type, self.chain).unwrap())
-            .collect();
+        let pool_types: Vec<PoolType> = self.fetchers.keys().copied().collect();
+        let mut pool_caches: Vec<PoolCache> = match self.force_start_block {
+            Some(_) => pool_types
+                .iter()
+                .map(|pool_type| PoolCache::new(*pool_type, self.chain))
+                .collect(),
+            None => self.read_caches(&pool_types).await?,
+        };
+
+        let progress_origins: Arc<Vec<u64>> = Arc::new(
+            pool_caches
+                .iter()
+                .map(|cache| progress_origin(cache, self.start_block, self.force_start_block))
+                .collect(),
+        );
 
         let mut fully_synced = false;
-        let mut last_synced_block = 0;
+        let mut last_synced_block = None;
+        let mut summaries: Vec<ProtocolSummary> = pool_caches
+            .iter()
+            .map(|cache| ProtocolSummary {
+                pool_type: cache.pool_type,
+                cached_pools: cache.pools.len(),
+                first_block: None,
+                duration: Duration::ZERO,
+            })
+            .collect();
 
         while !fully_synced {
             fully_synced = true;
             
-            // Use custom end_block if specified, otherwise get latest block
+            // Use custom end_block if specified, otherwise the latest confirmed block
             let end_block = match self.end_block {
                 Some(end_block) => end_block,
-                None => full.get_block_number().await.unwrap(),
+                None => Rpc::get_block_number_with_retry(&full, &circuit_breaker)
+                    .await?
+                    .saturating_sub(self.confirmations),
             };
 
-            println!("\n🔄 开始同步轮次 - 目标区块: {}, 上次同步: {}", end_block, last_synced_block);
+            println!("\n🔄 开始同步轮次 - 目标区块: {}, 上次同步: {}", end_block, last_synced_block.unwrap_or(0));
             println!("📊 协议状态:");
             for cache in &pool_caches {
                 println!("  {} - 缓存池数: {}, 上次同步区块: {}", 
                     cache.pool_type, cache.pools.len(), cache.last_synced_block);
             }
-            println!("");
-
-            for cache in &mut pool_caches {
-                // Use custom start_block if specified, otherwise use cache
-                let start_block = match self.start_block {
-                    Some(start_block) => {
-                        // 如果指定了自定义起始区块，只有在缓存还没达到这个区块时才使用
-                        if cache.last_synced_block < start_block {
-                            start_block
-                        } else {
-                            cache.last_synced_block + 1
-                        }
-                    },
-                    None => cache.last_synced_block + 1,
-                };
-                
-                if start_block <= end_block {
-                    fully_synced = false;
-                    
-                    println!("🔗 正在同步 {} 协议 (区块 {} → {})", cache.pool_type, start_block, end_block);
-
-                    let fetcher = self.fetchers[&cache.pool_type].clone();
-
-                    // fetch all of the pool addresses
-                    let pool_addrs = Rpc::fetch_pool_addrs(
-                        start_block,
-                        end_block,
-                        archive.clone(),
-                        fetcher.clone(),
-                        self.chain,
-                        self.rate_limit,
-                    )
-                    .await
-                    .expect(
-                        "Failed to fetch pool addresses. Exiting due to having inconclusive state",
-                    );
-
-                    // populate all of the pool data
-                    let mut new_pools = Rpc::populate_pools(
-                        pool_addrs,
-                        full.clone(),
-                        cache.pool_type,
-                        fetcher.clone(),
-                        self.rate_limit,
-                        self.chain,
-                    )
-                    .await
-                    .expect("Failed to sync pool data, Exiting due to haveing inconclusive state");
-
-
-                    // catch up all the old pools
-                    Rpc::populate_liquidity(
-                        start_block,
-                        end_block,
-                        &mut cache.pools,
-                        archive.clone(),
-                        cache.pool_type,
-                        self.rate_limit,
-                        cache.is_initial_sync,
-                    )
-                    .await
-                    .expect("Failed to populate liquidity information, Exiting due to having inconclusive state");
-
-                    // update the new pools
-                    if !new_pools.is_empty() {
-                        Rpc::populate_liquidity(
-                            start_block,
-                            end_block,
-                            &mut new_pools,
-                            archive.clone(),
-                            cache.pool_type,
-                            self.rate_limit,
-                            true,
-                        )
-                        .await
-                        .expect("Failed to populate liquidity information, Exiting due to having inconclusive state");
-                    }
+            println!();
 
-
-                    // merge old and new
-                    let new_pools_count = new_pools.len();
-                    cache.pools.extend(new_pools);
-
-
-                    // update info for cache
-                    cache.last_synced_block = end_block;
-                    last_synced_block = end_block;
-                    cache.is_initial_sync = false;
-                    
-                    println!("✅ {} 协议同步完成 - 总池数: {}, 新增池: {}, 同步至区块: {}", 
-                        cache.pool_type, cache.pools.len(), new_pools_count, end_block);
-                } else {
-                    println!("⏭️  {} 协议已为最新状态 (区块 {})", cache.pool_type, cache.last_synced_block);
+            // sync the protocols, up to `max_concurrent_protocols` at a time
+            let round = SyncRound {
+                archive: archive.clone(),
+                full: full.clone(),
+                liquidity: liquidity.clone(),
+                end_block,
+                retry_budget: retry_budget.clone(),
+                circuit_breaker: circuit_breaker.clone(),
+                rate: rate.clone(),
+                ndjson_sink: ndjson_sink.clone(),
+                batch_sink: batch_sink.clone(),
+                failed_token_metadata: Mutex::default(),
+                new_pool_addresses: Mutex::default(),
+                non_erc20_tokens: Mutex::default(),
+                known_tokens: known_tokens.clone(),
+                synced_blocks: Mutex::new(
+                    pool_caches.iter().map(|cache| cache.last_synced_block).collect(),
+                ),
+                progress_origins: progress_origins.clone(),
+            };
+            self.report_progress(&round);
+            let results: Vec<_> = futures::stream::iter(pool_caches.iter_mut().enumerate().map(
+                |(i, cache)| {
+                    let round = &round;
+                    async move {
+                        let start_block = self.first_block_to_sync(cache);
+                        let timer = Instant::now();
+                        let result = self.sync_protocol(cache, round).await;
+                        round.synced_blocks.lock().unwrap()[i] = cache.last_synced_block;
+                        self.report_progress(round);
+                        (i, start_block, timer.elapsed(), result)
+                    }
+                },
+            ))
+            .buffer_unordered(self.max_concurrent_protocols.max(1))
+            .collect()
+            .await;
+            stats
+                .failed_token_metadata
+                .extend(round.failed_token_metadata.lock().unwrap().drain());
+            stats
+                .new_pool_addresses
+                .append(&mut round.new_pool_addresses.lock().unwrap());
+            stats
+                .non_erc20_tokens
+                .extend(round.non_erc20_tokens.lock().unwrap().drain());
+            for (i, start_block, elapsed, result) in results {
+                summaries[i].duration += elapsed;
+                if let Some(failures) = result? {
+                    fully_synced = false;
+                    last_synced_block = Some(end_block);
+                    stats.failures.extend(failures);
+                    let first_block = summaries[i].first_block.get_or_insert(start_block);
+                    *first_block = (*first_block).min(start_block);
                 }
             }
             
@@ -189,26 +740,614 @@ impl PoolSync {
             }
         }
 
-        println!("\n🎉 所有协议同步完成! 最终状态:");
-        for cache in &pool_caches {
-            println!("  {} - 总池数: {}, 最新区块: {}", 
-                cache.pool_type, cache.pools.len(), cache.last_synced_block);
+        if self.log_summary {
+            info!("Sync summary\n{}", summary_table(&summaries, &pool_caches));
         }
-        println!("💾 正在保存缓存文件...\n");
 
         // write all of the cache files
-        pool_caches
-            .iter()
-            .for_each(|cache| write_cache_file(cache, self.chain).unwrap());
+        for cache in &pool_caches {
+            self.cache_store.write(cache, self.chain)?;
+        }
 
-        // return all the pools
-        Ok((
+        // the new pools were handed out as they were populated, the cached ones come last
+        if let Some(batch_sink) = batch_sink {
+            for (cache, summary) in pool_caches.iter().zip(&summaries) {
+                batch_sink.send(&cache.pools[..summary.cached_pools]).await;
+            }
+        }
+
+        // with nothing left to sync the pools are as of the oldest cache
+        let last_synced_block = last_synced_block.unwrap_or_else(|| {
             pool_caches
-                .into_iter()
-                .flat_map(|cache| cache.pools)
-                .collect(),
-            last_synced_block,
-        ))
+                .iter()
+                .map(|cache| cache.last_synced_block)
+                .min()
+                .unwrap_or(0)
+        });
+
+        for failure in &stats.failures {
+            warn!(
+                "Missing {} {} pools discovered in blocks {}-{}: {}",
+                failure.addresses.len(),
+                failure.pool_type,
+                failure.block_range.0,
+                failure.block_range.1,
+                failure.error
+            );
+        }
+
+        // merge the protocols, the caches keep every pool whatever the policy
+        let pools: Vec<Pool> = pool_caches
+            .into_iter()
+            .flat_map(|cache| cache.pools)
+            .collect();
+        let (pools, collisions) = resolve_address_collisions(pools, self.collision_policy);
+        for collision in &collisions {
+            warn!(
+                "Pool {} was found under multiple protocols {:?}, resolving with {:?}",
+                collision.address, collision.pool_types, self.collision_policy
+            );
+        }
+        stats.collisions = collisions;
+
+        // return all the pools
+        Ok((pools, last_synced_block, stats))
+    }
+
+    // Fail on the first enabled fetcher whose factory address has no contract code
+    async fn check_factory_code(&self, provider: &SyncProvider) -> Result<(), PoolSyncError> {
+        for (pool_type, fetcher) in &self.fetchers {
+            let address = fetcher.factory_address(self.chain);
+            let code = provider
+                .get_code_at(address)
+                .await
+                .map_err(|e| PoolSyncError::ProviderError(e.to_string()))?;
+            if code.is_empty() {
+                return Err(PoolSyncError::NoCodeAtFactory {
+                    pool_type: *pool_type,
+                    address,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // The first block a protocol syncs, see `first_block_to_sync`
+    fn first_block_to_sync(&self, cache: &PoolCache) -> u64 {
+        first_block_to_sync(cache, self.start_block, self.force_start_block)
+    }
+
+    // Tell the progress callback how far the round's protocols are
+    fn report_progress(&self, round: &SyncRound) {
+        if let Some(on_progress) = &self.on_progress {
+            let synced_blocks = round.synced_blocks.lock().unwrap().clone();
+            on_progress(&SyncProgress::from_blocks(
+                round.progress_origins.iter().copied().zip(synced_blocks),
+                round.end_block,
+            ));
+        }
+    }
+
+    // The stored cache of a pool type, or an empty one before its first sync
+    pub(crate) fn read_cache(&self, pool_type: &PoolType) -> Result<PoolCache, PoolSyncError> {
+        Ok(self
+            .cache_store
+            .read(pool_type, self.chain)?
+            .unwrap_or_else(|| PoolCache::new(*pool_type, self.chain)))
+    }
+
+    // The stored caches of `pool_types` in the same order, read concurrently on the blocking
+    // pool since large caches take a while to deserialize
+    pub(crate) async fn read_caches(
+        &self,
+        pool_types: &[PoolType],
+    ) -> Result<Vec<PoolCache>, PoolSyncError> {
+        let reads = pool_types.iter().map(|pool_type| {
+            let (store, pool_type, chain) = (self.cache_store.clone(), *pool_type, self.chain);
+            tokio::task::spawn_blocking(move || {
+                Ok(store
+                    .read(&pool_type, chain)?
+                    .unwrap_or_else(|| PoolCache::new(pool_type, chain)))
+            })
+        });
+        futures::future::join_all(reads)
+            .await
+            .into_iter()
+            .map(|read| {
+                read.map_err(|e| PoolSyncError::CacheError(format!("cache read failed: {}", e)))?
+            })
+            .collect()
+    }
+
+    // Connect to an rpc url through the configured http client, or a default one
+    fn http_provider(&self, url: &str) -> Result<Arc<SyncProvider>, PoolSyncError> {
+        let url = parse_url(url)?;
+        let builder = ProviderBuilder::new().network::<alloy::network::AnyNetwork>();
+        Ok(Arc::new(match &self.http_client {
+            Some(client) => {
+                let http = Http::with_client(client.clone(), url);
+                let is_local = http.guess_local();
+                builder.on_client(RpcClient::new(http, is_local))
+            }
+            None => builder.on_http(url),
+        }))
     }
+
+    // Sync one protocol from its cache up to the round's end block. Returns the batches that
+    // failed population, or `None` when the protocol was already up to date
+    async fn sync_protocol(
+        &self,
+        cache: &mut PoolCache,
+        round: &SyncRound,
+    ) -> Result<Option<Vec<SyncFailure>>, PoolSyncError> {
+        let end_block = round.end_block;
+        let start_block = self.first_block_to_sync(cache);
+        
+        if start_block > end_block {
+            println!("⏭️  {} 协议已为最新状态 (区块 {})", cache.pool_type, cache.last_synced_block);
+            info!(
+                "{} already synced past target block {} (cached at {})",
+                cache.pool_type, end_block, cache.last_synced_block
+            );
+            return Ok(None);
+        }
+
+        println!("🔗 正在同步 {} 协议 (区块 {} → {})", cache.pool_type, start_block, end_block);
+
+        let fetcher = self.fetchers[&cache.pool_type].clone();
+
+        // confirm the creation event is emitted before trusting an empty discovery
+        if self.probe_signatures && cache.is_initial_sync {
+            let probe_end = (start_block + SIGNATURE_PROBE_RANGE).min(end_block);
+            Rpc::probe_event_signature(
+                start_block,
+                probe_end,
+                round.archive.clone(),
+                fetcher.clone(),
+                self.chain,
+            )
+            .await
+            .map_err(|e| PoolSyncError::ProviderError(e.to_string()))?;
+        }
+
+        // fetch all of the pool addresses, skipping blocks an interrupted sync already covered
+        let discovery_start = match cache.discovered_block {
+            Some(discovered_block) if discovered_block >= start_block => discovered_block + 1,
+            _ => start_block,
+        };
+        let discovery_timer = Instant::now();
+        let creations = if discovery_start <= end_block {
+            Rpc::fetch_pool_creations(
+                discovery_start,
+                end_block,
+                round.archive.clone(),
+                fetcher.clone(),
+                self.chain,
+                self.rate_limit,
+                &round.rate,
+                &round.circuit_breaker,
+            )
+            .await
+            .expect(
+                "Failed to fetch pool addresses. Exiting due to having inconclusive state",
+            )
+        } else {
+            Vec::new()
+        };
+        let discovery_elapsed = discovery_timer.elapsed();
+
+        // drop excluded pools before they cost any population calls
+        let mut creations: Vec<PoolCreation> = creations
+            .into_iter()
+            .filter(|creation| !self.excluded_addresses.contains(&creation.address))
+            .collect();
+        cache
+            .pending
+            .retain(|pending| !self.excluded_addresses.contains(&pending.creation.address));
+
+        if self.capture_creators {
+            Rpc::fetch_creators(
+                round.archive.clone(),
+                &mut creations,
+                &round.rate,
+                &round.circuit_breaker,
+            )
+            .await;
+        }
+
+        // checkpoint the discovered pools, so population can resume without rediscovering them
+        let first_index = cache.next_creation_index;
+        cache.pending.extend(creations.iter().enumerate().map(|(i, creation)| PendingPool {
+            creation: *creation,
+            creation_index: first_index + i as u64,
+            state: PopulationState::Discovered,
+        }));
+        cache.next_creation_index += creations.len() as u64;
+        cache.discovered_block = Some(end_block);
+        self.cache_store.write_checkpoint(cache, self.chain)?;
+
+        let pending = std::mem::take(&mut cache.pending);
+        // pools retried from an earlier sync were created before this one's blocks
+        let first_creation_block = pending
+            .iter()
+            .map(|pending| pending.creation.block)
+            .min()
+            .map_or(start_block, |block| block.min(start_block));
+        let pool_addrs: Vec<_> = pending.iter().map(|pending| pending.creation.address).collect();
+        sync_metrics::pools_discovered(cache.pool_type, creations.len());
+
+        if cache.is_initial_sync && pool_addrs.is_empty() {
+            warn!(
+                "Initial sync of {} found no pools in blocks {}-{}, check that `{}` matches the factory event",
+                cache.pool_type,
+                start_block,
+                end_block,
+                fetcher.pair_created_signature()
+            );
+        }
+
+        // populate all of the pool data
+        let populated = Rpc::populate_pools(
+            pool_addrs.clone(),
+            round.full.clone(),
+            cache.pool_type,
+            fetcher.clone(),
+            self.rate_limit,
+            self.chain,
+            // read the state at the block the sync stops at, not the chain tip
+            PopulationConfig {
+                block: Some(end_block),
+                retry_budget: round.retry_budget.clone(),
+                circuit_breaker: round.circuit_breaker.clone(),
+                rate: round.rate.clone(),
+                known_tokens: round.known_tokens.clone(),
+                ..self.population.clone()
+            },
+        )
+        .await;
+        let populated = match populated {
+            Err(e) if is_retry_budget_exceeded(&e) || is_data_sync_unsupported(&e) => {
+                return Err(e
+                    .downcast()
+                    .unwrap_or_else(|e| PoolSyncError::ProviderError(format!("{:#}", e))))
+            }
+            populated => populated,
+        };
+        let (mut new_pools, failed_batches) = populated
+            .expect("Failed to sync pool data, Exiting due to haveing inconclusive state");
+        let failures: Vec<SyncFailure> = failed_batches
+            .into_iter()
+            .map(|(addresses, error)| SyncFailure {
+                pool_type: cache.pool_type,
+                block_range: (start_block, end_block),
+                addresses,
+                error,
+            })
+            .collect();
+
+        // pools in failed batches stay pending for the next sync, the rest were populated or
+        // rejected as invalid
+        let failed: HashSet<Address> = failures
+            .iter()
+            .flat_map(|failure| failure.addresses.iter().copied())
+            .collect();
+        let creation_indices: HashMap<Address, u64> = pending
+            .iter()
+            .map(|pending| (pending.creation.address, pending.creation_index))
+            .collect();
+        assign_creation_indices(&mut new_pools, &creation_indices);
+        let creations: Vec<PoolCreation> = pending.iter().map(|pending| pending.creation).collect();
+        cache.pending = pending
+            .into_iter()
+            .filter(|pending| failed.contains(&pending.creation.address))
+            .map(|pending| PendingPool {
+                state: PopulationState::Failed,
+                ..pending
+            })
+            .collect();
+        assign_created_at_blocks(&mut new_pools, &creations);
+        assign_creators(&mut new_pools, &creations);
+        for (pool, (token0, token1)) in token_order_mismatches(&new_pools, &creations) {
+            warn!(
+                "{} pool {}: data-sync returned tokens ({}, {}) but the factory created it with ({}, {})",
+                cache.pool_type,
+                pool.address(),
+                pool.token0_address(),
+                pool.token1_address(),
+                token0,
+                token1
+            );
+        }
+
+        // drop unwanted pools before any further work is done on them
+        new_pools.retain(|pool| self.filters.iter().all(|keep| keep(pool)));
+        sync_metrics::pools_populated(cache.pool_type, new_pools.len());
+        round
+            .failed_token_metadata
+            .lock()
+            .unwrap()
+            .extend(failed_token_metadata(&new_pools));
+        if self.check_erc20 {
+            let tokens = new_pools
+                .iter()
+                .flat_map(|pool| [pool.token0_address(), pool.token1_address()])
+                .collect();
+            let non_compliant = non_erc20_tokens(
+                &round.full,
+                tokens,
+                self.population.token_concurrency.min(self.rate_limit as usize),
+                self.population.token_name_attempts,
+                &round.rate,
+            )
+            .await;
+            round.non_erc20_tokens.lock().unwrap().extend(non_compliant);
+        }
+
+
+        // catch up all the old pools. They are never read from their contract again, a fresh
+        // snapshot would drop the ticks accumulated so far, so every event since the cache is
+        // replayed on top of the cached state instead
+        let liquidity_timer = Instant::now();
+        Rpc::populate_liquidity(
+            start_block,
+            end_block,
+            &mut cache.pools,
+            round.liquidity.clone(),
+            fetcher.clone(),
+            self.rate_limit,
+            &round.rate,
+            &round.circuit_breaker,
+            cache.is_initial_sync,
+        )
+        .await
+        .expect("Failed to populate liquidity information, Exiting due to having inconclusive state");
+
+        // update the new pools from the first block one of them was created in, the
+        // configured window only seeds this first replay and is clamped to those blocks so a
+        // later sync never replays it again
+        let (liquidity_start, liquidity_end) = match self.liquidity_range {
+            Some((from, to)) => (from.max(first_creation_block), to.min(end_block)),
+            None => (first_creation_block, end_block),
+        };
+        if !new_pools.is_empty() && liquidity_start <= liquidity_end {
+            Rpc::populate_liquidity(
+                liquidity_start,
+                liquidity_end,
+                &mut new_pools,
+                round.liquidity.clone(),
+                fetcher.clone(),
+                self.rate_limit,
+                &round.rate,
+                &round.circuit_breaker,
+                true,
+            )
+            .await
+            .expect("Failed to populate liquidity information, Exiting due to having inconclusive state");
+        }
+
+        debug!(
+            "{} blocks {}-{}: fetch_pool_addrs took {:?} for {} pools, populate_liquidity took {:?} for {} pools",
+            cache.pool_type,
+            start_block,
+            end_block,
+            discovery_elapsed,
+            pool_addrs.len(),
+            liquidity_timer.elapsed(),
+            cache.pools.len() + new_pools.len()
+        );
+
+        // bound the memory of pathological pools
+        if let Some(max_ticks) = self.max_ticks_per_pool {
+            for pool in cache.pools.iter_mut().chain(new_pools.iter_mut()) {
+                if let Some(v3) = pool.get_v3_mut() {
+                    if v3.truncate_ticks(max_ticks) {
+                        info!(
+                            "Truncated {} pool {} to the {} ticks nearest tick {}",
+                            cache.pool_type, v3.address, max_ticks, v3.tick
+                        );
+                    }
+                }
+            }
+        }
+
+        // hand the new pools out before they are merged
+        if let Some(sink) = &round.ndjson_sink {
+            write_ndjson(&mut *sink.lock().unwrap(), &new_pools)?;
+        }
+        if let Some(batch_sink) = &round.batch_sink {
+            batch_sink.send(&new_pools).await;
+        }
+
+        // merge old and new
+        let new_pools_count = new_pools.len();
+        round
+            .new_pool_addresses
+            .lock()
+            .unwrap()
+            .extend(new_pools.iter().map(Pool::address));
+        cache.pools.extend(new_pools);
+
+
+        // update info for cache
+        cache.last_synced_block = end_block;
+        cache.discovered_block = None;
+        cache.is_initial_sync = false;
+        
+        println!("✅ {} 协议同步完成 - 总池数: {}, 新增池: {}, 同步至区块: {}", 
+            cache.pool_type, cache.pools.len(), new_pools_count, end_block);
+
+        Ok(Some(failures))
+    }
+}
+
+/// What a sync did for one protocol, collected for the summary table
+struct ProtocolSummary {
+    pool_type: PoolType,
+    /// Pools in the cache before the sync
+    cached_pools: usize,
+    /// First block fetched, `None` when the protocol was already up to date
+    first_block: Option<u64>,
+    /// Time spent syncing the protocol across every round
+    duration: Duration,
+}
+
+/// Renders one row per protocol with its pool counts, the blocks covered by the sync and
+/// how long it took
+fn summary_table(summaries: &[ProtocolSummary], caches: &[PoolCache]) -> Table {
+    let mut table = Table::new();
+    table.set_header(vec!["Protocol", "Pools", "New pools", "Blocks", "Duration"]);
+    for (summary, cache) in summaries.iter().zip(caches) {
+        let blocks = match summary.first_block {
+            Some(first_block) => format!("{}-{}", first_block, cache.last_synced_block),
+            None => format!("up to date at {}", cache.last_synced_block),
+        };
+        table.add_row(vec![
+            summary.pool_type.to_string(),
+            cache.pools.len().to_string(),
+            cache.pools.len().saturating_sub(summary.cached_pools).to_string(),
+            blocks,
+            format!("{:.2?}", summary.duration),
+        ]);
+    }
+    table
 }
 
+/// Tokens of the pools left without a usable symbol or without decimals
+fn failed_token_metadata(pools: &[Pool]) -> HashSet<Address> {
+    let failed = |name: String, decimals: Option<u8>| {
+        name.is_empty() || name == UNKNOWN_TOKEN_NAME || decimals.is_none()
+    };
+    pools
+        .iter()
+        .flat_map(|pool| {
+            [
+                (pool.token0_address(), failed(pool.token0_name(), pool.token0_decimals())),
+                (pool.token1_address(), failed(pool.token1_name(), pool.token1_decimals())),
+            ]
+        })
+        .filter_map(|(token, failed)| failed.then_some(token))
+        .collect()
+}
+
+/// Appends each pool to the writer as a line of JSON and flushes it, so a reader tailing the
+/// file sees whole protocols at a time
+fn write_ndjson(writer: &mut impl Write, pools: &[Pool]) -> Result<(), PoolSyncError> {
+    for pool in pools {
+        serde_json::to_writer(&mut *writer, pool)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Finds the addresses with pools from more than one protocol and applies `policy` to them,
+/// leaving every other pool in place
+pub(crate) fn resolve_address_collisions(
+    mut pools: Vec<Pool>,
+    policy: CollisionPolicy,
+) -> (Vec<Pool>, Vec<AddressCollision>) {
+    let mut pool_types: HashMap<Address, Vec<PoolType>> = HashMap::new();
+    for pool in &pools {
+        pool_types.entry(pool.address()).or_default().push(pool.pool_type());
+    }
+
+    let mut collisions: Vec<AddressCollision> = pool_types
+        .into_iter()
+        .filter_map(|(address, mut pool_types)| {
+            pool_types.sort();
+            pool_types.dedup();
+            (pool_types.len() > 1).then_some(AddressCollision { address, pool_types })
+        })
+        .collect();
+    collisions.sort_by_key(|collision| collision.address);
+
+    // the protocol kept at each colliding address, `None` to drop them all
+    let kept: HashMap<Address, Option<PoolType>> = collisions
+        .iter()
+        .map(|collision| {
+            let first = collision.pool_types[0];
+            let kept = match policy {
+                CollisionPolicy::KeepAll | CollisionPolicy::DropAll => None,
+                CollisionPolicy::KeepFirst => Some(first),
+                CollisionPolicy::Prefer(preferred) => {
+                    Some(if collision.pool_types.contains(&preferred) { preferred } else { first })
+                }
+            };
+            (collision.address, kept)
+        })
+        .collect();
+
+    if policy != CollisionPolicy::KeepAll {
+        pools.retain(|pool| match kept.get(&pool.address()) {
+            Some(kept) => *kept == Some(pool.pool_type()),
+            None => true,
+        });
+    }
+    (pools, collisions)
+}
+
+/// The block a protocol resumes from: the block after the cache, raised to `start_block`
+/// when the cache has not reached it yet
+pub(crate) fn resolve_start_block(last_synced_block: u64, start_block: Option<u64>) -> u64 {
+    match start_block {
+        // 如果指定了自定义起始区块，只有在缓存还没达到这个区块时才使用
+        Some(start_block) if last_synced_block < start_block => start_block,
+        _ => last_synced_block + 1,
+    }
+}
+
+// The first block a protocol syncs: exactly the forced start until the fresh cache it was
+// given has been synced, otherwise the block after its cache raised to `start_block`, which
+// a forced start overrides
+fn first_block_to_sync(
+    cache: &PoolCache,
+    start_block: Option<u64>,
+    force_start_block: Option<u64>,
+) -> u64 {
+    match force_start_block {
+        Some(block) if cache.is_initial_sync => block,
+        Some(_) => resolve_start_block(cache.last_synced_block, None),
+        None => resolve_start_block(cache.last_synced_block, start_block),
+    }
+}
+
+// The block a protocol's progress counts from, the one before its first block to sync
+fn progress_origin(
+    cache: &PoolCache,
+    start_block: Option<u64>,
+    force_start_block: Option<u64>,
+) -> u64 {
+    first_block_to_sync(cache, start_block, force_start_block).saturating_sub(1)
+}
+
+/// Looks up a `POOLSYNC_*` variable, `None` when unset or blank
+pub(crate) type VarLookup<'a> = &'a dyn Fn(&str) -> Result<Option<String>, PoolSyncError>;
+
+/// The value of an environment variable, `None` when unset or blank
+pub(crate) fn env_var(key: &str) -> Result<Option<String>, PoolSyncError> {
+    match std::env::var(key) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(PoolSyncError::EnvConfigError(format!("{}: {}", key, e))),
+    }
+}
+
+pub(crate) fn parse_var<T>(vars: VarLookup, key: &str) -> Result<Option<T>, PoolSyncError>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    vars(key)?
+        .map(|value| {
+            value.parse().map_err(|e| {
+                PoolSyncError::EnvConfigError(format!("{}: invalid value {:?}: {}", key, value, e))
+            })
+        })
+        .transpose()
+}
+
+fn parse_url(url: &str) -> Result<reqwest::Url, PoolSyncError> {
+    url.parse()
+        .map_err(|e| PoolSyncError::ProviderError(format!("invalid rpc url {}: {}", url, e)))
+}
@@ -47,4 +47,46 @@ sol!(
     #[sol(rpc)]
     V2DataSync,
     "src/abi/V2DataSync.json"
-);
\ No newline at end of file
+);
+
+// Pool state views read directly by the rpc batch population strategy
+sol!(
+    #[derive(Debug)]
+    #[sol(rpc)]
+    contract V3PoolState {
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+        function slot0() external view returns (uint160 sqrtPriceX96, int24 tick);
+        function liquidity() external view returns (uint128);
+        function tickSpacing() external view returns (int24);
+        function fee() external view returns (uint24);
+    }
+);
+
+sol!(
+    #[derive(Debug)]
+    #[sol(rpc)]
+    contract V2PairState {
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+        function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+    }
+);
+
+// Full slot0 layout, read only when protocol fees are requested. Uniswap packs `feeProtocol`
+// into a uint8 while Agni widens it to a uint32, both fit the same word
+sol!(
+    #[derive(Debug)]
+    #[sol(rpc)]
+    contract V3PoolSlot0 {
+        function slot0() external view returns (
+            uint160 sqrtPriceX96,
+            int24 tick,
+            uint16 observationIndex,
+            uint16 observationCardinality,
+            uint16 observationCardinalityNext,
+            uint32 feeProtocol,
+            bool unlocked
+        );
+    }
+);
@@ -6,23 +6,35 @@
 
 use alloy::dyn_abi::DynSolType;
 use alloy::dyn_abi::DynSolValue;
-use alloy::primitives::{Address, Log};
+use alloy::json_abi::Event;
+use alloy::primitives::{Address, Bytes, Log, B256, U256};
+use alloy::sol_types::SolEvent;
 use pool_structures::v3_structure::UniswapV3Pool;
-use pool_structures::v2_structure::MerchantMoeV2Pool;
+use pool_structures::v2_structure::{MerchantMoeV2Pool, V2_SWAP_FEE, V2_SWAP_FEE_FRACTION};
+use pool_fetchers::{AgniV3Fetcher, MerchantMoeV2Fetcher, UniswapV3Fetcher};
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
 
 use crate::chain::Chain;
+use crate::errors::PoolSyncError;
+use crate::events::DataEvents;
 use crate::impl_pool_info;
 
-mod gen;
+pub(crate) mod gen;
 pub mod pool_builder;
 pub mod pool_fetchers;
 pub mod pool_structures;
 
+/// Average time between Mantle blocks, used to turn block counts into durations
+pub const MANTLE_BLOCK_TIME: Duration = Duration::from_secs(2);
+
 /// Enumerates the supported pool types
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum PoolType {
     UniswapV3,
     MerchantMoe,
@@ -30,24 +42,38 @@ pub enum PoolType {
 }
 
 impl PoolType {
+    /// Every supported pool type
+    pub const ALL: [PoolType; 3] = [PoolType::UniswapV3, PoolType::MerchantMoe, PoolType::Agni];
+
+    /// The built-in fetcher of the pool type, the one `PoolSyncBuilder::add_pool` registers
+    pub fn fetcher(&self) -> Arc<dyn PoolFetcher> {
+        match self {
+            PoolType::UniswapV3 => Arc::new(UniswapV3Fetcher),
+            PoolType::MerchantMoe => Arc::new(MerchantMoeV2Fetcher),
+            PoolType::Agni => Arc::new(AgniV3Fetcher),
+        }
+    }
+
+    /// The design the pool type follows, the family its built-in fetcher declares
+    pub fn family(&self) -> PoolFamily {
+        match self {
+            PoolType::UniswapV3 | PoolType::Agni => PoolFamily::V3,
+            PoolType::MerchantMoe => PoolFamily::V2,
+        }
+    }
+
     pub fn is_v3(&self) -> bool {
-        matches!(self, PoolType::UniswapV3 | PoolType::Agni)
+        self.family() == PoolFamily::V3
     }
     
     pub fn is_v2(&self) -> bool {
-        matches!(self, PoolType::MerchantMoe)
+        self.family() == PoolFamily::V2
     }
 
     pub fn build_pool(&self, pool_data: &[DynSolValue]) -> Pool {
-        if self.is_v3() {
-            let pool = UniswapV3Pool::from(pool_data);
-            Pool::new_v3(*self, pool)
-        } else if self.is_v2() {
-            let pool = MerchantMoeV2Pool::from(pool_data);
-            Pool::new_v2(*self, pool)
-        } else {
-            panic!("Invalid pool type");
-        }
+        self.family()
+            .build_pool(*self, pool_data)
+            .expect("Invalid pool type")
     }
 }
 
@@ -111,12 +137,177 @@ impl Pool {
         }
     }
 
+    /// Reads a pool from its JSON, as stored in the cache or exported with `KeyCase::Snake`.
+    /// The pool carries its full tick map, so pricing and swap simulation work offline
+    pub fn from_json_str(json: &str) -> Result<Pool, PoolSyncError> {
+        Ok(serde_json::from_str(json)?)
+    }
 
+    /// Name of the pool's variant, the same as its `PoolType`, e.g. `"UniswapV3"`
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Pool::UniswapV3(_) => "UniswapV3",
+            Pool::MerchantMoe(_) => "MerchantMoe",
+            Pool::Agni(_) => "Agni",
+        }
+    }
+
+    /// Names of the pool's fields holding a value, in alphabetical order and as they appear
+    /// in the cache JSON. Unset optional fields, e.g. unknown decimals, are left out
+    pub fn field_names(&self) -> Vec<String> {
+        let fields = match self {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => serde_json::to_value(pool),
+            Pool::MerchantMoe(pool) => serde_json::to_value(pool),
+        };
+        match fields {
+            Ok(Value::Object(fields)) => fields
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(name, _)| name)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+
+
+    /// Spot price of token0 denominated in token1, `None` if it cannot be determined
+    pub fn price_token0_in_token1(&self) -> Option<f64> {
+        match self {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => pool.price_token0_in_token1(),
+            Pool::MerchantMoe(pool) => pool.price_token0_in_token1(),
+        }
+    }
+
+    /// Spot price of token1 denominated in token0, `None` if it cannot be determined
+    pub fn price_token1_in_token0(&self) -> Option<f64> {
+        self.price_token0_in_token1().map(|price| 1.0 / price)
+    }
 
+    /// Price of `token_in` in the other token after the swap fee, adjusted for decimals,
+    /// `None` if it cannot be determined
+    pub fn execution_price(&self, token_in: Address, amount_in: U256) -> Option<f64> {
+        match self {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => pool.execution_price(token_in, amount_in),
+            Pool::MerchantMoe(pool) => pool.execution_price(token_in, amount_in),
+        }
+    }
+
+    /// Output amount of swapping `amount_in` of `token_in` through this pool
+    pub fn simulate_swap(&self, token_in: Address, amount_in: U256) -> Result<U256, PoolSyncError> {
+        let tokens = (self.token0_address(), self.token1_address());
+        let zero_for_one = swap_direction(self.address(), tokens, token_in)?;
+
+        match self {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => pool.simulate_swap(zero_for_one, amount_in),
+            Pool::MerchantMoe(pool) => pool.simulate_swap(zero_for_one, amount_in).ok_or_else(|| {
+                PoolSyncError::SwapSimulationError(format!("pool {} has no reserves", pool.address))
+            }),
+        }
+    }
+
+    /// Minimum output of swapping `amount_in` of `token_in` through this pool within a
+    /// slippage tolerance of `slippage_bps` basis points, e.g. a swap's `amountOutMinimum`
+    pub fn min_amount_out(
+        &self,
+        token_in: Address,
+        amount_in: U256,
+        slippage_bps: u32,
+    ) -> Result<U256, PoolSyncError> {
+        match self {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => {
+                pool.min_amount_out(token_in, amount_in, slippage_bps)
+            }
+            Pool::MerchantMoe(pool) => pool.min_amount_out(token_in, amount_in, slippage_bps),
+        }
+    }
+
+    /// Fee taken from `amount_in` of `token_in` when swapping it through this pool, in
+    /// `token_in` units
+    pub fn fee_amount_for_swap(&self, token_in: Address, amount_in: U256) -> Result<U256, PoolSyncError> {
+        match self {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => pool.fee_amount_for_swap(token_in, amount_in),
+            Pool::MerchantMoe(pool) => pool.fee_amount_for_swap(token_in, amount_in),
+        }
+    }
+
+    /// A pool is valid when its address and tokens are set and it trades two distinct
+    /// tokens, a pool of a token against itself breaks all price math
     pub fn is_valid(&self) -> bool {
         self.address() != Address::ZERO
             && self.token0_address() != Address::ZERO
             && self.token1_address() != Address::ZERO
+            && self.token0_address() != self.token1_address()
+    }
+
+    pub fn set_creation_index(&mut self, index: u64) {
+        if let Some(pool) = self.get_v3_mut() {
+            pool.creation_index = index;
+        } else if let Some(pool) = self.get_v2_mut() {
+            pool.creation_index = index;
+        }
+    }
+
+    pub fn set_created_at_block(&mut self, block: u64) {
+        if let Some(pool) = self.get_v3_mut() {
+            pool.created_at_block = Some(block);
+        } else if let Some(pool) = self.get_v2_mut() {
+            pool.created_at_block = Some(block);
+        }
+    }
+
+    pub fn set_creator(&mut self, creator: Address) {
+        if let Some(pool) = self.get_v3_mut() {
+            pool.creator = Some(creator);
+        } else if let Some(pool) = self.get_v2_mut() {
+            pool.creator = Some(creator);
+        }
+    }
+
+    fn set_total_supplies(&mut self, token0: Option<U256>, token1: Option<U256>) {
+        if let Some(pool) = self.get_v3_mut() {
+            pool.token0_total_supply = token0;
+            pool.token1_total_supply = token1;
+        } else if let Some(pool) = self.get_v2_mut() {
+            pool.token0_total_supply = token0;
+            pool.token1_total_supply = token1;
+        }
+    }
+
+    // Decimals the pool read could not resolve, leaving the known ones as they are
+    fn fill_missing_decimals(&mut self, token0: Option<u8>, token1: Option<u8>) {
+        if let Some(pool) = self.get_v3_mut() {
+            pool.token0_decimals = pool.token0_decimals.or(token0);
+            pool.token1_decimals = pool.token1_decimals.or(token1);
+        } else if let Some(pool) = self.get_v2_mut() {
+            pool.token0_decimals = pool.token0_decimals.or(token0);
+            pool.token1_decimals = pool.token1_decimals.or(token1);
+        }
+    }
+
+    /// The raw data-sync tuple the pool was decoded from, `None` unless population ran
+    /// with `debug_raw_tuples`
+    pub fn debug_raw(&self) -> Option<&[String]> {
+        match self {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => pool.debug_raw.as_deref(),
+            Pool::MerchantMoe(pool) => pool.debug_raw.as_deref(),
+        }
+    }
+
+    fn set_debug_raw(&mut self, raw: Vec<String>) {
+        if let Some(pool) = self.get_v3_mut() {
+            pool.debug_raw = Some(raw);
+        } else if let Some(pool) = self.get_v2_mut() {
+            pool.debug_raw = Some(raw);
+        }
+    }
+
+    fn set_extra(&mut self, extra: HashMap<String, String>) {
+        if let Some(pool) = self.get_v3_mut() {
+            pool.extra = extra;
+        } else if let Some(pool) = self.get_v2_mut() {
+            pool.extra = extra;
+        }
     }
 
     fn update_token0_name(pool: &mut Pool, token0: String) {
@@ -158,7 +349,15 @@ pub trait PoolFetcher: Send + Sync {
     /// Returns the type of pool this fetcher is responsible for
     fn pool_type(&self) -> PoolType;
 
-    /// Returns the factory address for the given chain
+    /// Returns the design the fetched pools follow, which decides how they are read and
+    /// built, the family of `pool_type` by default. `PoolSyncBuilder::build` rejects a
+    /// family that cannot build the pool type, which `PoolFamily::Other` never can
+    fn family(&self) -> PoolFamily {
+        self.pool_type().family()
+    }
+
+    /// Returns the factory address for the given chain, the zero address when the protocol
+    /// has no factory on it, which `PoolSyncBuilder::build` rejects
     fn factory_address(&self, chain: Chain) -> Address;
 
     /// Returns the event signature for pool creation
@@ -167,8 +366,332 @@ pub trait PoolFetcher: Send + Sync {
     /// Attempts to create a `Pool` instance from a log entry
     fn log_to_address(&self, log: &Log) -> Address;
 
+    /// Returns the `(token0, token1)` the creation event lists for the pool, used to check
+    /// the order the data-sync contract reports them in. Defaults to `None`, skipping the check
+    fn log_to_tokens(&self, _log: &Log) -> Option<(Address, Address)> {
+        None
+    }
+
     /// Get the DynSolType for the pool
     fn get_pool_repr(&self) -> DynSolType;
+
+    /// Captures the protocol specific values of a fork from the tuple the data-sync contract
+    /// returned for a pool, stored as strings in the pool's `extra` map. Defaults to `None`,
+    /// pools read through `PopulationStrategy::RpcBatch` never carry extras
+    fn decode_extra(&self, _data: &[DynSolValue]) -> Option<HashMap<String, DynSolValue>> {
+        None
+    }
+
+    /// Returns the signatures of the events replayed to keep the pool state current.
+    /// Defaults to the Uniswap V3 Mint, Burn and Swap events
+    fn data_event_signatures(&self) -> Vec<&str> {
+        vec![
+            DataEvents::Mint::SIGNATURE,
+            DataEvents::Burn::SIGNATURE,
+            DataEvents::Swap::SIGNATURE,
+            DataEvents::Initialize::SIGNATURE,
+        ]
+    }
+}
+
+/// Sets the creation index of every pool found in `indices`, leaving the rest untouched
+pub fn assign_creation_indices(pools: &mut [Pool], indices: &HashMap<Address, u64>) {
+    for pool in pools {
+        if let Some(index) = indices.get(&pool.address()) {
+            pool.set_creation_index(*index);
+        }
+    }
+}
+
+/// A pool creation event emitted by a factory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolCreation {
+    /// Address of the created pool
+    pub address: Address,
+    /// Block the pool was created in
+    pub block: u64,
+    /// `(token0, token1)` as listed by the event, `None` when the fetcher does not decode them
+    pub tokens: Option<(Address, Address)>,
+    /// Hash of the transaction that emitted the event
+    #[serde(default)]
+    pub tx_hash: Option<B256>,
+    /// Sender of that transaction, `None` unless creators were fetched
+    #[serde(default)]
+    pub creator: Option<Address>,
+}
+
+/// Records the block each pool was created in from the discovered creation events
+pub fn assign_created_at_blocks(pools: &mut [Pool], creations: &[PoolCreation]) {
+    let blocks: HashMap<Address, u64> = creations
+        .iter()
+        .map(|creation| (creation.address, creation.block))
+        .collect();
+    for pool in pools {
+        if let Some(block) = blocks.get(&pool.address()) {
+            pool.set_created_at_block(*block);
+        }
+    }
+}
+
+/// Records the sender of each pool's creation transaction where it was fetched
+pub fn assign_creators(pools: &mut [Pool], creations: &[PoolCreation]) {
+    let creators: HashMap<Address, Address> = creations
+        .iter()
+        .filter_map(|creation| Some((creation.address, creation.creator?)))
+        .collect();
+    for pool in pools {
+        if let Some(creator) = creators.get(&pool.address()) {
+            pool.set_creator(*creator);
+        }
+    }
+}
+
+/// Pools whose token0/token1 differ from the ones in their creation event, paired with the
+/// event's `(token0, token1)`. Any hit means the data-sync contract's output no longer lines
+/// up with the decoding in `PoolType::build_pool`
+pub fn token_order_mismatches<'a>(
+    pools: &'a [Pool],
+    creations: &[PoolCreation],
+) -> Vec<(&'a Pool, (Address, Address))> {
+    let tokens: HashMap<Address, (Address, Address)> = creations
+        .iter()
+        .filter_map(|creation| Some((creation.address, creation.tokens?)))
+        .collect();
+    pools
+        .iter()
+        .filter_map(|pool| {
+            let expected = *tokens.get(&pool.address())?;
+            (expected != (pool.token0_address(), pool.token1_address())).then_some((pool, expected))
+        })
+        .collect()
+}
+
+/// Quotes `amount_in` of `path[0]` through each pool in turn, hop `i` swapping `path[i]`
+/// for `path[i + 1]` in `pools[i]`, and returns the amount of the last token received
+pub fn quote_route(pools: &[&Pool], amount_in: U256, path: &[Address]) -> Result<U256, PoolSyncError> {
+    if pools.is_empty() || path.len() != pools.len() + 1 {
+        return Err(PoolSyncError::SwapSimulationError(format!(
+            "a route through {} pools needs {} tokens, got {}",
+            pools.len(),
+            pools.len() + 1,
+            path.len()
+        )));
+    }
+
+    let mut amount = amount_in;
+    for (pool, hop) in pools.iter().zip(path.windows(2)) {
+        let (token_in, token_out) = (hop[0], hop[1]);
+        let tokens = [pool.token0_address(), pool.token1_address()];
+        if !tokens.contains(&token_out) || token_in == token_out {
+            return Err(PoolSyncError::SwapSimulationError(format!(
+                "pool {} does not swap {} for {}",
+                pool.address(),
+                token_in,
+                token_out
+            )));
+        }
+        amount = pool.simulate_swap(token_in, amount)?;
+    }
+    Ok(amount)
+}
+
+/// Whether `token_in` is token0 of the pool at `address`, failing when the pool trades
+/// neither token
+pub(crate) fn swap_direction(
+    address: Address,
+    (token0, token1): (Address, Address),
+    token_in: Address,
+) -> Result<bool, PoolSyncError> {
+    if token_in == token0 {
+        Ok(true)
+    } else if token_in == token1 {
+        Ok(false)
+    } else {
+        Err(PoolSyncError::SwapSimulationError(format!(
+            "token {} is not traded by pool {}",
+            token_in, address
+        )))
+    }
+}
+
+/// `amount_out` less a slippage tolerance of `slippage_bps` basis points, rounded down
+pub(crate) fn apply_slippage(amount_out: U256, slippage_bps: u32) -> Result<U256, PoolSyncError> {
+    if slippage_bps > 10_000 {
+        return Err(PoolSyncError::SwapSimulationError(format!(
+            "slippage of {} bps is above 100%",
+            slippage_bps
+        )));
+    }
+    Ok(amount_out * U256::from(10_000 - slippage_bps) / U256::from(10_000u64))
+}
+
+/// Percentage by which the execution price `amount_out / amount_in` falls short of the
+/// `spot` price, both in raw output units per raw input unit
+pub(crate) fn price_impact_percent(spot: f64, amount_in: U256, amount_out: U256) -> Result<f64, PoolSyncError> {
+    if amount_in.is_zero() || spot <= 0.0 || !spot.is_finite() {
+        return Err(PoolSyncError::SwapSimulationError(
+            "price impact needs a non-zero trade and spot price".to_string(),
+        ));
+    }
+    let execution = f64::from(amount_out) / f64::from(amount_in);
+    Ok((1.0 - execution / spot) * 100.0)
+}
+
+/// Protocol version family of a pool, shared by every fork of the same design
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PoolFamily {
+    /// Constant product pools with reserves, e.g. MerchantMoe
+    V2,
+    /// Concentrated liquidity pools with ticks, e.g. UniswapV3 and Agni
+    V3,
+    /// Any other design, without a pool layout to populate
+    Other,
+}
+
+impl PoolFamily {
+    /// Whether pools of `pool_type` can be built with this family's layout, only the
+    /// family of the pool type itself can
+    pub fn builds(&self, pool_type: PoolType) -> bool {
+        *self != PoolFamily::Other && *self == pool_type.family()
+    }
+
+    /// Builds a pool of `pool_type` from the tuple the data-sync contract returned for it,
+    /// `None` when this family cannot build the pool type
+    pub fn build_pool(&self, pool_type: PoolType, pool_data: &[DynSolValue]) -> Option<Pool> {
+        if !self.builds(pool_type) {
+            return None;
+        }
+        match self {
+            PoolFamily::V3 => Some(Pool::new_v3(pool_type, UniswapV3Pool::from(pool_data))),
+            PoolFamily::V2 => Some(Pool::new_v2(pool_type, MerchantMoeV2Pool::from(pool_data))),
+            PoolFamily::Other => None,
+        }
+    }
+}
+
+/// All pools of the given family across protocols, in their original order
+pub fn pools_by_family(pools: &[Pool], family: PoolFamily) -> Vec<&Pool> {
+    pools
+        .iter()
+        .filter(|pool| pool.pool_type().family() == family)
+        .collect()
+}
+
+/// All pools of the given fee tier in hundredths of a basis point, e.g. 500, 3000 or 10000,
+/// in their original order. MerchantMoe pairs are in the 3000 tier
+pub fn pools_with_fee(pools: &[Pool], fee: u32) -> Vec<&Pool> {
+    pools.iter().filter(|pool| pool.fee() == fee).collect()
+}
+
+/// Pools whose last Swap/Mint/Burn/Sync event came after `block`, in their original order.
+/// Pools that never saw an event since they were populated are left out
+pub fn pools_changed_since(pools: &[Pool], block: u64) -> Vec<&Pool> {
+    pools
+        .iter()
+        .filter(|pool| pool.last_update_block() > block)
+        .collect()
+}
+
+/// Renders a decoded value for a pool's `extra` map, numbers in decimal and bytes in hex
+pub(crate) fn sol_value_to_string(value: &DynSolValue) -> String {
+    match value {
+        DynSolValue::Bool(value) => value.to_string(),
+        DynSolValue::Int(value, _) => value.to_string(),
+        DynSolValue::Uint(value, _) => value.to_string(),
+        DynSolValue::FixedBytes(value, size) => Bytes::copy_from_slice(&value[..*size]).to_string(),
+        DynSolValue::Address(value) => value.to_string(),
+        DynSolValue::Bytes(value) => Bytes::copy_from_slice(value).to_string(),
+        DynSolValue::String(value) => value.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Groups pools by their canonical (sorted) token pair, so every pool trading the same
+/// two tokens lands in one bucket regardless of protocol or token order
+pub fn group_by_pair(pools: &[Pool]) -> HashMap<(Address, Address), Vec<&Pool>> {
+    let mut groups: HashMap<(Address, Address), Vec<&Pool>> = HashMap::new();
+    for pool in pools {
+        let (token0, token1) = (pool.token0_address(), pool.token1_address());
+        let pair = if token0 <= token1 {
+            (token0, token1)
+        } else {
+            (token1, token0)
+        };
+        groups.entry(pair).or_default().push(pool);
+    }
+    groups
+}
+
+/// Price of `base` in `quote` across every pool trading the pair, as the geometric mean of
+/// the pool prices weighted by each pool's depth. Depth is the active liquidity of a V3
+/// pool and `sqrt(reserve0 * reserve1)` of a V2 pool, the same invariant in both families.
+/// Pools for other pairs, without a price or without depth are skipped, `None` when no
+/// pool is left
+pub fn consolidated_price(pools: &[&Pool], base: Address, quote: Address) -> Option<f64> {
+    let (mut weighted_log_price, mut total_depth) = (0.0, 0.0);
+    for pool in pools {
+        let price = if (pool.token0_address(), pool.token1_address()) == (base, quote) {
+            pool.price_token0_in_token1()
+        } else if (pool.token0_address(), pool.token1_address()) == (quote, base) {
+            pool.price_token1_in_token0()
+        } else {
+            None
+        };
+        let depth = match pool {
+            Pool::UniswapV3(pool) | Pool::Agni(pool) => pool.liquidity as f64,
+            Pool::MerchantMoe(pool) => {
+                (f64::from(pool.token0_reserves) * f64::from(pool.token1_reserves)).sqrt()
+            }
+        };
+        if let Some(price) = price.filter(|price| price.is_finite() && *price > 0.0) {
+            if depth > 0.0 {
+                weighted_log_price += depth * price.ln();
+                total_depth += depth;
+            }
+        }
+    }
+    (total_depth > 0.0).then(|| (weighted_log_price / total_depth).exp())
+}
+
+/// Validates that the fetcher's creation event signature is canonical and returns its topic hash
+///
+/// A signature that does not round trip through the event parser (e.g. `uint` instead of
+/// `uint256`, or stray whitespace) hashes to a topic the factory never emits, which would
+/// otherwise make discovery silently find zero pools.
+pub fn validate_event_signature(fetcher: &dyn PoolFetcher) -> Result<B256, PoolSyncError> {
+    let signature = fetcher.pair_created_signature();
+    match Event::parse(signature) {
+        Ok(event) if event.signature() == signature => Ok(event.selector()),
+        _ => Err(PoolSyncError::InvalidEventSignature(
+            fetcher.pool_type(),
+            signature.to_string(),
+        )),
+    }
+}
+
+/// Validates that the fetcher has a factory address on the chain and returns it
+///
+/// Discovery filters the creation logs by this address, so the zero address a fetcher
+/// returns for a chain it has no factory on would make it find nothing without failing.
+pub fn validate_factory_address(
+    fetcher: &dyn PoolFetcher,
+    chain: Chain,
+) -> Result<Address, PoolSyncError> {
+    match fetcher.factory_address(chain) {
+        Address::ZERO => Err(PoolSyncError::MissingFactoryAddress(fetcher.pool_type(), chain)),
+        address => Ok(address),
+    }
+}
+
+/// Validates that the fetcher's family can build its pool type and returns the family
+///
+/// Population reads and builds the pools with the layout of the fetcher's family, which
+/// would otherwise fail on the first pool instead of when the sync is configured.
+pub fn validate_pool_family(fetcher: &dyn PoolFetcher) -> Result<PoolFamily, PoolSyncError> {
+    match fetcher.family() {
+        family if family.builds(fetcher.pool_type()) => Ok(family),
+        family => Err(PoolSyncError::UnsupportedPoolFamily(fetcher.pool_type(), family)),
+    }
 }
 
 /// Defines common methods that are used to access information about the pools
@@ -178,11 +701,69 @@ pub trait PoolInfo {
     fn token1_address(&self) -> Address;
     fn token0_name(&self) -> String;
     fn token1_name(&self) -> String;
-    fn token0_decimals(&self) -> u8;
-    fn token1_decimals(&self) -> u8;
+    fn token0_decimals(&self) -> Option<u8>;
+    fn token1_decimals(&self) -> Option<u8>;
     fn pool_type(&self) -> PoolType;
     fn fee(&self) -> u32;
+    /// The fee kept by liquidity providers after the protocol fee for a swap in the given
+    /// direction. Equal to `fee` unless the protocol fee was fetched
+    fn lp_fee(&self, zero_for_one: bool) -> u32;
     fn stable(&self) -> bool;
+    fn last_update_block(&self) -> u64;
+    fn creation_index(&self) -> u64;
+    fn created_at_block(&self) -> Option<u64>;
+    /// Sender of the pool's creation transaction, `None` unless `capture_creators` was set
+    fn creator(&self) -> Option<Address>;
+    fn token0_total_supply(&self) -> Option<U256>;
+    fn token1_total_supply(&self) -> Option<U256>;
+    fn extra(&self) -> &HashMap<String, String>;
+
+    /// The swap fee as a fraction of the input amount, e.g. `0.003` for the 3000 V3 fee
+    /// tier and for every MerchantMoe pair
+    fn fee_fraction(&self) -> f64 {
+        if self.pool_type().is_v2() {
+            V2_SWAP_FEE_FRACTION
+        } else {
+            f64::from(self.fee()) / 1e6
+        }
+    }
+
+    /// Identifier unique across chains and protocols and stable across runs, e.g.
+    /// `mantle:uniswapv3:0x5bef...` with the address in lowercase hex. Pools do not know their
+    /// chain, so it is passed in
+    fn pool_id(&self, chain: Chain) -> String {
+        format!(
+            "{}:{}:{:#x}",
+            chain.to_string().to_lowercase(),
+            self.pool_type().to_string().to_lowercase(),
+            self.address()
+        )
+    }
+
+    /// Returns whichever token is in the provided base set, `None` if neither or both are
+    fn base_token(&self, bases: &HashSet<Address>) -> Option<Address> {
+        match (
+            bases.contains(&self.token0_address()),
+            bases.contains(&self.token1_address()),
+        ) {
+            (true, false) => Some(self.token0_address()),
+            (false, true) => Some(self.token1_address()),
+            _ => None,
+        }
+    }
+
+    /// Blocks since the pool was created, `None` when its creation block is unknown or
+    /// `current_block` is before it
+    fn age_blocks(&self, current_block: u64) -> Option<u64> {
+        current_block.checked_sub(self.created_at_block()?)
+    }
+
+    /// Approximate time since the pool was created, its age in blocks at `MANTLE_BLOCK_TIME`
+    /// per block
+    fn age_duration(&self, current_block: u64) -> Option<Duration> {
+        let blocks = self.age_blocks(current_block)?;
+        Some(MANTLE_BLOCK_TIME * u32::try_from(blocks).unwrap_or(u32::MAX))
+    }
 }
 
 /* 
@@ -244,14 +825,14 @@ macro_rules! impl_pool_info {
                 }
             }
 
-            fn token0_decimals(&self) -> u8 {
+            fn token0_decimals(&self) -> Option<u8> {
                 match self {
                     $(
                         $enum_name::$variant(pool) => pool.token0_decimals,
                     )+
                 }
             }
-            fn token1_decimals(&self) -> u8 {
+            fn token1_decimals(&self) -> Option<u8> {
                 match self {
                     $(
                         $enum_name::$variant(pool) => pool.token1_decimals,
@@ -270,13 +851,78 @@ macro_rules! impl_pool_info {
             fn fee(&self) -> u32 {
                 match self {
                     Pool::UniswapV3(pool) | Pool::Agni(pool) => pool.fee,
-                    Pool::MerchantMoe(_) => 0, // V2 pools don't have fees in the same way
+                    Pool::MerchantMoe(_) => V2_SWAP_FEE,
+                }
+            }
+
+            fn lp_fee(&self, zero_for_one: bool) -> u32 {
+                match self {
+                    Pool::UniswapV3(pool) => pool.lp_fee(PoolType::UniswapV3, zero_for_one),
+                    Pool::Agni(pool) => pool.lp_fee(PoolType::Agni, zero_for_one),
+                    // every MerchantMoe swap pays 0.3% to the liquidity providers
+                    Pool::MerchantMoe(_) => V2_SWAP_FEE,
                 }
             }
 
             fn stable(&self) -> bool {
                 false
             }
+
+            fn last_update_block(&self) -> u64 {
+                match self {
+                    $(
+                        $enum_name::$variant(pool) => pool.last_update_block,
+                    )+
+                }
+            }
+
+            fn creation_index(&self) -> u64 {
+                match self {
+                    $(
+                        $enum_name::$variant(pool) => pool.creation_index,
+                    )+
+                }
+            }
+
+            fn created_at_block(&self) -> Option<u64> {
+                match self {
+                    $(
+                        $enum_name::$variant(pool) => pool.created_at_block,
+                    )+
+                }
+            }
+
+            fn creator(&self) -> Option<Address> {
+                match self {
+                    $(
+                        $enum_name::$variant(pool) => pool.creator,
+                    )+
+                }
+            }
+
+            fn token0_total_supply(&self) -> Option<U256> {
+                match self {
+                    $(
+                        $enum_name::$variant(pool) => pool.token0_total_supply,
+                    )+
+                }
+            }
+
+            fn token1_total_supply(&self) -> Option<U256> {
+                match self {
+                    $(
+                        $enum_name::$variant(pool) => pool.token1_total_supply,
+                    )+
+                }
+            }
+
+            fn extra(&self) -> &HashMap<String, String> {
+                match self {
+                    $(
+                        $enum_name::$variant(pool) => &pool.extra,
+                    )+
+                }
+            }
         }
     };
 }
@@ -1,30 +1,215 @@
 //! Pool builder for constructing pools from raw data
 
+use crate::cache::TokenCache;
 use crate::PoolInfo;
-use alloy::dyn_abi::DynSolType;
-use alloy::network::Network;
-use alloy::primitives::Address;
+use alloy::eips::{BlockId, BlockNumberOrTag};
+use alloy::network::{Network, TransactionBuilder};
+use alloy::primitives::{Address, Bytes, U256};
 use alloy::providers::Provider;
-use alloy::transports::Transport;
+use alloy::rpc::client::{BatchRequest, Waiter};
+use alloy::sol_types::{SolCall, SolValue};
+use alloy::transports::{Transport, TransportError};
 use anyhow::Result;
+use futures::StreamExt;
+use log::warn;
 use rand::Rng;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
-use super::gen::{V3DataSync, V2DataSync};
+use super::gen::{V2DataSync, V2PairState, V3DataSync, V3PoolSlot0, V3PoolState};
+use super::pool_structures::v2_structure::MerchantMoeV2Pool;
+use super::pool_structures::v3_structure::UniswapV3Pool;
 
 use crate::pools::gen::ERC20;
-use crate::pools::{Pool, PoolType, Chain};
+use crate::errors::PoolSyncError;
+use crate::rpc::{is_retry_budget_exceeded, AdaptiveRate, CircuitBreaker};
+use crate::sync_metrics;
+use crate::pools::{
+    sol_value_to_string, validate_pool_family, Chain, Pool, PoolFamily, PoolFetcher, PoolType,
+};
 
 pub const INITIAL_BACKOFF: u64 = 1000; // 1 second
 pub const MAX_RETRIES: u32 = 5;
 
+/// How pool state is read from the chain during population
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PopulationStrategy {
+    /// Deploy-and-call the data-sync contracts, reading a whole batch in one `eth_call`
+    #[default]
+    DataSync,
+    /// Batched `eth_call`s of each pool's own view functions, for providers that
+    /// reject state-override deploys
+    RpcBatch,
+}
+
+/// What population does when the data-sync contract deploy reverts, as it does on providers
+/// that cap the gas of `eth_call` or reject state-override deploys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataSyncRevertPolicy {
+    /// Read the batch again with `PopulationStrategy::RpcBatch`
+    #[default]
+    FallbackToRpcBatch,
+    /// Fail the sync with `PoolSyncError::DataSyncUnsupported`
+    Fail,
+}
+
+/// Number of population retries shared by every batch of a sync, unlimited by default
+#[derive(Debug, Clone, Default)]
+pub struct RetryBudget {
+    remaining: Option<Arc<AtomicU32>>,
+}
+
+impl RetryBudget {
+    /// A budget allowing `retries` retries in total
+    pub fn new(retries: u32) -> Self {
+        Self {
+            remaining: Some(Arc::new(AtomicU32::new(retries))),
+        }
+    }
+
+    /// Retries left, `None` when the budget is unlimited
+    pub fn remaining(&self) -> Option<u32> {
+        self.remaining.as_ref().map(|remaining| remaining.load(Ordering::SeqCst))
+    }
+
+    /// Takes one retry from the budget, false once it is spent
+    fn try_spend(&self) -> bool {
+        match &self.remaining {
+            Some(remaining) => remaining
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+                .is_ok(),
+            None => true,
+        }
+    }
+}
+
+/// Concurrency of the data-sync calls of a `Rpc::populate_pools` run, shared by every batch
+/// and every chunk of a batch so that together they stay within the rate limit. The calls
+/// are paced by `PopulationConfig::rate`. Unlimited by default
+#[derive(Debug, Clone, Default)]
+pub struct CallLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl CallLimiter {
+    /// A limiter allowing `rate_limit` calls in flight
+    pub fn new(rate_limit: u64) -> Self {
+        Self {
+            semaphore: Some(Arc::new(Semaphore::new(rate_limit.max(1) as usize))),
+        }
+    }
+
+    /// Waits for a free call slot, held until the permit is dropped
+    async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        Some(self.semaphore.as_ref()?.acquire().await.unwrap())
+    }
+}
+
+/// Options controlling how discovered pools are populated
+#[derive(Debug, Clone)]
+pub struct PopulationConfig {
+    /// Strategy used to read the pool state
+    pub strategy: PopulationStrategy,
+    /// Maximum number of concurrent token `symbol()` calls per batch
+    pub token_concurrency: usize,
+    /// Number of times each token name call is attempted while the rpc fails
+    pub token_name_attempts: u32,
+    /// Read each V3 pool's `feeProtocol` with an extra `slot0` call
+    pub protocol_fee: bool,
+    /// Read every token's `totalSupply()` at `block` alongside its name
+    pub total_supply: bool,
+    /// Block the pool state is read at, the chain tip when `None`
+    pub block: Option<u64>,
+    /// Retries shared by every `build_pools` call made with this config
+    pub retry_budget: RetryBudget,
+    /// What to do when the data-sync contract deploy reverts
+    pub on_data_sync_revert: DataSyncRevertPolicy,
+    /// Token metadata resolved ahead of the sync, these tokens are not called again
+    pub known_tokens: Arc<TokenCache>,
+    /// Split the addresses of a `build_pools` call into chunks of this size, each read and
+    /// retried on its own. `None` reads all of them at once
+    pub chunk_size: Option<usize>,
+    /// Maximum number of chunks read at once
+    pub chunk_concurrency: usize,
+    /// Keep the formatted data-sync tuple of every pool in its `debug_raw` field
+    pub debug_raw: bool,
+    /// Breaker shared with the rest of the sync, holding calls back while the rpc is degraded
+    pub circuit_breaker: CircuitBreaker,
+    /// Limiter shared by every data-sync call of a population run
+    pub limiter: CallLimiter,
+    /// Request rate shared with the rest of the sync. Every data-sync and token call waits
+    /// for a slot of it, and a 429 response to any of them lowers it
+    pub rate: AdaptiveRate,
+}
+
+impl PopulationConfig {
+    fn block_tag(&self) -> BlockNumberOrTag {
+        self.block
+            .map(BlockNumberOrTag::Number)
+            .unwrap_or(BlockNumberOrTag::Latest)
+    }
+}
+
+/// Populate the pools at `addresses`, in chunks of `config.chunk_size` read up to
+/// `config.chunk_concurrency` at a time when set. Returns the pools in the order of the
+/// chunks and the addresses of every chunk that failed after all retries, with its error.
+/// A spent retry budget or a rejected data-sync deploy fails the whole call, as does a
+/// fetcher whose family cannot build `pool_type`, see `validate_pool_family`
 pub async fn build_pools<P, T, N>(
     provider: &Arc<P>,
     addresses: Vec<Address>,
     pool_type: PoolType,
-    data: DynSolType,
+    fetcher: &dyn PoolFetcher,
+    chain: Chain,
+    config: PopulationConfig,
+) -> Result<(Vec<Pool>, Vec<(Vec<Address>, anyhow::Error)>)>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    validate_pool_family(fetcher)?;
+    let chunk_size = match config.chunk_size {
+        Some(chunk_size) if chunk_size > 0 && chunk_size < addresses.len() => chunk_size,
+        _ => addresses.len().max(1),
+    };
+
+    let config = &config;
+    let mut chunks = futures::stream::iter(addresses.chunks(chunk_size).map(|chunk| async move {
+        let populated =
+            build_pool_chunk(provider, chunk.to_vec(), pool_type, fetcher, chain, config).await;
+        (chunk, populated)
+    }))
+    .buffered(config.chunk_concurrency.max(1));
+
+    let mut pools = Vec::new();
+    let mut failed = Vec::new();
+    while let Some((chunk, populated)) = chunks.next().await {
+        match populated {
+            Ok(chunk_pools) => pools.extend(chunk_pools),
+            // retrying any other chunk would fail the same way
+            Err(e) if is_retry_budget_exceeded(&e) || is_data_sync_unsupported(&e) => {
+                return Err(e)
+            }
+            Err(e) => failed.push((chunk.to_vec(), e)),
+        }
+    }
+    Ok((pools, failed))
+}
+
+
+// Populate the pools with a single read, retried with backoff while it fails
+async fn build_pool_chunk<P, T, N>(
+    provider: &Arc<P>,
+    addresses: Vec<Address>,
+    pool_type: PoolType,
+    fetcher: &dyn PoolFetcher,
     chain: Chain,
+    config: &PopulationConfig,
 ) -> Result<Vec<Pool>>
 where
     P: Provider<T, N> + Sync + 'static,
@@ -35,15 +220,43 @@ where
     let mut backoff = INITIAL_BACKOFF;
 
     loop {
-        match populate_pool_data(provider, addresses.clone(), pool_type, data.clone(), chain).await
-        {
+        let permit = config.limiter.acquire().await;
+        config.circuit_breaker.acquire().await;
+        config.rate.tick().await;
+        sync_metrics::rpc_call("eth_call");
+        let populated = populate_pool_data(
+            provider,
+            addresses.clone(),
+            pool_type,
+            fetcher,
+            chain,
+            config,
+        )
+        .await;
+        // a revert is an answer from the node, not a failure to reach it
+        config
+            .circuit_breaker
+            .record(populated.as_ref().is_err_and(|e| !is_data_sync_unsupported(e)));
+        if let Err(e) = &populated {
+            sync_metrics::rpc_error("eth_call");
+            if let Some(e) = transport_error(e) {
+                config.rate.record(e);
+            }
+        }
+        drop(permit);
+        match populated {
             Ok(pools) => {
                 return Ok(pools);
             }
+            // a revert repeats on every retry
+            Err(e) if is_data_sync_unsupported(&e) => return Err(e),
             Err(e) => {
                 if retry_count >= MAX_RETRIES {
-                    eprintln!("Max retries reached. Error: {:?} {:?}", e, addresses);
-                    return Ok(Vec::new());
+                    return Err(e.context(format!("max retries reached for {:?}", addresses)));
+                }
+                if !config.retry_budget.try_spend() {
+                    return Err(anyhow::Error::new(PoolSyncError::RetryBudgetExceeded)
+                        .context(format!("last error for {:?}: {:#}", addresses, e)));
                 }
 
                 let jitter = rand::thread_rng().gen_range(0..=100);
@@ -61,32 +274,126 @@ async fn populate_pool_data<P, T, N>(
     provider: &Arc<P>,
     pool_addresses: Vec<Address>,
     pool_type: PoolType,
-    data: DynSolType,
-    _chain: Chain
+    fetcher: &dyn PoolFetcher,
+    _chain: Chain,
+    config: &PopulationConfig,
 ) -> Result<Vec<Pool>>
 where
     P: Provider<T, N> + Sync + 'static,
     T: Transport + Sync + Clone,
     N: Network,
 {
-    let pool_data = match pool_type {
+    let block = config.block_tag();
+    let family = fetcher.family();
+    let mut pools = match config.strategy {
+        PopulationStrategy::DataSync => {
+            let read = read_pools_data_sync(
+                provider,
+                pool_addresses.clone(),
+                pool_type,
+                fetcher,
+                block,
+                config.debug_raw,
+            );
+            match read.await {
+                Err(e)
+                    if is_data_sync_unsupported(&e)
+                        && config.on_data_sync_revert == DataSyncRevertPolicy::FallbackToRpcBatch =>
+                {
+                    warn!("{:#}, reading the batch with rpc calls instead", e);
+                    read_pools_rpc_batch(provider, pool_addresses, pool_type, family, block)
+                        .await?
+                }
+                read => read?,
+            }
+        }
+        PopulationStrategy::RpcBatch => {
+            read_pools_rpc_batch(provider, pool_addresses, pool_type, family, block).await?
+        }
+    };
+
+    if config.protocol_fee && family == PoolFamily::V3 {
+        populate_protocol_fees(provider, &mut pools, block).await?;
+    }
+
+    // Fill in missing token names and symbols
+    populate_token_metadata(
+        provider,
+        &mut pools,
+        config.token_concurrency,
+        config.token_name_attempts,
+        config.total_supply,
+        block,
+        &config.known_tokens,
+        &config.rate,
+    )
+    .await;
+
+    Ok(pools)
+}
+
+// Read a batch of pools with a single deploy-and-call of the data-sync contract
+async fn read_pools_data_sync<P, T, N>(
+    provider: &Arc<P>,
+    pool_addresses: Vec<Address>,
+    pool_type: PoolType,
+    fetcher: &dyn PoolFetcher,
+    block: BlockNumberOrTag,
+    debug_raw: bool,
+) -> Result<Vec<Pool>>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let family = fetcher.family();
+    let deployed = match family {
         // V3-style pools (Uniswap V3, Agni)
-        PoolType::UniswapV3 | PoolType::Agni => {
-            V3DataSync::deploy_builder(provider.clone(), pool_addresses.to_vec()).await?
+        PoolFamily::V3 => {
+            V3DataSync::deploy_builder(provider.clone(), pool_addresses.to_vec())
+                .block(block.into())
+                .call()
+                .await
         }
         // V2-style pools (MerchantMoe)
-        PoolType::MerchantMoe => {
-            V2DataSync::deploy_builder(provider.clone(), pool_addresses.to_vec()).await?
+        PoolFamily::V2 => {
+            V2DataSync::deploy_builder(provider.clone(), pool_addresses.to_vec())
+                .block(block.into())
+                .call()
+                .await
         }
+        PoolFamily::Other => return Err(PoolSyncError::UnsupportedPoolType.into()),
+    };
+    let pool_data = match deployed {
+        Err(alloy::contract::Error::TransportError(e)) if is_revert(&e) => {
+            return Err(anyhow::Error::new(PoolSyncError::DataSyncUnsupported(
+                pool_type,
+                e.to_string(),
+            )))
+        }
+        deployed => deployed?,
     };
 
-    let decoded_data = data.abi_decode_sequence(&pool_data)?;
+    let decoded_data = fetcher.get_pool_repr().abi_decode_sequence(&pool_data)?;
     let mut pools = Vec::new();
 
     if let Some(pool_data_arr) = decoded_data.as_array() {
         for pool_data_tuple in pool_data_arr {
             if let Some(pool_data) = pool_data_tuple.as_tuple() {
-                let pool = pool_type.build_pool(pool_data);
+                let Some(mut pool) = family.build_pool(pool_type, pool_data) else {
+                    continue;
+                };
+                if let Some(extra) = fetcher.decode_extra(pool_data) {
+                    pool.set_extra(
+                        extra
+                            .iter()
+                            .map(|(key, value)| (key.clone(), sol_value_to_string(value)))
+                            .collect(),
+                    );
+                }
+                if debug_raw {
+                    pool.set_debug_raw(pool_data.iter().map(sol_value_to_string).collect());
+                }
                 if pool.is_valid() {
                     pools.push(pool);
                 }
@@ -94,18 +401,549 @@ where
         }
     }
 
-    // Fill in missing token names and symbols
-    for pool in &mut pools {
-        let token0_contract = ERC20::new(pool.token0_address(), &provider);
-        if let Ok(ERC20::symbolReturn { _0: name }) = token0_contract.symbol().call().await {
-            Pool::update_token0_name(pool, name);
+    Ok(pools)
+}
+
+// Queue an `eth_call` of `input` on `to` at `block` into the batch
+fn add_eth_call<T>(
+    batch: &mut BatchRequest<'_, T>,
+    to: Address,
+    input: Vec<u8>,
+    block: BlockNumberOrTag,
+) -> Result<Waiter<Bytes>>
+where
+    T: Transport + Clone,
+{
+    let request = json!({ "to": to, "input": Bytes::from(input) });
+    Ok(batch.add_call("eth_call", &(request, block))?)
+}
+
+// Read a batch of pools through batched calls of their own view functions. The first
+// batch reads the pool state and the second the decimals of every distinct token.
+// Pools whose state calls fail are skipped, tokens whose decimals fail are marked unknown
+async fn read_pools_rpc_batch<P, T, N>(
+    provider: &Arc<P>,
+    pool_addresses: Vec<Address>,
+    pool_type: PoolType,
+    family: PoolFamily,
+    block: BlockNumberOrTag,
+) -> Result<Vec<Pool>>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let calls: Vec<Vec<u8>> = if family == PoolFamily::V3 {
+        vec![
+            V3PoolState::token0Call {}.abi_encode(),
+            V3PoolState::token1Call {}.abi_encode(),
+            V3PoolState::slot0Call {}.abi_encode(),
+            V3PoolState::liquidityCall {}.abi_encode(),
+            V3PoolState::tickSpacingCall {}.abi_encode(),
+            V3PoolState::feeCall {}.abi_encode(),
+        ]
+    } else {
+        vec![
+            V2PairState::token0Call {}.abi_encode(),
+            V2PairState::token1Call {}.abi_encode(),
+            V2PairState::getReservesCall {}.abi_encode(),
+        ]
+    };
+
+    let mut batch = BatchRequest::new(provider.client());
+    let mut state_waiters = Vec::with_capacity(pool_addresses.len());
+    for address in &pool_addresses {
+        let waiters = calls
+            .iter()
+            .map(|input| add_eth_call(&mut batch, *address, input.clone(), block))
+            .collect::<Result<Vec<_>>>()?;
+        state_waiters.push((*address, waiters));
+    }
+    batch.send().await?;
+
+    let mut states = Vec::with_capacity(state_waiters.len());
+    for (address, waiters) in state_waiters {
+        let mut returns = Vec::with_capacity(waiters.len());
+        for waiter in waiters {
+            returns.push(waiter.await.ok());
         }
+        if let Some(returns) = returns.into_iter().collect::<Option<Vec<Bytes>>>() {
+            states.push((address, returns));
+        }
+    }
 
-        let token1_contract = ERC20::new(pool.token1_address(), &provider);
-        if let Ok(ERC20::symbolReturn { _0: name }) = token1_contract.symbol().call().await {
-            Pool::update_token1_name(pool, name);
+    // resolve the decimals of every token seen in the state batch
+    let tokens: HashSet<Address> = states
+        .iter()
+        .flat_map(|(_, returns)| {
+            returns[..2]
+                .iter()
+                .filter_map(|ret| Address::abi_decode(ret, false).ok())
+        })
+        .collect();
+    let mut batch = BatchRequest::new(provider.client());
+    let decimal_waiters = tokens
+        .into_iter()
+        .map(|token| {
+            add_eth_call(&mut batch, token, ERC20::decimalsCall {}.abi_encode(), block)
+                .map(|waiter| (token, waiter))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    batch.send().await?;
+
+    let mut decimals = HashMap::new();
+    for (token, waiter) in decimal_waiters {
+        if let Ok(ret) = waiter.await {
+            if let Ok(ERC20::decimalsReturn { _0 }) =
+                ERC20::decimalsCall::abi_decode_returns(&ret, false)
+            {
+                decimals.insert(token, _0);
+            }
         }
     }
 
+    let mut pools = Vec::with_capacity(states.len());
+    for (address, returns) in states {
+        let pool = if family == PoolFamily::V3 {
+            decode_v3_state(address, &returns, &decimals).map(|pool| Pool::new_v3(pool_type, pool))
+        } else {
+            decode_v2_state(address, &returns, &decimals).map(|pool| Pool::new_v2(pool_type, pool))
+        };
+        if let Some(pool) = pool.filter(|pool| pool.is_valid()) {
+            pools.push(pool);
+        }
+    }
     Ok(pools)
-}
\ No newline at end of file
+}
+
+fn decode_v3_state(
+    address: Address,
+    returns: &[Bytes],
+    decimals: &HashMap<Address, u8>,
+) -> Option<UniswapV3Pool> {
+    let token0 = V3PoolState::token0Call::abi_decode_returns(&returns[0], false).ok()?._0;
+    let token1 = V3PoolState::token1Call::abi_decode_returns(&returns[1], false).ok()?._0;
+    let slot0 = V3PoolState::slot0Call::abi_decode_returns(&returns[2], false).ok()?;
+    let liquidity = V3PoolState::liquidityCall::abi_decode_returns(&returns[3], false).ok()?._0;
+    let tick_spacing =
+        V3PoolState::tickSpacingCall::abi_decode_returns(&returns[4], false).ok()?._0;
+    let fee = V3PoolState::feeCall::abi_decode_returns(&returns[5], false).ok()?._0;
+
+    Some(UniswapV3Pool {
+        address,
+        token0,
+        token1,
+        token0_decimals: decimals.get(&token0).copied(),
+        token1_decimals: decimals.get(&token1).copied(),
+        liquidity,
+        sqrt_price: U256::from(slot0.sqrtPriceX96),
+        tick: slot0.tick.as_i32(),
+        tick_spacing: tick_spacing.as_i32(),
+        fee: fee.to(),
+        ..Default::default()
+    })
+}
+
+fn decode_v2_state(
+    address: Address,
+    returns: &[Bytes],
+    decimals: &HashMap<Address, u8>,
+) -> Option<MerchantMoeV2Pool> {
+    let token0 = V2PairState::token0Call::abi_decode_returns(&returns[0], false).ok()?._0;
+    let token1 = V2PairState::token1Call::abi_decode_returns(&returns[1], false).ok()?._0;
+    let reserves = V2PairState::getReservesCall::abi_decode_returns(&returns[2], false).ok()?;
+
+    Some(MerchantMoeV2Pool {
+        address,
+        token0,
+        token1,
+        token0_decimals: decimals.get(&token0).copied(),
+        token1_decimals: decimals.get(&token1).copied(),
+        token0_reserves: U256::from(reserves.reserve0),
+        token1_reserves: U256::from(reserves.reserve1),
+        ..Default::default()
+    })
+}
+
+// Read the `feeProtocol` of every V3 pool from its full slot0 in a single batch,
+// pools whose call fails keep a protocol fee of zero
+async fn populate_protocol_fees<P, T, N>(
+    provider: &Arc<P>,
+    pools: &mut [Pool],
+    block: BlockNumberOrTag,
+) -> Result<()>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let mut batch = BatchRequest::new(provider.client());
+    let waiters = pools
+        .iter()
+        .map(|pool| {
+            add_eth_call(&mut batch, pool.address(), V3PoolSlot0::slot0Call {}.abi_encode(), block)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    batch.send().await?;
+
+    for (pool, waiter) in pools.iter_mut().zip(waiters) {
+        let Ok(ret) = waiter.await else { continue };
+        if let (Some(pool), Ok(slot0)) = (
+            pool.get_v3_mut(),
+            V3PoolSlot0::slot0Call::abi_decode_returns(&ret, false),
+        ) {
+            pool.protocol_fee = slot0.feeProtocol;
+        }
+    }
+    Ok(())
+}
+
+/// Number of pools whose price state is read in one batch request by `refresh_pool_prices`
+const REFRESH_BATCH_SIZE: usize = 200;
+
+// Read the current price state of every pool at `block` in batches of their own view
+// functions, slot0 and liquidity for V3 and the reserves for V2. Nothing else is touched,
+// pools whose calls fail keep their previous state and are logged
+pub(crate) async fn refresh_pool_prices<P, T, N>(
+    provider: &Arc<P>,
+    pools: &mut [Pool],
+    block: u64,
+) -> Result<()>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let tag = BlockNumberOrTag::Number(block);
+    let mut stale = 0;
+    for chunk in pools.chunks_mut(REFRESH_BATCH_SIZE) {
+        let mut batch = BatchRequest::new(provider.client());
+        let waiters = chunk
+            .iter()
+            .map(|pool| {
+                let calls = if pool.is_v3() {
+                    vec![
+                        V3PoolState::slot0Call {}.abi_encode(),
+                        V3PoolState::liquidityCall {}.abi_encode(),
+                    ]
+                } else {
+                    vec![V2PairState::getReservesCall {}.abi_encode()]
+                };
+                calls
+                    .into_iter()
+                    .map(|input| add_eth_call(&mut batch, pool.address(), input, tag))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        batch.send().await?;
+
+        for (pool, waiters) in chunk.iter_mut().zip(waiters) {
+            let mut returns = Vec::with_capacity(waiters.len());
+            for waiter in waiters {
+                returns.push(waiter.await.ok());
+            }
+            let refreshed = returns
+                .into_iter()
+                .collect::<Option<Vec<Bytes>>>()
+                .and_then(|returns| apply_price_state(pool, &returns, block));
+            if refreshed.is_none() {
+                stale += 1;
+            }
+        }
+    }
+    if stale > 0 {
+        warn!("{} pools kept their cached prices, their state could not be read", stale);
+    }
+    Ok(())
+}
+
+fn apply_price_state(pool: &mut Pool, returns: &[Bytes], block: u64) -> Option<()> {
+    match pool {
+        Pool::UniswapV3(pool) | Pool::Agni(pool) => {
+            let slot0 = V3PoolState::slot0Call::abi_decode_returns(&returns[0], false).ok()?;
+            let liquidity =
+                V3PoolState::liquidityCall::abi_decode_returns(&returns[1], false).ok()?._0;
+            pool.sqrt_price = U256::from(slot0.sqrtPriceX96);
+            pool.tick = slot0.tick.as_i32();
+            pool.liquidity = liquidity;
+            pool.last_update_block = block;
+        }
+        Pool::MerchantMoe(pool) => {
+            let reserves =
+                V2PairState::getReservesCall::abi_decode_returns(&returns[0], false).ok()?;
+            pool.token0_reserves = U256::from(reserves.reserve0);
+            pool.token1_reserves = U256::from(reserves.reserve1);
+            pool.last_update_block = block;
+        }
+    }
+    Some(())
+}
+
+/// Name given to tokens that answer neither `symbol()` nor `name()` with a usable value
+pub const UNKNOWN_TOKEN_NAME: &str = "UNKNOWN";
+
+/// Fetch the name of every distinct token in the pools, resolving up to `concurrency`
+/// tokens at once, and when `total_supply` is set its `totalSupply()` at `block` in the same
+/// task, so the supplies match the pool state read there. Tokens in `known` take their name
+/// and any missing decimals from it instead.
+/// See `resolve_token_name` for the calls tried per token
+#[allow(clippy::too_many_arguments)]
+pub async fn populate_token_metadata<P, T, N>(
+    provider: &Arc<P>,
+    pools: &mut [Pool],
+    concurrency: usize,
+    attempts: u32,
+    total_supply: bool,
+    block: BlockNumberOrTag,
+    known: &TokenCache,
+    rate: &AdaptiveRate,
+) where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let tokens: HashSet<Address> = pools
+        .iter()
+        .flat_map(|pool| [pool.token0_address(), pool.token1_address()])
+        .collect();
+
+    let metadata: HashMap<Address, (Option<String>, Option<U256>)> =
+        futures::stream::iter(tokens.into_iter().map(|token| {
+            let provider = provider.clone();
+            let known_name = known.get(&token).map(|metadata| metadata.name.clone());
+            async move {
+                let name = match known_name {
+                    Some(name) => Some(name),
+                    None => resolve_token_name(&provider, token, attempts, rate).await,
+                };
+                let supply = match total_supply {
+                    true => resolve_total_supply(&provider, token, block, attempts, rate).await,
+                    false => None,
+                };
+                (token, (name, supply))
+            }
+        }))
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    for pool in pools {
+        let (name0, supply0) = metadata[&pool.token0_address()].clone();
+        let (name1, supply1) = metadata[&pool.token1_address()].clone();
+        if let Some(name) = name0 {
+            Pool::update_token0_name(pool, name);
+        }
+        if let Some(name) = name1 {
+            Pool::update_token1_name(pool, name);
+        }
+        if total_supply {
+            pool.set_total_supplies(supply0, supply1);
+        }
+        let decimals = |token| known.get(&token).and_then(|metadata| metadata.decimals);
+        pool.fill_missing_decimals(
+            decimals(pool.token0_address()),
+            decimals(pool.token1_address()),
+        );
+    }
+}
+
+/// The tokens that do not answer all of `decimals()`, `symbol()` and `totalSupply()` with a
+/// decodable value, e.g. NFTs or other contracts paired in a pool. Calls are made up to
+/// `attempts` times while the rpc fails, tokens the rpc never answers for are not reported
+pub async fn non_erc20_tokens<P, T, N>(
+    provider: &Arc<P>,
+    tokens: HashSet<Address>,
+    concurrency: usize,
+    attempts: u32,
+    rate: &AdaptiveRate,
+) -> HashSet<Address>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    futures::stream::iter(tokens.into_iter().map(|token| async move {
+        let call = |input| call_token(provider, token, input, BlockId::latest(), attempts, rate);
+        let decimals = call(ERC20::decimalsCall {}.abi_encode());
+        let symbol = call(ERC20::symbolCall {}.abi_encode());
+        let supply = call(ERC20::totalSupplyCall {}.abi_encode());
+        let compliant = [
+            decimals
+                .await
+                .decoded(|ret| ERC20::decimalsCall::abi_decode_returns(ret, true).is_ok()),
+            symbol.await.decoded(|ret| decode_token_name(ret).is_some()),
+            supply.await.decoded(|ret| U256::abi_decode(ret, true).is_ok()),
+        ];
+        compliant.contains(&Some(false)).then_some(token)
+    }))
+    .buffer_unordered(concurrency.max(1))
+    .filter_map(|token| async move { token })
+    .collect()
+    .await
+}
+
+/// `decimals()` of a token, `None` when it reverts, returns something else than a `u8` or
+/// the rpc never answers
+pub async fn resolve_token_decimals<P, T, N>(
+    provider: &Arc<P>,
+    token: Address,
+    attempts: u32,
+    rate: &AdaptiveRate,
+) -> Option<u8>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let input = ERC20::decimalsCall {}.abi_encode();
+    match call_token(provider, token, input, BlockId::latest(), attempts, rate).await {
+        TokenCall::Returned(ret) => ERC20::decimalsCall::abi_decode_returns(&ret, true)
+            .ok()
+            .map(|ret| ret._0),
+        TokenCall::Reverted | TokenCall::Failed => None,
+    }
+}
+
+// `totalSupply()` of a token at `block`, `None` when it reverts or the rpc never answers
+async fn resolve_total_supply<P, T, N>(
+    provider: &Arc<P>,
+    token: Address,
+    block: BlockNumberOrTag,
+    attempts: u32,
+    rate: &AdaptiveRate,
+) -> Option<U256>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let input = ERC20::totalSupplyCall {}.abi_encode();
+    match call_token(provider, token, input, block.into(), attempts, rate).await {
+        TokenCall::Returned(ret) => U256::abi_decode(&ret, true).ok(),
+        TokenCall::Reverted | TokenCall::Failed => None,
+    }
+}
+
+/// Resolves a token's display name, trying `symbol()` as a string, `symbol()` as a
+/// bytes32, `name()` as a string and finally `UNKNOWN_TOKEN_NAME`. Each call is made up to
+/// `attempts` times while the rpc fails, `None` when it never answers
+pub async fn resolve_token_name<P, T, N>(
+    provider: &Arc<P>,
+    token: Address,
+    attempts: u32,
+    rate: &AdaptiveRate,
+) -> Option<String>
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let calls = [
+        ERC20::symbolCall {}.abi_encode(),
+        ERC20::nameCall {}.abi_encode(),
+    ];
+    for input in calls {
+        match call_token(provider, token, input, BlockId::latest(), attempts, rate).await {
+            TokenCall::Returned(ret) => {
+                if let Some(name) = decode_token_name(&ret) {
+                    return Some(name);
+                }
+            }
+            TokenCall::Reverted => {}
+            TokenCall::Failed => return None,
+        }
+    }
+    Some(UNKNOWN_TOKEN_NAME.to_string())
+}
+
+enum TokenCall {
+    Returned(Bytes),
+    Reverted,
+    Failed,
+}
+
+impl TokenCall {
+    // Whether the call returned a value `decode` accepts, `None` when the rpc never answered
+    fn decoded(self, decode: impl FnOnce(&[u8]) -> bool) -> Option<bool> {
+        match self {
+            TokenCall::Returned(ret) => Some(decode(&ret)),
+            TokenCall::Reverted => Some(false),
+            TokenCall::Failed => None,
+        }
+    }
+}
+
+// Call the token at `block`, retrying with the same backoff as the data-sync calls unless it
+// reverts. Every attempt waits for a slot of the rate, and a 429 response lowers it
+async fn call_token<P, T, N>(
+    provider: &Arc<P>,
+    token: Address,
+    input: Vec<u8>,
+    block: BlockId,
+    attempts: u32,
+    rate: &AdaptiveRate,
+) -> TokenCall
+where
+    P: Provider<T, N> + Sync + 'static,
+    T: Transport + Sync + Clone,
+    N: Network,
+{
+    let tx = N::TransactionRequest::default()
+        .with_to(token)
+        .with_input(input);
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=attempts.max(1) {
+        rate.tick().await;
+        let called = provider.call(&tx).block(block).await;
+        if let Err(e) = &called {
+            rate.record(e);
+        }
+        match called {
+            Ok(ret) => return TokenCall::Returned(ret),
+            Err(e) if is_revert(&e) => return TokenCall::Reverted,
+            Err(_) if attempt < attempts => {
+                let jitter = rand::thread_rng().gen_range(0..=100);
+                tokio::time::sleep(Duration::from_millis(backoff + jitter)).await;
+                backoff *= 2; // Exponential backoff
+            }
+            Err(_) => {}
+        }
+    }
+    TokenCall::Failed
+}
+
+/// Whether an error was caused by the data-sync contract deploy reverting
+pub(crate) fn is_data_sync_unsupported(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<PoolSyncError>(),
+        Some(PoolSyncError::DataSyncUnsupported(..))
+    )
+}
+
+// The rpc error a read failed with, when the node was reached
+fn transport_error(error: &anyhow::Error) -> Option<&TransportError> {
+    match error.downcast_ref::<alloy::contract::Error>() {
+        Some(alloy::contract::Error::TransportError(e)) => Some(e),
+        _ => error.downcast_ref::<TransportError>(),
+    }
+}
+
+fn is_revert(error: &TransportError) -> bool {
+    error.as_error_resp().is_some_and(|payload| {
+        payload.code == 3 || payload.message.to_lowercase().contains("revert")
+    })
+}
+
+// Decode a `string` or `bytes32` return value, ignoring empty names
+fn decode_token_name(ret: &[u8]) -> Option<String> {
+    let name = match String::abi_decode(ret, true) {
+        Ok(name) => name,
+        Err(_) if ret.len() == 32 => {
+            let end = ret.iter().position(|byte| *byte == 0).unwrap_or(32);
+            String::from_utf8(ret[..end].to_vec()).ok()?
+        }
+        Err(_) => return None,
+    };
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
@@ -16,7 +16,8 @@ impl PoolFetcher for AgniV3Fetcher {
 
     fn factory_address(&self, chain: Chain) -> Address {
         match chain {
-            Chain::Mantle => address!("25780dc8Fc3cfBD75F33bFDAB65e969b603b2035 "), // Agni V3 Factory on Mantle
+            Chain::Mantle => address!("25780dc8Fc3cfBD75F33bFDAB65e969b603b2035"), // Agni V3 Factory on Mantle
+            Chain::Local { .. } => chain.local_factory(&PoolType::Agni).unwrap_or_default(),
         }
     }
 
@@ -29,6 +30,11 @@ impl PoolFetcher for AgniV3Fetcher {
         decoded_log.data.pool
     }
 
+    fn log_to_tokens(&self, log: &Log) -> Option<(Address, Address)> {
+        let decoded_log = AgniV3Factory::PoolCreated::decode_log(log, false).ok()?;
+        Some((decoded_log.data.token0, decoded_log.data.token1))
+    }
+
     fn get_pool_repr(&self) -> DynSolType {
         DynSolType::Array(Box::new(DynSolType::Tuple(vec![
             DynSolType::Address,     // pool address
@@ -3,6 +3,7 @@ use alloy::sol_types::SolEvent;
 use alloy::primitives::Log;
 use alloy::dyn_abi::DynSolType;
 use crate::pools::PoolFetcher;
+use crate::events::DataEvents;
 use crate::pools::gen::MerchantMoeV2Factory;
 use crate::pools::PoolType;
 use crate::Chain;
@@ -15,7 +16,8 @@ impl PoolFetcher for MerchantMoeV2Fetcher {
 
     fn factory_address(&self, chain: Chain) -> Address {
         match chain {
-            Chain::Mantle => address!("5bEf015CA9424A7C07B68490616a4C1F094BEdEc "),
+            Chain::Mantle => address!("5bEf015CA9424A7C07B68490616a4C1F094BEdEc"),
+            Chain::Local { .. } => chain.local_factory(&PoolType::MerchantMoe).unwrap_or_default(),
         }
     }
 
@@ -29,6 +31,11 @@ impl PoolFetcher for MerchantMoeV2Fetcher {
         
     }
 
+    fn log_to_tokens(&self, log: &Log) -> Option<(Address, Address)> {
+        let decoded_log = MerchantMoeV2Factory::PairCreated::decode_log(log, false).ok()?;
+        Some((decoded_log.data.token0, decoded_log.data.token1))
+    }
+
     fn get_pool_repr(&self) -> DynSolType {
         DynSolType::Array(Box::new(DynSolType::Tuple(vec![
             DynSolType::Address,
@@ -41,5 +48,7 @@ impl PoolFetcher for MerchantMoeV2Fetcher {
         ])))
     }
 
-
-}
\ No newline at end of file
+    fn data_event_signatures(&self) -> Vec<&str> {
+        vec![DataEvents::Sync::SIGNATURE]
+    }
+}
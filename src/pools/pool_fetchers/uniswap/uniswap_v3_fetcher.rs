@@ -16,6 +16,7 @@ impl PoolFetcher for UniswapV3Fetcher {
     fn factory_address(&self, chain: Chain) -> Address {
         match chain {
             Chain::Mantle => address!("0d922Fb1Bc191F64970ac40376643808b4B74Df9"),
+            Chain::Local { .. } => chain.local_factory(&PoolType::UniswapV3).unwrap_or_default(),
         }
     }
 
@@ -29,6 +30,11 @@ impl PoolFetcher for UniswapV3Fetcher {
         
     }
 
+    fn log_to_tokens(&self, log: &Log) -> Option<(Address, Address)> {
+        let decoded_log = UniswapV3Factory::PoolCreated::decode_log(log, false).ok()?;
+        Some((decoded_log.data.token0, decoded_log.data.token1))
+    }
+
     fn get_pool_repr(&self) -> DynSolType {
         DynSolType::Array(Box::new(DynSolType::Tuple(vec![
             DynSolType::Address,
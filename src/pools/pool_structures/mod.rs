@@ -1,2 +1,3 @@
+pub mod tick_bitmap_codec;
 pub mod v3_structure;
 pub mod v2_structure;
\ No newline at end of file
@@ -0,0 +1,48 @@
+//! Compact encoding of a V3 pool's tick bitmap
+//!
+//! Flipping ticks off leaves zero words behind in the bitmap, so only the non-zero words are
+//! kept, each as its big endian `i16` word position followed by the 32 byte word, ordered by
+//! position. The cache files store the encoding as a single hex string and still read the
+//! plain map of every word written by earlier versions, everywhere else the bitmap keeps its
+//! plain map.
+
+use alloy::primitives::U256;
+use std::collections::HashMap;
+
+use crate::errors::PoolSyncError;
+
+/// Size of one encoded word, the position followed by the word itself
+const ENTRY_LEN: usize = 2 + 32;
+
+/// Encodes the non-zero words of the bitmap
+pub fn encode(bitmap: &HashMap<i16, U256>) -> Vec<u8> {
+    let mut words: Vec<(&i16, &U256)> = bitmap.iter().filter(|(_, word)| !word.is_zero()).collect();
+    words.sort_by_key(|(position, _)| **position);
+
+    let mut encoded = Vec::with_capacity(words.len() * ENTRY_LEN);
+    for (position, word) in words {
+        encoded.extend_from_slice(&position.to_be_bytes());
+        encoded.extend_from_slice(&word.to_be_bytes::<32>());
+    }
+    encoded
+}
+
+/// Rebuilds a bitmap from the output of `encode`
+// `usize::is_multiple_of` needs Rust 1.87, the remainder works on older toolchains
+#[allow(clippy::manual_is_multiple_of)]
+pub fn decode(encoded: &[u8]) -> Result<HashMap<i16, U256>, PoolSyncError> {
+    if encoded.len() % ENTRY_LEN != 0 {
+        return Err(PoolSyncError::CacheError(format!(
+            "tick bitmap of {} bytes is not a whole number of {} byte words",
+            encoded.len(),
+            ENTRY_LEN
+        )));
+    }
+    Ok(encoded
+        .chunks_exact(ENTRY_LEN)
+        .map(|entry| {
+            let position = i16::from_be_bytes([entry[0], entry[1]]);
+            (position, U256::from_be_slice(&entry[2..]))
+        })
+        .collect())
+}
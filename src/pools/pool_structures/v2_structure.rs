@@ -1,11 +1,19 @@
 
 use crate::events::{DataEvents};
-use crate::pools::PoolType;
+use crate::errors::PoolSyncError;
+use crate::pools::{apply_slippage, price_impact_percent, swap_direction, PoolType};
 use alloy::dyn_abi::DynSolValue;
 use alloy::primitives::{Address, U256};
 use alloy::rpc::types::Log;
 use alloy::sol_types::SolEvent;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Fraction of the input amount every MerchantMoe swap pays in fees
+pub const V2_SWAP_FEE_FRACTION: f64 = 0.003;
+
+/// The MerchantMoe swap fee in hundredths of a basis point, the unit of the V3 fee tiers
+pub const V2_SWAP_FEE: u32 = 3000;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct MerchantMoeV2Pool {
@@ -14,27 +22,174 @@ pub struct MerchantMoeV2Pool {
     pub token1: Address,
     pub token0_name: String,
     pub token1_name: String,
-    pub token0_decimals: u8,
-    pub token1_decimals: u8,
+    /// `None` when the data-sync contract returned an out of range value
+    pub token0_decimals: Option<u8>,
+    /// `None` when the data-sync contract returned an out of range value
+    pub token1_decimals: Option<u8>,
     pub token0_reserves: U256,
     pub token1_reserves: U256, 
+    /// Block of the last processed Sync event affecting this pool
+    #[serde(default)]
+    pub last_update_block: u64,
+    /// Position of this pool in its factory's creation order, counted from the first synced block
+    #[serde(default)]
+    pub creation_index: u64,
+    /// Block the factory created this pool in, `None` for pools cached before it was tracked
+    #[serde(default)]
+    pub created_at_block: Option<u64>,
+    /// Sender of the transaction that created this pool, `None` unless creators were fetched
+    #[serde(default)]
+    pub creator: Option<Address>,
+    /// `totalSupply()` of token0, `None` unless total supplies were fetched
+    #[serde(default)]
+    pub token0_total_supply: Option<U256>,
+    /// `totalSupply()` of token1, `None` unless total supplies were fetched
+    #[serde(default)]
+    pub token1_total_supply: Option<U256>,
+    /// Protocol specific values captured by `PoolFetcher::decode_extra`
+    #[serde(default)]
+    pub extra: HashMap<String, String>,
+    /// The data-sync tuple the pool was decoded from, one formatted value per field. Only
+    /// set when population ran with `debug_raw_tuples`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_raw: Option<Vec<String>>,
+}
+
+impl MerchantMoeV2Pool {
+    /// Spot price of token0 denominated in token1, adjusted for decimals.
+    /// Returns `None` when either token's decimals are unknown or a reserve is empty
+    pub fn price_token0_in_token1(&self) -> Option<f64> {
+        let (decimals0, decimals1) = (self.token0_decimals?, self.token1_decimals?);
+        if self.token0_reserves.is_zero() || self.token1_reserves.is_zero() {
+            return None;
+        }
+        let ratio = f64::from(self.token1_reserves) / f64::from(self.token0_reserves);
+        Some(ratio * 10f64.powi(decimals0 as i32 - decimals1 as i32))
+    }
+
+    /// Spot price of token1 denominated in token0, adjusted for decimals
+    pub fn price_token1_in_token0(&self) -> Option<f64> {
+        self.price_token0_in_token1().map(|price| 1.0 / price)
+    }
+
+    /// Price of `token_in` denominated in the other token received for `amount_in` of it,
+    /// adjusted for decimals. Follows the constant product curve after the 0.3% swap fee, so
+    /// it approaches the spot price less the fee for small trades. `None` for a zero
+    /// `amount_in`, a token the pair does not trade, unknown decimals or empty reserves
+    pub fn execution_price(&self, token_in: Address, amount_in: U256) -> Option<f64> {
+        let zero_for_one = swap_direction(self.address, (self.token0, self.token1), token_in).ok()?;
+        let (decimals0, decimals1) = (self.token0_decimals?, self.token1_decimals?);
+        let (reserve_in, reserve_out, decimals_in, decimals_out) = if zero_for_one {
+            (self.token0_reserves, self.token1_reserves, decimals0, decimals1)
+        } else {
+            (self.token1_reserves, self.token0_reserves, decimals1, decimals0)
+        };
+        if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+            return None;
+        }
+        // in floating point, the integer output of simulate_swap rounds away small trades
+        let amount_in = f64::from(amount_in);
+        let amount_in_with_fee = amount_in * (1.0 - V2_SWAP_FEE_FRACTION);
+        let amount_out = amount_in_with_fee * f64::from(reserve_out)
+            / (f64::from(reserve_in) + amount_in_with_fee);
+        Some(amount_out / amount_in * 10f64.powi(decimals_in as i32 - decimals_out as i32))
+    }
+
+    /// The constant product `reserve0 * reserve1` the pair keeps through swaps. Reserves are
+    /// uint112 on chain so the product always fits, `None` only for reserves set beyond that
+    pub fn invariant_k(&self) -> Option<U256> {
+        self.token0_reserves.checked_mul(self.token1_reserves)
+    }
+
+    /// Percentage the execution price of swapping `amount_in` of `token_in` is worse than
+    /// the reserve ratio before the trade, the swap fee included
+    pub fn price_impact(&self, token_in: Address, amount_in: U256) -> Result<f64, PoolSyncError> {
+        let zero_for_one = swap_direction(self.address, (self.token0, self.token1), token_in)?;
+        let amount_out = self.simulate_swap(zero_for_one, amount_in).ok_or_else(|| {
+            PoolSyncError::SwapSimulationError(format!("pool {} has no reserves", self.address))
+        })?;
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (self.token0_reserves, self.token1_reserves)
+        } else {
+            (self.token1_reserves, self.token0_reserves)
+        };
+        price_impact_percent(f64::from(reserve_out) / f64::from(reserve_in), amount_in, amount_out)
+    }
+
+    /// Output amount of an exact input swap against the constant product curve, after
+    /// the 0.3% swap fee. `None` when either reserve is empty
+    pub fn simulate_swap(&self, zero_for_one: bool, amount_in: U256) -> Option<U256> {
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (self.token0_reserves, self.token1_reserves)
+        } else {
+            (self.token1_reserves, self.token0_reserves)
+        };
+        if reserve_in.is_zero() || reserve_out.is_zero() {
+            return None;
+        }
+        let amount_in_with_fee = amount_in * U256::from(997u64);
+        let numerator = amount_in_with_fee * reserve_out;
+        let denominator = reserve_in * U256::from(1000u64) + amount_in_with_fee;
+        Some(numerator / denominator)
+    }
+
+    /// Output of swapping `amount_in` of `token_in` reduced by a slippage tolerance of
+    /// `slippage_bps` basis points, the `amountOutMin` to pass to the router. Fails when the
+    /// pair has no reserves or the tolerance is above 10000
+    pub fn min_amount_out(
+        &self,
+        token_in: Address,
+        amount_in: U256,
+        slippage_bps: u32,
+    ) -> Result<U256, PoolSyncError> {
+        let zero_for_one = swap_direction(self.address, (self.token0, self.token1), token_in)?;
+        let amount_out = self.simulate_swap(zero_for_one, amount_in).ok_or_else(|| {
+            PoolSyncError::SwapSimulationError(format!("pool {} has no reserves", self.address))
+        })?;
+        apply_slippage(amount_out, slippage_bps)
+    }
+
+    /// Fee taken from `amount_in` of `token_in` by the 0.3% swap fee, in `token_in` units
+    pub fn fee_amount_for_swap(&self, token_in: Address, amount_in: U256) -> Result<U256, PoolSyncError> {
+        swap_direction(self.address, (self.token0, self.token1), token_in)?;
+        if self.token0_reserves.is_zero() || self.token1_reserves.is_zero() {
+            return Err(PoolSyncError::SwapSimulationError(format!(
+                "pool {} has no reserves",
+                self.address
+            )));
+        }
+        Ok(amount_in * U256::from(3u64) / U256::from(1000u64))
+    }
 }
 
 pub fn process_sync_data(pool: &mut MerchantMoeV2Pool, log: Log, _pool_type: PoolType) {
+    if log.topic0() != Some(&DataEvents::Sync::SIGNATURE_HASH) {
+        return;
+    }
+    if let Some(block_number) = log.block_number {
+        pool.last_update_block = pool.last_update_block.max(block_number);
+    }
     let sync_event = DataEvents::Sync::decode_log(log.as_ref(), true).unwrap();
     let (reserve0, reserve1) = (U256::from(sync_event.reserve0), U256::from(sync_event.reserve1));
     pool.token0_reserves = reserve0;
     pool.token1_reserves = reserve1;
 }
 
+// Number of fields of a data-sync tuple a V2 pool is read from
+const V2_DATA_FIELDS: usize = 7;
+
 impl From<&[DynSolValue]> for MerchantMoeV2Pool {
     fn from(data: &[DynSolValue]) -> Self {
+        // a tuple missing fields gives an invalid pool that population drops
+        if data.len() < V2_DATA_FIELDS {
+            return Self::default();
+        }
         Self {
             address: data[0].as_address().unwrap(),
             token0: data[1].as_address().unwrap(),
             token1: data[2].as_address().unwrap(),
-            token0_decimals: data[3].as_uint().unwrap().0.to::<u8>(),
-            token1_decimals: data[4].as_uint().unwrap().0.to::<u8>(),
+            token0_decimals: data[3].as_uint().unwrap().0.try_into().ok(),
+            token1_decimals: data[4].as_uint().unwrap().0.try_into().ok(),
             token0_reserves: data[5].as_uint().unwrap().0.to::<U256>(),
             token1_reserves: data[6].as_uint().unwrap().0.to::<U256>(),
             ..Default::default()
@@ -1,12 +1,69 @@
 use alloy::dyn_abi::DynSolValue;
-use alloy::primitives::{Address, U256};
+use alloy::primitives::{Address, I256, U256};
 use alloy::rpc::types::Log;
-use alloy::sol_types::SolEvent;
+use alloy::network::Network;
+use alloy::providers::Provider;
+use alloy::sol_types::{SolCall, SolEvent};
+use alloy::transports::Transport;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use uniswap_v3_math::error::UniswapV3MathError;
+use uniswap_v3_math::tick_math::{MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK};
+use uniswap_v3_math::{
+    full_math, liquidity_math, sqrt_price_math, swap_math, tick_bitmap, tick_math,
+};
 
+use crate::errors::PoolSyncError;
 use crate::events::DataEvents;
-use crate::pools::PoolType;
+use crate::pools::gen::V3PoolState;
+use crate::rpc::AdaptiveRate;
+use crate::pools::{apply_slippage, price_impact_percent, swap_direction, PoolType};
+
+/// A field of the local pool state next to the value read from the pool contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldCheck<T> {
+    pub local: T,
+    pub on_chain: T,
+}
+
+impl<T: PartialEq> FieldCheck<T> {
+    pub fn matches(&self) -> bool {
+        self.local == self.on_chain
+    }
+}
+
+/// Comparison of a synced V3 pool against its contract, from `verify_against_chain`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub sqrt_price: FieldCheck<U256>,
+    pub tick: FieldCheck<i32>,
+    pub liquidity: FieldCheck<u128>,
+    pub tick_spacing: FieldCheck<i32>,
+    pub fee: FieldCheck<u32>,
+}
+
+impl VerifyReport {
+    /// Names of the fields that differ from the chain, empty when the pool is in sync
+    pub fn mismatches(&self) -> Vec<&'static str> {
+        [
+            ("sqrt_price", self.sqrt_price.matches()),
+            ("tick", self.tick.matches()),
+            ("liquidity", self.liquidity.matches()),
+            ("tick_spacing", self.tick_spacing.matches()),
+            ("fee", self.fee.matches()),
+        ]
+        .into_iter()
+        .filter(|(_, matches)| !matches)
+        .map(|(field, _)| field)
+        .collect()
+    }
+
+    /// Whether every field agrees with the chain
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches().is_empty()
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct UniswapV3Pool {
@@ -15,15 +72,616 @@ pub struct UniswapV3Pool {
     pub token1: Address,
     pub token0_name: String,
     pub token1_name: String,
-    pub token0_decimals: u8,
-    pub token1_decimals: u8,
+    /// `None` when the data-sync contract returned an out of range value
+    pub token0_decimals: Option<u8>,
+    /// `None` when the data-sync contract returned an out of range value
+    pub token1_decimals: Option<u8>,
     pub liquidity: u128,
     pub sqrt_price: U256,
     pub fee: u32,
     pub tick: i32,
     pub tick_spacing: i32,
+    /// Stored in the cache files with only its non-zero words, see `tick_bitmap_codec`
     pub tick_bitmap: HashMap<i16, U256>,
     pub ticks: HashMap<i32, TickInfo>,
+    /// Block of the last processed Swap/Mint/Burn event affecting this pool
+    #[serde(default)]
+    pub last_update_block: u64,
+    /// Position of this pool in its factory's creation order, counted from the first synced block
+    #[serde(default)]
+    pub creation_index: u64,
+    /// Block the factory created this pool in, `None` for pools cached before it was tracked
+    #[serde(default)]
+    pub created_at_block: Option<u64>,
+    /// Sender of the transaction that created this pool, `None` unless creators were fetched
+    #[serde(default)]
+    pub creator: Option<Address>,
+    /// Raw `feeProtocol` from slot0, zero unless protocol fees were fetched
+    #[serde(default)]
+    pub protocol_fee: u32,
+    /// Set when ticks far from the current tick were dropped to respect a tick limit
+    #[serde(default)]
+    pub truncated: bool,
+    /// `totalSupply()` of token0, `None` unless total supplies were fetched
+    #[serde(default)]
+    pub token0_total_supply: Option<U256>,
+    /// `totalSupply()` of token1, `None` unless total supplies were fetched
+    #[serde(default)]
+    pub token1_total_supply: Option<U256>,
+    /// Protocol specific values captured by `PoolFetcher::decode_extra`
+    #[serde(default)]
+    pub extra: HashMap<String, String>,
+    /// Fields that could not be decoded from the data-sync result and hold a default
+    /// instead, a bitset of the `FALLBACK_*` flags
+    #[serde(default)]
+    pub decode_fallbacks: u8,
+    /// The data-sync tuple the pool was decoded from, one formatted value per field. Only
+    /// set when population ran with `debug_raw_tuples`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_raw: Option<Vec<String>>,
+}
+
+impl UniswapV3Pool {
+    /// The fee did not fit a u32 and was set to 3000
+    pub const FALLBACK_FEE: u8 = 1;
+    /// The tick did not fit an i32 and was set to 0
+    pub const FALLBACK_TICK: u8 = 1 << 1;
+    /// The tick spacing did not fit an i32 and was set to 0
+    pub const FALLBACK_TICK_SPACING: u8 = 1 << 2;
+    /// The liquidity did not fit a u128 and was set to 0
+    pub const FALLBACK_LIQUIDITY: u8 = 1 << 3;
+
+    /// Whether any field fell back to a default while decoding, such a pool may look
+    /// plausible while its fee, tick, tick spacing or liquidity is wrong. Unknown decimals
+    /// are left as `None` instead, see `token0_decimals`
+    pub fn has_default_fallbacks(&self) -> bool {
+        self.decode_fallbacks != 0
+    }
+
+    /// Spot price of token0 denominated in token1, adjusted for decimals.
+    /// Returns `None` when either token's decimals are unknown or the pool has no price
+    pub fn price_token0_in_token1(&self) -> Option<f64> {
+        let (decimals0, decimals1) = (self.token0_decimals?, self.token1_decimals?);
+        if self.sqrt_price.is_zero() {
+            return None;
+        }
+        let sqrt_price = f64::from(self.sqrt_price) / 2f64.powi(96);
+        Some(sqrt_price * sqrt_price * 10f64.powi(decimals0 as i32 - decimals1 as i32))
+    }
+
+    /// Spot price of token1 denominated in token0, adjusted for decimals
+    pub fn price_token1_in_token0(&self) -> Option<f64> {
+        self.price_token0_in_token1().map(|price| 1.0 / price)
+    }
+
+    /// Price of `token_in` denominated in the other token after the swap fee, adjusted for
+    /// decimals. The fee fraction is taken off the spot price and the trade is assumed too
+    /// small to cross a tick. `None` for a zero `amount_in`, a token the pool does not trade
+    /// or when the spot price cannot be determined
+    pub fn execution_price(&self, token_in: Address, amount_in: U256) -> Option<f64> {
+        let zero_for_one = swap_direction(self.address, (self.token0, self.token1), token_in).ok()?;
+        if amount_in.is_zero() {
+            return None;
+        }
+        let spot = if zero_for_one {
+            self.price_token0_in_token1()?
+        } else {
+            self.price_token1_in_token0()?
+        };
+        Some(spot * (1.0 - f64::from(self.fee) / 1e6))
+    }
+
+    /// Token balances implied by the in-range `liquidity` at the current `sqrt_price`, as
+    /// `(reserve0, reserve1)`. These are the reserves of a V2 pool with the same depth around
+    /// the current price, not the pool's actual token balances. Zero for a pool with no price
+    pub fn virtual_reserves(&self) -> (U256, U256) {
+        if self.sqrt_price.is_zero() {
+            return (U256::ZERO, U256::ZERO);
+        }
+        let liquidity = U256::from(self.liquidity);
+        let q96 = U256::from(1) << 96;
+        // liquidity is at most 128 bits and the sqrt price at least 32, neither result overflows
+        let reserve0 = full_math::mul_div(liquidity, q96, self.sqrt_price)
+            .expect("virtual reserve0 fits in 256 bits");
+        let reserve1 = full_math::mul_div(liquidity, self.sqrt_price, q96)
+            .expect("virtual reserve1 fits in 256 bits");
+        (reserve0, reserve1)
+    }
+
+    /// Sqrt price (Q64.96) at which token0 trades for `price` token1, the inverse of
+    /// `price_token0_in_token1`. Returns `None` when either token's decimals are unknown or
+    /// the price is not positive or outside the range a pool can represent
+    pub fn sqrt_price_for_price(&self, price: f64) -> Option<U256> {
+        let raw_price = self.raw_price(price)?;
+        let sqrt_price = U256::try_from(raw_price.sqrt() * 2f64.powi(96)).ok()?;
+        (MIN_SQRT_RATIO..MAX_SQRT_RATIO)
+            .contains(&sqrt_price)
+            .then_some(sqrt_price)
+    }
+
+    /// Tick nearest to the price of token0 in token1, rounded to the nearest multiple of the
+    /// pool's tick spacing so it can be used as a position bound. Prices beyond the range a
+    /// pool can represent give the outermost usable tick. Returns `None` when either token's
+    /// decimals are unknown or the price is not positive
+    pub fn tick_for_price(&self, price: f64) -> Option<i32> {
+        let raw_price = self.raw_price(price)?;
+        let tick = raw_price.ln() / 1.0001f64.ln();
+        let spacing = self.tick_spacing.max(1);
+        let (min_steps, max_steps) = (MIN_TICK / spacing, MAX_TICK / spacing);
+        let steps = (tick / spacing as f64)
+            .round()
+            .clamp(min_steps as f64, max_steps as f64);
+        Some(steps as i32 * spacing)
+    }
+
+    /// Undoes the decimal adjustment of a price of token0 in token1
+    fn raw_price(&self, price: f64) -> Option<f64> {
+        let (decimals0, decimals1) = (self.token0_decimals?, self.token1_decimals?);
+        if !(price.is_finite() && price > 0.0) {
+            return None;
+        }
+        Some(price / 10f64.powi(decimals0 as i32 - decimals1 as i32))
+    }
+
+    /// The fee left to liquidity providers after the protocol's cut for a swap in the given
+    /// direction. Uniswap takes `fee / n` with `n` packed in 4 bits per token, Agni takes
+    /// `fee * n / 10000` with `n` packed in 16 bits per token
+    pub fn lp_fee(&self, pool_type: PoolType, zero_for_one: bool) -> u32 {
+        let protocol_cut = match pool_type {
+            PoolType::Agni => {
+                let share = if zero_for_one {
+                    self.protocol_fee & 0xffff
+                } else {
+                    self.protocol_fee >> 16
+                };
+                (self.fee as u64 * share as u64 / 10_000) as u32
+            }
+            _ => {
+                let denominator = if zero_for_one {
+                    self.protocol_fee & 0xf
+                } else {
+                    (self.protocol_fee >> 4) & 0xf
+                };
+                self.fee.checked_div(denominator).unwrap_or(0)
+            }
+        };
+        self.fee.saturating_sub(protocol_cut)
+    }
+
+    /// Reads the pool's slot0, liquidity, tick spacing and fee from its contract at `block`
+    /// and reports which of them the local state agrees with. Run it against the block a
+    /// sync ended at to check the replayed state
+    pub async fn verify_against_chain<P, T, N>(
+        &self,
+        provider: &Arc<P>,
+        block: u64,
+    ) -> Result<VerifyReport, PoolSyncError>
+    where
+        P: Provider<T, N> + Sync + 'static,
+        T: Transport + Sync + Clone,
+        N: Network,
+    {
+        self.verify_against_chain_at_rate(provider, block, &AdaptiveRate::default()).await
+    }
+
+    // `verify_against_chain` with each of its reads paced by `rate`
+    pub(crate) async fn verify_against_chain_at_rate<P, T, N>(
+        &self,
+        provider: &Arc<P>,
+        block: u64,
+        rate: &AdaptiveRate,
+    ) -> Result<VerifyReport, PoolSyncError>
+    where
+        P: Provider<T, N> + Sync + 'static,
+        T: Transport + Sync + Clone,
+        N: Network,
+    {
+        let contract = V3PoolState::new(self.address, provider.clone());
+        let read = |error: alloy::contract::Error| self.read_error(error, rate);
+
+        let (sqrt_price, tick, liquidity) = self.read_price_state(provider, block, rate).await?;
+        rate.tick().await;
+        let tick_spacing = contract.tickSpacing().block(block.into()).call().await.map_err(read)?._0;
+        rate.tick().await;
+        let fee = contract.fee().block(block.into()).call().await.map_err(read)?._0;
+
+        Ok(VerifyReport {
+            sqrt_price: FieldCheck {
+                local: self.sqrt_price,
+                on_chain: sqrt_price,
+            },
+            tick: FieldCheck {
+                local: self.tick,
+                on_chain: tick,
+            },
+            liquidity: FieldCheck {
+                local: self.liquidity,
+                on_chain: liquidity,
+            },
+            tick_spacing: FieldCheck {
+                local: self.tick_spacing,
+                on_chain: tick_spacing.as_i32(),
+            },
+            fee: FieldCheck {
+                local: self.fee,
+                on_chain: fee.to(),
+            },
+        })
+    }
+
+    /// Snapshot of the pool at a past `block`, with the price, tick and liquidity read from
+    /// its contract at that block. Everything else, the tick map included, is carried over
+    /// from this pool, so swaps simulated on the snapshot only stay exact while they do not
+    /// cross a tick whose liquidity changed since `block`
+    pub async fn state_at_block<P, T, N>(
+        &self,
+        provider: &Arc<P>,
+        block: u64,
+    ) -> Result<UniswapV3Pool, PoolSyncError>
+    where
+        P: Provider<T, N> + Sync + 'static,
+        T: Transport + Sync + Clone,
+        N: Network,
+    {
+        let (sqrt_price, tick, liquidity) =
+            self.read_price_state(provider, block, &AdaptiveRate::default()).await?;
+        Ok(UniswapV3Pool {
+            sqrt_price,
+            tick,
+            liquidity,
+            last_update_block: block,
+            ..self.clone()
+        })
+    }
+
+    /// Reads the sqrt price, tick and liquidity of the pool's contract at `block`, each
+    /// read paced by `rate`
+    async fn read_price_state<P, T, N>(
+        &self,
+        provider: &Arc<P>,
+        block: u64,
+        rate: &AdaptiveRate,
+    ) -> Result<(U256, i32, u128), PoolSyncError>
+    where
+        P: Provider<T, N> + Sync + 'static,
+        T: Transport + Sync + Clone,
+        N: Network,
+    {
+        let contract = V3PoolState::new(self.address, provider.clone());
+        let read = |error: alloy::contract::Error| self.read_error(error, rate);
+        let decode = |error: alloy::sol_types::Error| {
+            PoolSyncError::ProviderError(format!("failed to decode pool {}: {}", self.address, error))
+        };
+
+        // decoded leniently, the full slot0 carries more fields than the two read here
+        rate.tick().await;
+        let slot0 = contract.slot0().block(block.into()).call_raw().await.map_err(read)?;
+        let slot0 = V3PoolState::slot0Call::abi_decode_returns(&slot0, false).map_err(decode)?;
+        rate.tick().await;
+        let liquidity = contract.liquidity().block(block.into()).call().await.map_err(read)?._0;
+        Ok((U256::from(slot0.sqrtPriceX96), slot0.tick.as_i32(), liquidity))
+    }
+
+    // An HTTP 429 slows `rate` down like it does for the sync's own reads
+    fn read_error(&self, error: alloy::contract::Error, rate: &AdaptiveRate) -> PoolSyncError {
+        if let alloy::contract::Error::TransportError(e) = &error {
+            rate.record(e);
+        }
+        PoolSyncError::ProviderError(format!("failed to read pool {}: {}", self.address, error))
+    }
+
+    /// Percentage the execution price of swapping `amount_in` of `token_in` is worse than
+    /// the spot price before the trade, the swap fee included
+    pub fn price_impact(&self, token_in: Address, amount_in: U256) -> Result<f64, PoolSyncError> {
+        let zero_for_one = swap_direction(self.address, (self.token0, self.token1), token_in)?;
+        let amount_out = self.simulate_swap(zero_for_one, amount_in)?;
+        // raw token1 per raw token0
+        let price = (f64::from(self.sqrt_price) / 2f64.powi(96)).powi(2);
+        let spot = if zero_for_one { price } else { 1.0 / price };
+        price_impact_percent(spot, amount_in, amount_out)
+    }
+
+    /// Output amount of an exact input swap, stepping through the initialized ticks the
+    /// same way the pool contract does. Fails when the local ticks run out of liquidity
+    /// before the input is filled
+    pub fn simulate_swap(&self, zero_for_one: bool, amount_in: U256) -> Result<U256, PoolSyncError> {
+        self.swap_exact_input(zero_for_one, amount_in)
+            .map(|(amount_out, _)| amount_out)
+    }
+
+    /// Output of swapping `amount_in` of `token_in` reduced by a slippage tolerance of
+    /// `slippage_bps` basis points, the `amountOutMinimum` to pass to the router. Fails when
+    /// the swap cannot be simulated or the tolerance is above 10000
+    pub fn min_amount_out(
+        &self,
+        token_in: Address,
+        amount_in: U256,
+        slippage_bps: u32,
+    ) -> Result<U256, PoolSyncError> {
+        let zero_for_one = swap_direction(self.address, (self.token0, self.token1), token_in)?;
+        apply_slippage(self.simulate_swap(zero_for_one, amount_in)?, slippage_bps)
+    }
+
+    /// Fee taken from `amount_in` of `token_in` by the simulated swap, in `token_in` units
+    /// and summed over every tick range the swap crosses
+    pub fn fee_amount_for_swap(&self, token_in: Address, amount_in: U256) -> Result<U256, PoolSyncError> {
+        let zero_for_one = swap_direction(self.address, (self.token0, self.token1), token_in)?;
+        self.swap_exact_input(zero_for_one, amount_in)
+            .map(|(_, fee_paid)| fee_paid)
+    }
+
+    /// Fees a position of `liquidity` between `tick_lower` and `tick_upper` would have earned
+    /// over `swaps`, as `(fees0, fees1)` in raw token units. The pool must be in its state
+    /// before the first swap, e.g. from `state_at_block`, and `swaps` are its Swap logs in
+    /// chain order, logs of other pools or events are skipped.
+    ///
+    /// Each swap moves the price from where the previous one left it to the price in its
+    /// event. Along the part of that move inside the range, the position provides input in
+    /// proportion to its liquidity and earns the fee on it, whatever the rest of the pool's
+    /// liquidity. The protocol's cut of the fee is not deducted
+    pub fn position_fees(
+        &self,
+        swaps: &[Log],
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+    ) -> Result<(U256, U256), PoolSyncError> {
+        let sqrt_lower = tick_math::get_sqrt_ratio_at_tick(tick_lower).map_err(math_error)?;
+        let sqrt_upper = tick_math::get_sqrt_ratio_at_tick(tick_upper).map_err(math_error)?;
+        let fee = U256::from(self.fee);
+        let fee_denominator = U256::from(1_000_000u32) - fee;
+
+        let (mut fees0, mut fees1) = (U256::ZERO, U256::ZERO);
+        let mut sqrt_price = self.sqrt_price;
+        for log in swaps {
+            let is_swap = log.topic0() == Some(&DataEvents::Swap::SIGNATURE_HASH);
+            if log.address() != self.address || !is_swap {
+                continue;
+            }
+            let swap = DataEvents::Swap::decode_log(log.as_ref(), true).map_err(|e| {
+                PoolSyncError::SwapSimulationError(format!("bad swap log of {}: {}", self.address, e))
+            })?;
+            let next_sqrt_price = U256::from(swap.sqrtPriceX96);
+
+            // the part of the move inside the position's range
+            let from = sqrt_price.min(next_sqrt_price).max(sqrt_lower);
+            let to = sqrt_price.max(next_sqrt_price).min(sqrt_upper);
+            if from < to {
+                // a rising price means token1 was swapped in, a falling one token0
+                let (input, fees) = if next_sqrt_price > sqrt_price {
+                    let input = sqrt_price_math::_get_amount_1_delta(from, to, liquidity, false);
+                    (input, &mut fees1)
+                } else {
+                    let input = sqrt_price_math::_get_amount_0_delta(from, to, liquidity, false);
+                    (input, &mut fees0)
+                };
+                *fees += full_math::mul_div(input.map_err(math_error)?, fee, fee_denominator)
+                    .map_err(math_error)?;
+            }
+            sqrt_price = next_sqrt_price;
+        }
+        Ok((fees0, fees1))
+    }
+
+    // Steps an exact input swap through the ticks, returning the output amount and the
+    // total fee paid
+    fn swap_exact_input(&self, zero_for_one: bool, amount_in: U256) -> Result<(U256, U256), PoolSyncError> {
+        let sqrt_price_limit = if zero_for_one {
+            MIN_SQRT_RATIO + U256::from(1u64)
+        } else {
+            MAX_SQRT_RATIO - U256::from(1u64)
+        };
+
+        let mut amount_remaining = I256::from_raw(amount_in);
+        let mut amount_out = U256::ZERO;
+        let mut fee_paid = U256::ZERO;
+        let mut sqrt_price = self.sqrt_price;
+        let mut tick = self.tick;
+        let mut liquidity = self.liquidity;
+
+        while !amount_remaining.is_zero() && sqrt_price != sqrt_price_limit {
+            let next_tick = self.next_initialized_tick(tick, zero_for_one);
+            let tick_next = next_tick
+                .unwrap_or(if zero_for_one { MIN_TICK } else { MAX_TICK })
+                .clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next = tick_math::get_sqrt_ratio_at_tick(tick_next).map_err(math_error)?;
+            let sqrt_price_target = if zero_for_one {
+                sqrt_price_next.max(sqrt_price_limit)
+            } else {
+                sqrt_price_next.min(sqrt_price_limit)
+            };
+
+            let (sqrt_price_after, step_in, step_out, fee_amount) = swap_math::compute_swap_step(
+                sqrt_price,
+                sqrt_price_target,
+                liquidity,
+                amount_remaining,
+                self.fee,
+            )
+            .map_err(math_error)?;
+            sqrt_price = sqrt_price_after;
+            amount_remaining -= I256::from_raw(step_in + fee_amount);
+            amount_out += step_out;
+            fee_paid += fee_amount;
+
+            if sqrt_price == sqrt_price_next {
+                // cross the initialized tick, applying its liquidity change
+                if let Some(info) = next_tick.and_then(|next| self.ticks.get(&next)) {
+                    let liquidity_net = if zero_for_one {
+                        -info.liquidity_net
+                    } else {
+                        info.liquidity_net
+                    };
+                    liquidity =
+                        liquidity_math::add_delta(liquidity, liquidity_net).map_err(math_error)?;
+                }
+                tick = if zero_for_one { tick_next - 1 } else { tick_next };
+            } else {
+                tick = tick_math::get_tick_at_sqrt_ratio(sqrt_price).map_err(math_error)?;
+            }
+        }
+
+        if !amount_remaining.is_zero() {
+            return Err(PoolSyncError::SwapSimulationError(format!(
+                "pool {} ran out of liquidity with {} left to swap",
+                self.address, amount_remaining
+            )));
+        }
+        Ok((amount_out, fee_paid))
+    }
+
+    /// Keeps only the `max_ticks` ticks nearest the current tick, rebuilding the bitmap from
+    /// the ticks that remain. Returns true and marks the pool truncated if any were dropped
+    pub fn truncate_ticks(&mut self, max_ticks: usize) -> bool {
+        if self.ticks.len() <= max_ticks {
+            return false;
+        }
+
+        let mut ticks: Vec<i32> = self.ticks.keys().copied().collect();
+        ticks.sort_by_key(|tick| ((*tick as i64 - self.tick as i64).abs(), *tick));
+        for tick in &ticks[max_ticks..] {
+            self.ticks.remove(tick);
+        }
+
+        self.tick_bitmap.clear();
+        let initialized: Vec<i32> = self
+            .ticks
+            .iter()
+            .filter(|(_, info)| info.liquidity_gross > 0)
+            .map(|(tick, _)| *tick)
+            .collect();
+        for tick in initialized {
+            flip_tick(self, tick, self.tick_spacing);
+        }
+
+        self.truncated = true;
+        true
+    }
+
+    /// Liquidity depth between `lower_tick` and `upper_tick`: the active liquidity of every
+    /// tick spacing in `[lower_tick, upper_tick)` summed together. Active liquidity is derived
+    /// from the current `liquidity` by accumulating `liquidity_net` across initialized ticks
+    pub fn liquidity_within_range(&self, lower_tick: i32, upper_tick: i32) -> u128 {
+        let spacing = self.tick_spacing.max(1) as u128;
+        let depth = self
+            .active_liquidity_segments(lower_tick, upper_tick)
+            .into_iter()
+            .fold(0u128, |depth, (start, end, active)| {
+                depth.saturating_add(active.saturating_mul((end - start) as u128))
+            });
+        depth / spacing
+    }
+
+    /// Liquidity depth of every `bucket_ticks` wide bucket across the initialized ticks, as
+    /// `(lower tick of the bucket, depth)` in ascending order. Buckets are aligned to
+    /// multiples of `bucket_ticks` and their depth is measured like `liquidity_within_range`,
+    /// so a depth chart can be drawn straight from the result. Empty when the pool has no
+    /// ticks or `bucket_ticks` is not positive
+    pub fn liquidity_distribution(&self, bucket_ticks: i32) -> Vec<(i32, u128)> {
+        let (Some(lowest), Some(highest)) = (self.ticks.keys().min(), self.ticks.keys().max())
+        else {
+            return Vec::new();
+        };
+        if bucket_ticks <= 0 {
+            return Vec::new();
+        }
+        let spacing = self.tick_spacing.max(1) as u128;
+        let first = lowest.div_euclid(bucket_ticks) * bucket_ticks;
+        let buckets = ((highest - first) / bucket_ticks + 1) as usize;
+
+        let mut depths = vec![0u128; buckets];
+        let last = first + buckets as i32 * bucket_ticks;
+        for (mut start, end, active) in self.active_liquidity_segments(first, last) {
+            // split the segment at the bucket boundaries it crosses
+            while start < end {
+                let bucket = ((start - first) / bucket_ticks) as usize;
+                let bucket_end = (first + (bucket as i32 + 1) * bucket_ticks).min(end);
+                let width = (bucket_end - start) as u128;
+                depths[bucket] = depths[bucket].saturating_add(active.saturating_mul(width));
+                start = bucket_end;
+            }
+        }
+
+        depths
+            .into_iter()
+            .enumerate()
+            .map(|(bucket, depth)| (first + bucket as i32 * bucket_ticks, depth / spacing))
+            .collect()
+    }
+
+    /// Splits `[lower_tick, upper_tick)` at every initialized tick into `(start, end, active
+    /// liquidity)` segments, walking from the current tick to find the liquidity at the start
+    fn active_liquidity_segments(&self, lower_tick: i32, upper_tick: i32) -> Vec<(i32, i32, u128)> {
+        if lower_tick >= upper_tick {
+            return Vec::new();
+        }
+
+        // active liquidity at the lower bound, walked from the current tick
+        let mut active = self.liquidity as i128;
+        for (tick, info) in &self.ticks {
+            if *tick > self.tick && *tick <= lower_tick {
+                active += info.liquidity_net;
+            } else if *tick > lower_tick && *tick <= self.tick {
+                active -= info.liquidity_net;
+            }
+        }
+
+        let mut crossings: Vec<(i32, i128)> = self
+            .ticks
+            .iter()
+            .filter(|(tick, _)| **tick > lower_tick && **tick < upper_tick)
+            .map(|(tick, info)| (*tick, info.liquidity_net))
+            .collect();
+        crossings.sort_unstable_by_key(|(tick, _)| *tick);
+
+        let mut segments = Vec::with_capacity(crossings.len() + 1);
+        let mut segment_start = lower_tick;
+        for (tick, liquidity_net) in crossings.into_iter().chain([(upper_tick, 0)]) {
+            segments.push((segment_start, tick, active.max(0) as u128));
+            active += liquidity_net;
+            segment_start = tick;
+        }
+        segments
+    }
+
+    /// Returns the next initialized tick at or below `from_tick` when `lte`, or strictly
+    /// above it otherwise, walking the `tick_bitmap` across word boundaries.
+    /// `None` when no initialized tick exists in that direction
+    pub fn next_initialized_tick(&self, from_tick: i32, lte: bool) -> Option<i32> {
+        if self.tick_spacing <= 0 {
+            return None;
+        }
+        let min_word = *self.tick_bitmap.keys().min()?;
+        let max_word = *self.tick_bitmap.keys().max()?;
+        let word_of = |compressed: i32| (compressed >> 8) as i16;
+
+        let mut tick = from_tick.clamp(MIN_TICK, MAX_TICK);
+        loop {
+            let (next, initialized) = tick_bitmap::next_initialized_tick_within_one_word(
+                &self.tick_bitmap,
+                tick,
+                self.tick_spacing,
+                lte,
+            )
+            .ok()?;
+            if initialized {
+                return Some(next);
+            }
+
+            // continue the search in the neighbouring word, stopping past the populated words
+            if lte {
+                tick = next - 1;
+                if tick < MIN_TICK || word_of(tick.div_euclid(self.tick_spacing)) < min_word {
+                    return None;
+                }
+            } else {
+                tick = next;
+                if tick >= MAX_TICK || word_of(tick.div_euclid(self.tick_spacing) + 1) > max_word {
+                    return None;
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -33,6 +691,10 @@ pub struct TickInfo {
     pub liquidity_gross: u128,
 }
 
+fn math_error(error: UniswapV3MathError) -> PoolSyncError {
+    PoolSyncError::SwapSimulationError(error.to_string())
+}
+
 pub fn process_tick_data(
     pool: &mut UniswapV3Pool,
     log: Log,
@@ -40,6 +702,9 @@ pub fn process_tick_data(
     is_initial_sync: bool,
 ) {
     let event_sig = log.topic0().unwrap();
+    if let Some(block_number) = log.block_number {
+        pool.last_update_block = pool.last_update_block.max(block_number);
+    }
 
     if *event_sig == DataEvents::Burn::SIGNATURE_HASH {
         process_burn(pool, log, is_initial_sync);
@@ -47,6 +712,8 @@ pub fn process_tick_data(
         process_mint(pool, log, is_initial_sync);
     } else if *event_sig == DataEvents::Swap::SIGNATURE_HASH {
         process_swap(pool, log);
+    } else if *event_sig == DataEvents::Initialize::SIGNATURE_HASH {
+        process_initialize(pool, log);
     }
 }
 
@@ -79,6 +746,16 @@ fn process_swap(pool: &mut UniswapV3Pool, log: Log) {
     pool.liquidity = swap_event.liquidity;
 }
 
+// The price a pool is initialized at, kept only while the pool has no price yet. Any later
+// swap or snapshot is more recent than the initialization
+fn process_initialize(pool: &mut UniswapV3Pool, log: Log) {
+    let initialize_event = DataEvents::Initialize::decode_log(log.as_ref(), true).unwrap();
+    if pool.sqrt_price.is_zero() {
+        pool.tick = initialize_event.tick.as_i32();
+        pool.sqrt_price = U256::from(initialize_event.sqrtPriceX96);
+    }
+}
+
 /// Modifies a positions liquidity in the pool.
 pub fn modify_position(
     pool: &mut UniswapV3Pool,
@@ -189,50 +866,58 @@ pub fn flip_tick(pool: &mut UniswapV3Pool, tick: i32, tick_spacing: i32) {
     }
 }
 
+// Number of leading fields of a data-sync tuple a V3 pool is read from
+const V3_DATA_FIELDS: usize = 10;
+
 impl From<&[DynSolValue]> for UniswapV3Pool {
     fn from(data: &[DynSolValue]) -> Self {
-        // Safe conversion function for decimals with bounds checking
-        let safe_u8_conversion = |value: &DynSolValue| -> u8 {
-            let uint_val = value.as_uint().unwrap().0;
-            if uint_val > U256::from(255u32) {
-                18 // Default to 18 decimals if value is too large
-            } else {
-                uint_val.try_into().unwrap_or(18)
-            }
-        };
+        // a tuple missing fields, e.g. from a fork's contract, gives an invalid pool that
+        // population drops instead of a panic
+        if data.len() < V3_DATA_FIELDS {
+            return Self::default();
+        }
 
-        // Safe conversion function for fee with bounds checking
-        let safe_u32_conversion = |value: &DynSolValue| -> u32 {
-            let uint_val = value.as_uint().unwrap().0;
-            if uint_val > U256::from(u32::MAX) {
-                3000 // Default fee if value is too large
-            } else {
-                uint_val.try_into().unwrap_or(3000)
-            }
+        // Decimals that do not fit in a u8 are marked unknown rather than guessed
+        let safe_u8_conversion = |value: &DynSolValue| -> Option<u8> {
+            value.as_uint().unwrap().0.try_into().ok()
         };
 
-        // Safe conversion function for tick values
-        let safe_i32_conversion = |value: &DynSolValue| -> i32 {
-            match value.as_int() {
-                Some(signed_val) => {
-                    // Try to convert to i32, use default if overflow
-                    signed_val.0.try_into().unwrap_or(0)
-                }
-                None => 0, // Default to 0 if conversion fails
-            }
+        // Tick values that do not fit in an i32 or are not integers at all
+        let safe_i32_conversion = |value: &DynSolValue| -> Option<i32> {
+            value.as_int().and_then(|(signed_val, _)| signed_val.try_into().ok())
         };
 
+        // Values that do not fit fall back to a default, recording the field in the bitset
+        let mut decode_fallbacks = 0;
+        let fee = u32::try_from(data[9].as_uint().unwrap().0).unwrap_or_else(|_| {
+            decode_fallbacks |= Self::FALLBACK_FEE;
+            3000
+        });
+        let tick = safe_i32_conversion(&data[7]).unwrap_or_else(|| {
+            decode_fallbacks |= Self::FALLBACK_TICK;
+            0
+        });
+        let tick_spacing = safe_i32_conversion(&data[8]).unwrap_or_else(|| {
+            decode_fallbacks |= Self::FALLBACK_TICK_SPACING;
+            0
+        });
+        let liquidity = u128::try_from(data[5].as_uint().unwrap().0).unwrap_or_else(|_| {
+            decode_fallbacks |= Self::FALLBACK_LIQUIDITY;
+            0
+        });
+
         Self {
             address: data[0].as_address().unwrap(),
             token0: data[1].as_address().unwrap(),
             token0_decimals: safe_u8_conversion(&data[2]),
             token1: data[3].as_address().unwrap(),
             token1_decimals: safe_u8_conversion(&data[4]),
-            liquidity: data[5].as_uint().unwrap().0.try_into().unwrap_or(0),
+            liquidity,
             sqrt_price: data[6].as_uint().unwrap().0,
-            tick: safe_i32_conversion(&data[7]),
-            tick_spacing: safe_i32_conversion(&data[8]),
-            fee: safe_u32_conversion(&data[9]),
+            tick,
+            tick_spacing,
+            fee,
+            decode_fallbacks,
             ..Default::default()
         }
     }
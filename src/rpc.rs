@@ -1,43 +1,348 @@
 use alloy::network::Network;
 use alloy::primitives::Address;
 use alloy::providers::Provider;
+use alloy::rpc::client::BatchRequest;
 use alloy::rpc::types::{Filter, Log};
 use alloy::sol_types::SolEvent;
-use alloy::transports::Transport;
+use alloy::transports::{RpcError, Transport, TransportErrorKind};
 use anyhow::anyhow;
 use anyhow::Result;
 use futures::StreamExt;
 use indicatif::ProgressBar;
-use log::info;
+use log::{debug, warn};
 use rand::Rng;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::{Mutex, Semaphore};
-use tokio::time::{interval, Duration};
+use tokio::sync::Semaphore;
+use tokio::time::{Duration, Instant};
 
 use crate::events::*;
-use crate::pools::pool_builder;
+use crate::pools::pool_builder::{self, CallLimiter, PopulationConfig};
+use crate::pools::pool_structures::v2_structure::process_sync_data;
 use crate::pools::pool_structures::v3_structure::process_tick_data;
-use crate::pools::PoolFetcher;
+use crate::pools::{validate_event_signature, PoolCreation, PoolFetcher};
+use crate::sync_metrics;
 use crate::util::create_progress_bar;
-use crate::{Chain, Pool, PoolInfo, PoolType};
+use crate::{Chain, Pool, PoolInfo, PoolSyncError, PoolType};
 
 // Retry constants
 const MAX_RETRIES: u32 = 5;
+// Number of creation transactions looked up in one batch request
+const CREATOR_BATCH_SIZE: usize = 100;
 const INITIAL_BACKOFF: u64 = 1000; // 1 second
+// Lowest request rate an endpoint answering HTTP 429 is slowed down to, per second
+const MIN_ADAPTIVE_RATE: f64 = 0.1;
+// Time without a 429 response for a lowered request rate to double back up
+const RATE_RECOVERY_PERIOD: Duration = Duration::from_secs(10);
+// Error messages of providers capping the size of an `eth_getLogs` response
+const RESPONSE_SIZE_ERRORS: [&str; 4] = [
+    "response size",
+    "query returned more than",
+    "too many results",
+    "exceeds max results",
+];
+
+/// Spaces requests at the configured rate limit. Every HTTP 429 response halves the rate,
+/// and it doubles back every `RATE_RECOVERY_PERIOD` without one until the limit is reached.
+/// A sync shares one between its log queries, data-sync calls and token calls, so a 429 on
+/// any of them slows all of them. Unlimited by default
+#[derive(Debug, Clone, Default)]
+pub struct AdaptiveRate {
+    inner: Option<Arc<RateInner>>,
+}
+
+#[derive(Debug)]
+struct RateInner {
+    max_rate: f64,
+    state: std::sync::Mutex<RateState>,
+}
+
+#[derive(Debug)]
+struct RateState {
+    // rate set by the last 429 response and when it arrived
+    throttled: Option<(f64, Instant)>,
+    // earliest time the next request may be sent
+    next_slot: Instant,
+}
+
+impl AdaptiveRate {
+    /// A rate starting at `rate_limit` requests per second
+    pub fn new(rate_limit: u64) -> Self {
+        Self {
+            inner: Some(Arc::new(RateInner {
+                max_rate: rate_limit.max(1) as f64,
+                state: std::sync::Mutex::new(RateState {
+                    throttled: None,
+                    next_slot: Instant::now(),
+                }),
+            })),
+        }
+    }
+
+    /// Whether requests are paced at all
+    pub(crate) fn is_limited(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// Requests per second currently allowed, `None` when unlimited
+    #[cfg(test)]
+    pub(crate) fn current_rate(&self) -> Option<f64> {
+        let inner = self.inner.as_ref()?;
+        Some(inner.rate_at(&inner.state.lock().unwrap(), Instant::now()))
+    }
+
+    /// Waits for the next request slot at the current rate
+    pub(crate) async fn tick(&self) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let slot = {
+            let mut state = inner.state.lock().unwrap();
+            let now = Instant::now();
+            let slot = state.next_slot.max(now);
+            state.next_slot = slot + Duration::from_secs_f64(1.0 / inner.rate_at(&state, now));
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Halves the current rate after a 429 response, returning the new rate, `None` when
+    /// unlimited
+    pub(crate) fn throttle(&self) -> Option<f64> {
+        let inner = self.inner.as_ref()?;
+        let mut state = inner.state.lock().unwrap();
+        let now = Instant::now();
+        let rate = (inner.rate_at(&state, now) / 2.0).max(MIN_ADAPTIVE_RATE);
+        state.throttled = Some((rate, now));
+        Some(rate)
+    }
+
+    /// Halves the rate when `error` is an HTTP 429 response
+    pub(crate) fn record(&self, error: &RpcError<TransportErrorKind>) {
+        if !is_rate_limited(error) {
+            return;
+        }
+        if let Some(rate) = self.throttle() {
+            warn!("rpc answered HTTP 429, lowering the request rate to {:.2}/s", rate);
+        }
+    }
+}
+
+impl RateInner {
+    fn rate_at(&self, state: &RateState, now: Instant) -> f64 {
+        match state.throttled {
+            Some((rate, at)) => {
+                let periods =
+                    now.duration_since(at).as_secs_f64() / RATE_RECOVERY_PERIOD.as_secs_f64();
+                (rate * 2f64.powf(periods)).min(self.max_rate)
+            }
+            None => self.max_rate,
+        }
+    }
+}
+
+/// Thresholds of the circuit breaker that pauses a sync while the rpc fails most requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Fraction of failed requests in the window that trips the breaker, e.g. `0.5`
+    pub error_rate: f64,
+    /// Number of most recent requests the error rate is measured over
+    pub window: usize,
+    /// Time requests are held back once the breaker tripped, after which a single probe
+    /// request is let through
+    pub cooldown: Duration,
+}
+
+/// Pauses every request of a sync while the rpc is degraded. Once the failed share of the
+/// last `window` requests reaches `error_rate` the breaker opens and requests wait out the
+/// cooldown. Then it is half-open: one probe request goes through, closing the breaker when
+/// it succeeds and starting another cooldown when it fails. A probe reporting no outcome
+/// within a cooldown, e.g. because its request was cancelled, is replaced by the next
+/// request. Disabled by default
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreaker {
+    inner: Option<Arc<BreakerInner>>,
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    config: CircuitBreakerConfig,
+    state: std::sync::Mutex<BreakerState>,
+    // wakes the requests waiting for the probe of a half-open breaker
+    probed: tokio::sync::Notify,
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    // outcomes of the most recent requests, true for a failure
+    Closed(VecDeque<bool>),
+    Open { until: Instant },
+    // the probe is in flight, a new one is let through at the deadline
+    HalfOpen { deadline: Instant },
+}
+
+impl CircuitBreaker {
+    /// A breaker tripping at the thresholds of `config`
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            inner: Some(Arc::new(BreakerInner {
+                config,
+                state: std::sync::Mutex::new(BreakerState::Closed(VecDeque::new())),
+                probed: tokio::sync::Notify::new(),
+            })),
+        }
+    }
+
+    /// Whether the breaker is holding requests back
+    #[cfg(test)]
+    pub(crate) fn is_open(&self) -> bool {
+        self.inner.as_ref().is_some_and(|inner| {
+            !matches!(*inner.state.lock().unwrap(), BreakerState::Closed(_))
+        })
+    }
+
+    /// Waits until a request may be sent, through the cooldown of an open breaker and the
+    /// probe of a half-open one
+    pub(crate) async fn acquire(&self) {
+        let Some(inner) = &self.inner else { return };
+        loop {
+            let probed = inner.probed.notified();
+            let (until, probing) = {
+                let mut state = inner.state.lock().unwrap();
+                let now = Instant::now();
+                match *state {
+                    BreakerState::Closed(_) => return,
+                    // this request is the probe, or replaces one that never reported
+                    BreakerState::Open { until } | BreakerState::HalfOpen { deadline: until }
+                        if now >= until =>
+                    {
+                        *state = BreakerState::HalfOpen {
+                            deadline: now + inner.config.cooldown,
+                        };
+                        return;
+                    }
+                    BreakerState::Open { until } => (until, false),
+                    BreakerState::HalfOpen { deadline } => (deadline, true),
+                }
+            };
+            if probing {
+                let _ = tokio::time::timeout_at(until, probed).await;
+            } else {
+                tokio::time::sleep_until(until).await;
+            }
+        }
+    }
+
+    /// Records the outcome of a request, tripping the breaker when the error rate is reached
+    pub(crate) fn record(&self, failed: bool) {
+        let Some(inner) = &self.inner else { return };
+        let config = &inner.config;
+        let mut state = inner.state.lock().unwrap();
+        let trip = match &mut *state {
+            BreakerState::Closed(outcomes) => {
+                outcomes.push_back(failed);
+                if outcomes.len() > config.window {
+                    outcomes.pop_front();
+                }
+                let failures = outcomes.iter().filter(|failed| **failed).count();
+                outcomes.len() >= config.window
+                    && failures > 0
+                    && failures as f64 >= config.error_rate * outcomes.len() as f64
+            }
+            // requests sent before the breaker opened say nothing about the endpoint now
+            BreakerState::Open { .. } => return,
+            BreakerState::HalfOpen { .. } => {
+                if !failed {
+                    debug!("rpc probe succeeded, resuming requests");
+                    *state = BreakerState::Closed(VecDeque::new());
+                    inner.probed.notify_waiters();
+                    return;
+                }
+                true
+            }
+        };
+        if trip {
+            warn!(
+                "rpc is failing {:.0}% or more of requests, pausing requests for {:?}",
+                config.error_rate * 100.0,
+                config.cooldown
+            );
+            *state = BreakerState::Open {
+                until: Instant::now() + config.cooldown,
+            };
+            inner.probed.notify_waiters();
+        }
+    }
+}
+
+// Whether the endpoint rejected the request with HTTP 429 Too Many Requests
+fn is_rate_limited(error: &RpcError<TransportErrorKind>) -> bool {
+    matches!(error, RpcError::Transport(TransportErrorKind::HttpError(http)) if http.is_rate_limit_err())
+}
+
+// Whether the node refused or cut off a log response for its size: an error capping the
+// number of results or bytes, or a body that ends before its JSON does
+fn is_response_too_large(error: &RpcError<TransportErrorKind>) -> bool {
+    match error {
+        RpcError::ErrorResp(payload) => {
+            let message = payload.message.to_lowercase();
+            RESPONSE_SIZE_ERRORS.iter().any(|pattern| message.contains(pattern))
+        }
+        RpcError::DeserError { err, .. } => err.is_eof(),
+        _ => false,
+    }
+}
 
 // Define event configurations
 #[derive(Debug)]
 struct EventConfig {
-    events: &'static [&'static str],
+    events: Vec<String>,
     step_size: u64,
     description: &'static str,
-    requires_initial_sync: bool,
+}
+
+/// Endpoints used by a sync. Any url left unset falls back to the `ARCHIVE`, `FULL` and
+/// `WS` environment variables
+#[derive(Debug, Clone, Default)]
+pub struct RpcConfig {
+    /// Archive node used for log queries and the initial sync
+    pub archive_url: Option<String>,
+    /// Full node used for the latest block and pool population
+    pub full_url: Option<String>,
+    /// Websocket endpoint for live updates
+    pub ws_url: Option<String>,
+}
+
+impl RpcConfig {
+    /// The archive node url
+    pub fn archive_url(&self) -> Result<String, PoolSyncError> {
+        Self::resolve(&self.archive_url, "ARCHIVE")
+    }
+
+    /// The full node url
+    pub fn full_url(&self) -> Result<String, PoolSyncError> {
+        Self::resolve(&self.full_url, "FULL")
+    }
+
+    /// The websocket url
+    pub fn ws_url(&self) -> Result<String, PoolSyncError> {
+        Self::resolve(&self.ws_url, "WS")
+    }
+
+    fn resolve(configured: &Option<String>, var: &str) -> Result<String, PoolSyncError> {
+        match configured {
+            Some(url) => Ok(url.clone()),
+            None => std::env::var(var).map_err(|_| {
+                PoolSyncError::ProviderError(format!("no {} rpc url configured", var))
+            }),
+        }
+    }
 }
 
 pub struct Rpc;
 impl Rpc {
     // Fetch all pool addresses for the protocol
+    #[allow(clippy::too_many_arguments)]
     pub async fn fetch_pool_addrs<P, T, N>(
         start_block: u64,
         end_block: u64,
@@ -45,7 +350,41 @@ impl Rpc {
         fetcher: Arc<dyn PoolFetcher>,
         chain: Chain,
         rate_limit: u64,
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
     ) -> Result<Vec<Address>>
+    where
+        P: Provider<T, N> + 'static,
+        T: Transport + Clone + 'static,
+        N: Network,
+    {
+        let creations = Rpc::fetch_pool_creations(
+            start_block,
+            end_block,
+            provider,
+            fetcher,
+            chain,
+            rate_limit,
+            rate,
+            breaker,
+        )
+        .await?;
+        anyhow::Ok(creations.into_iter().map(|creation| creation.address).collect())
+    }
+
+    // Fetch the creation event of every pool for the protocol, with the block it was
+    // created in and its tokens, in creation order
+    #[allow(clippy::too_many_arguments)]
+    pub async fn fetch_pool_creations<P, T, N>(
+        start_block: u64,
+        end_block: u64,
+        provider: Arc<P>,
+        fetcher: Arc<dyn PoolFetcher>,
+        chain: Chain,
+        rate_limit: u64,
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
+    ) -> Result<Vec<PoolCreation>>
     where
         P: Provider<T, N> + 'static,
         T: Transport + Clone + 'static,
@@ -65,33 +404,181 @@ impl Rpc {
         let progress_bar = Arc::new(create_progress_bar(num_tasks, pb_info));
 
         // fetch all of the logs
-        let logs = Rpc::fetch_event_logs(
+        let mut logs = Rpc::fetch_event_logs(
             start_block,
             end_block,
             step_size,  // 使用动态的步长而不是硬编码值
             provider,
             rate_limit,
+            rate,
+            breaker,
             progress_bar,
             filter,
         )
         .await?;
 
-        // extract the addresses from the logs
-        let addresses: Vec<Address> = logs
+        // chunks complete out of order, restore creation order
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+
+        // extract the addresses from the logs, in creation order
+        let creations: Vec<PoolCreation> = logs
             .iter()
-            .map(|log| fetcher.log_to_address(&log.inner))
+            .map(|log| PoolCreation {
+                address: fetcher.log_to_address(&log.inner),
+                block: log.block_number.unwrap_or_default(),
+                tokens: fetcher.log_to_tokens(&log.inner),
+                tx_hash: log.transaction_hash,
+                creator: None,
+            })
             .collect();
-        anyhow::Ok(addresses)
+        anyhow::Ok(creations)
     }
 
+    // Resolve the sender of every creation transaction with batched
+    // `eth_getTransactionByHash` calls, paced by the sync's rate and retried with the same
+    // backoff as the log queries. Creations without a transaction hash, whose transaction
+    // the node does not return, or whose batch keeps failing keep no creator
+    pub async fn fetch_creators<P, T, N>(
+        provider: Arc<P>,
+        creations: &mut [PoolCreation],
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
+    ) where
+        P: Provider<T, N> + 'static,
+        T: Transport + Clone + 'static,
+        N: Network,
+    {
+        for chunk in creations.chunks_mut(CREATOR_BATCH_SIZE) {
+            let mut retry_count = 0;
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                breaker.acquire().await;
+                rate.tick().await;
+                sync_metrics::rpc_call("eth_getTransactionByHash");
+                let creators = Self::fetch_creator_batch(&provider, chunk).await;
+                breaker.record(creators.is_err());
+                match creators {
+                    Ok(creators) => {
+                        for (creation, creator) in chunk.iter_mut().zip(creators) {
+                            creation.creator = creator;
+                        }
+                        break;
+                    }
+                    Err(e) => {
+                        sync_metrics::rpc_error("eth_getTransactionByHash");
+                        rate.record(&e);
+                        if retry_count >= MAX_RETRIES {
+                            warn!(
+                                "Failed to fetch the creators of {} pools after {} retries, leaving them unset: {}",
+                                chunk.len(),
+                                MAX_RETRIES,
+                                e
+                            );
+                            break;
+                        }
+                        let jitter = rand::thread_rng().gen_range(0..=100);
+                        tokio::time::sleep(Duration::from_millis(backoff + jitter)).await;
+                        retry_count += 1;
+                        backoff *= 2;
+                    }
+                }
+            }
+        }
+    }
+
+    // Send one batch of `eth_getTransactionByHash` calls and read the sender of each
+    async fn fetch_creator_batch<P, T, N>(
+        provider: &Arc<P>,
+        creations: &[PoolCreation],
+    ) -> Result<Vec<Option<Address>>, RpcError<TransportErrorKind>>
+    where
+        P: Provider<T, N> + 'static,
+        T: Transport + Clone + 'static,
+        N: Network,
+    {
+        let mut batch = BatchRequest::new(provider.client());
+        let waiters = creations
+            .iter()
+            .map(|creation| {
+                creation
+                    .tx_hash
+                    .map(|hash| {
+                        batch.add_call::<_, Option<serde_json::Value>>(
+                            "eth_getTransactionByHash",
+                            &(hash,),
+                        )
+                    })
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        batch.send().await?;
+
+        let mut creators = Vec::with_capacity(waiters.len());
+        for waiter in waiters {
+            let creator = match waiter {
+                Some(waiter) => waiter
+                    .await?
+                    .and_then(|tx| serde_json::from_value(tx["from"].clone()).ok()),
+                None => None,
+            };
+            creators.push(creator);
+        }
+        Ok(creators)
+    }
+
+    // Probe the factory over a small range to confirm the creation topic is emitted.
+    // Returns false only when the factory emitted logs but none carried the expected topic,
+    // an empty range is inconclusive and treated as a pass
+    pub async fn probe_event_signature<P, T, N>(
+        start_block: u64,
+        end_block: u64,
+        provider: Arc<P>,
+        fetcher: Arc<dyn PoolFetcher>,
+        chain: Chain,
+    ) -> Result<bool>
+    where
+        P: Provider<T, N> + 'static,
+        T: Transport + Clone + 'static,
+        N: Network,
+    {
+        let topic = validate_event_signature(fetcher.as_ref())?;
+        let filter = Filter::new()
+            .address(fetcher.factory_address(chain))
+            .from_block(start_block)
+            .to_block(end_block);
+
+        let logs = Rpc::get_logs_with_retry(
+            provider,
+            &filter,
+            &AdaptiveRate::default(),
+            &CircuitBreaker::default(),
+        )
+        .await?;
+        let matched = logs.is_empty() || logs.iter().any(|log| log.topic0() == Some(&topic));
+        if !matched {
+            warn!(
+                "{} factory emitted {} logs in blocks {}-{} but none matched the creation event `{}`",
+                fetcher.pool_type(),
+                logs.len(),
+                start_block,
+                end_block,
+                fetcher.pair_created_signature()
+            );
+        }
+        anyhow::Ok(matched)
+    }
+
+    // Populate the pools in batches, returning the populated pools along with every batch
+    // of addresses that still failed after all retries and its error
     pub async fn populate_pools<P, T, N>(
         pool_addrs: Vec<Address>,
         provider: Arc<P>,
         pool: PoolType,
         fetcher: Arc<dyn PoolFetcher>,
         rate_limit: u64,
-        chain: Chain
-    ) -> Result<Vec<Pool>>
+        chain: Chain,
+        config: PopulationConfig,
+    ) -> Result<(Vec<Pool>, Vec<(Vec<Address>, String)>)>
     where
         P: Provider<T, N> + 'static,
         T: Transport + Clone + 'static,
@@ -101,12 +588,21 @@ impl Rpc {
         let batch_size = 50; // Standard batch size for V3 pools
 
         // informational and rate limiting initialization
-        let total_tasks = (pool_addrs.len() + batch_size - 1) / batch_size; // Ceiling division
+        let total_tasks = pool_addrs.len().div_ceil(batch_size);
         let progress_bar = create_progress_bar(total_tasks as u64, format!("  💾 加载 {} 池数据 ({} 个池)", pool, pool_addrs.len()));
-        let semaphore = Arc::new(Semaphore::new(rate_limit as usize));
-        let interval = Arc::new(tokio::sync::Mutex::new(interval(Duration::from_secs_f64(
-            1.0 / rate_limit as f64,
-        ))));
+        // every data-sync call, of a batch or of one of its chunks, takes a slot of the
+        // same limiter. The calls are paced at the rate limit unless a rate is shared with them
+        let rate = match config.rate.is_limited() {
+            true => config.rate.clone(),
+            false => AdaptiveRate::new(rate_limit),
+        };
+        let config = PopulationConfig {
+            token_concurrency: config.token_concurrency.min(rate_limit as usize),
+            chunk_concurrency: config.chunk_concurrency.min(rate_limit as usize),
+            limiter: CallLimiter::new(rate_limit),
+            rate,
+            ..config
+        };
 
         // break the addresses up into chunk
         let addr_chunks: Vec<Vec<Address>> = pool_addrs
@@ -116,72 +612,49 @@ impl Rpc {
 
         let mut stream = futures::stream::iter(addr_chunks.into_iter().map(|chunk| {
             let provider = provider.clone();
-            let sem = semaphore.clone();
             let pb = progress_bar.clone();
             let fetcher = fetcher.clone();
-            let interval = interval.clone();
-            let data = fetcher.get_pool_repr();
+            let config = config.clone();
 
             async move {
-                let _permit = sem.acquire().await.unwrap();
-                interval.lock().await.tick().await;
-                let mut retry_count = 0;
-                let mut backoff = 1000; // Initial backoff of 1 second
-                loop {
-                    // try building pools from this set of addresses
-                    match pool_builder::build_pools(
-                        &provider,
-                        chunk.clone(),
-                        pool,
-                        data.clone(),
-                        chain
-                    )
-                    .await
-                    {
-                        Ok(populated_pools) if !populated_pools.is_empty() => {
-                            pb.inc(1);
-                            drop(provider);
-                            return anyhow::Ok::<Vec<Pool>>(populated_pools);
-                        }
-                        Err(e) => {
-                            if retry_count >= MAX_RETRIES {
-                                info!("Failed to populate pools data: {}", e);
-                                drop(provider);
-                                return Ok(Vec::new());
-                            }
-                            let jitter = rand::thread_rng().gen_range(0..=100);
-                            let sleep_duration = Duration::from_millis(backoff + jitter);
-                            tokio::time::sleep(sleep_duration).await;
-                            retry_count += 1;
-                            backoff *= 2; // Exponential backoff
-                        }
-                        _ => continue,
-                    }
+                // build_pools retries with backoff, the chunks it returns as failed are lost
+                let populated =
+                    pool_builder::build_pools(&provider, chunk, pool, fetcher.as_ref(), chain, config).await;
+                if populated.as_ref().is_ok_and(|(_, failed)| failed.is_empty()) {
+                    pb.inc(1);
                 }
+                populated
             }
         }))
         .buffer_unordered(rate_limit as usize);
 
         let mut all_pools = Vec::new();
-
-        while let Some(pool_res) = stream.next().await {
-            match pool_res {
-                Ok(pool) => all_pools.extend(pool),
-                Err(e) => return Err(e),
+        let mut failed = Vec::new();
+        while let Some(populated) = stream.next().await {
+            // the sync is out of retries or the provider rejects the data-sync deploy, give
+            // up on every remaining batch
+            let (pools, failed_chunks) = populated?;
+            all_pools.extend(pools);
+            for (chunk, e) in failed_chunks {
+                warn!("Failed to populate {} pools data: {:#}", pool, e);
+                failed.push((chunk, format!("{:#}", e)));
             }
         }
 
         progress_bar.finish_with_message(format!("✅ {} 池数据获取完成", pool));
-        Ok(all_pools)
+        Ok((all_pools, failed))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn populate_liquidity<P, T, N>(
         start_block: u64,
         end_block: u64,
         pools: &mut [Pool],
         provider: Arc<P>,
-        pool_type: PoolType,
+        fetcher: Arc<dyn PoolFetcher>,
         rate_limit: u64,
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
         is_initial_sync: bool,
     ) -> anyhow::Result<()>
     where
@@ -203,16 +676,17 @@ impl Rpc {
         let mut current_block = start_block;
 
         // get the configuration for this sync and config we should sync
-        let config = Rpc::get_event_config(pool_type, is_initial_sync);
-        if is_initial_sync && config.requires_initial_sync {
+        let pool_type = fetcher.pool_type();
+        let config = Rpc::get_event_config(fetcher.as_ref(), is_initial_sync);
+        if config.events.is_empty() {
             return anyhow::Ok(());
         }
 
         // construct the progress bar
         let num_tasks = (end_block - start_block) / config.step_size + 1;
         let pb_info = format!(
-            "  🔄 同步 {} 流动性数据 ({} 个池)",
-            pool_type, pools.len()
+            "  🔄 同步 {} 流动性数据 - {} ({} 个池)",
+            pool_type, config.description, pools.len()
         );
         let progress_bar = Arc::new(create_progress_bar(num_tasks, pb_info));
 
@@ -227,6 +701,8 @@ impl Rpc {
                 provider.clone(),
                 progress_bar.clone(),
                 rate_limit,
+                rate,
+                breaker,
             )
             .await?;
 
@@ -245,13 +721,12 @@ impl Rpc {
                     let address = log.address();
                     if let Some(&index) = address_to_index.get(&address) {
                         if let Some(pool) = pools.get_mut(index) {
-                            // Only process V3 pools
-                            process_tick_data(
-                                pool.get_v3_mut().unwrap(),
-                                log,
-                                pool_type,
-                                is_initial_sync,
-                            );
+                            match pool {
+                                Pool::UniswapV3(pool) | Pool::Agni(pool) => {
+                                    process_tick_data(pool, log, pool_type, is_initial_sync)
+                                }
+                                Pool::MerchantMoe(pool) => process_sync_data(pool, log, pool_type),
+                            }
                             logs_processed += 1;
                         }
                     }
@@ -266,16 +741,19 @@ impl Rpc {
             current_block = batch_end + 1;
         }
         
-        progress_bar.finish_with_message(format!("完成"));
+        progress_bar.finish_with_message("完成");
         anyhow::Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn fetch_event_logs<T, N, P>(
         start_block: u64,
         end_block: u64,
         step_size: u64,
         provider: Arc<P>,
         rate_limit: u64,
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
         progress_bar: Arc<ProgressBar>,
         filter: Filter,
     ) -> anyhow::Result<Vec<Log>>
@@ -287,11 +765,8 @@ impl Rpc {
         // generate the block range for the sync and setup progress bar
         let block_range = Rpc::get_block_range(step_size, start_block, end_block);
 
-        // semaphore and interval for rate limiting
+        // semaphore for rate limiting, the requests are paced by the rate shared with the sync
         let semaphore = Arc::new(Semaphore::new(rate_limit as usize));
-        let interval = Arc::new(Mutex::new(interval(Duration::from_secs_f64(
-            1.0 / rate_limit as f64,
-        ))));
 
         // Create a stream of futures
         let mut stream =
@@ -299,16 +774,19 @@ impl Rpc {
                 let provider = provider.clone();
                 let sem = semaphore.clone();
                 let pb = progress_bar.clone();
-                let interval = interval.clone();
+                let rate = rate.clone();
                 let filter = filter.clone();
+                let breaker = breaker.clone();
 
                 async move {
                     let _permit = sem.acquire().await.unwrap();
-                    interval.lock().await.tick().await;
 
-                    let filter = filter.from_block(from_block).to_block(to_block);
-                    let logs = Rpc::get_logs_with_retry(provider, &filter).await;
+                    let timer = Instant::now();
+                    let logs =
+                        Rpc::get_logs_splitting(provider, &filter, from_block, to_block, &rate, &breaker)
+                            .await;
                     if logs.is_ok() {
+                        sync_metrics::chunk_duration(timer.elapsed());
                         pb.inc(1);
                     }
                     logs
@@ -333,6 +811,7 @@ impl Rpc {
     // Given a config and a range, fetch all the logs for it
     // This is a top level call which will delegate to individual fetching
     // functions to get the logs and to ensure retries on failure
+    #[allow(clippy::too_many_arguments)]
     async fn fetch_logs_for_config<P, T, N>(
         config: &EventConfig,
         start_block: u64,
@@ -340,29 +819,70 @@ impl Rpc {
         provider: Arc<P>,
         progress_bar: Arc<ProgressBar>,
         rate_limit: u64,
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
     ) -> Result<Vec<Log>>
     where
         P: Provider<T, N> + 'static,
         T: Transport + Clone + 'static,
         N: Network,
     {
-        let filter = Filter::new().events(config.events.iter().copied());
+        let filter = Filter::new().events(config.events.iter().map(String::as_str));
         Rpc::fetch_event_logs(
             start_block,
             end_block,
             config.step_size,
             provider,
             rate_limit,
+            rate,
+            breaker,
             progress_bar,
             filter,
         )
         .await
     }
 
-    // Fetch logs with retry functionality
+    // Fetch the logs of a block range, halving the range while the node rejects or truncates
+    // the response for its size, down to single blocks
+    async fn get_logs_splitting<P, T, N>(
+        provider: Arc<P>,
+        filter: &Filter,
+        from_block: u64,
+        to_block: u64,
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
+    ) -> anyhow::Result<Vec<Log>>
+    where
+        P: Provider<T, N> + 'static,
+        T: Transport + Clone + 'static,
+        N: Network,
+    {
+        let mut ranges = vec![(from_block, to_block)];
+        let mut logs = Vec::new();
+        while let Some((from, to)) = ranges.pop() {
+            let range_filter = filter.clone().from_block(from).to_block(to);
+            match Rpc::get_logs_with_retry(provider.clone(), &range_filter, rate, breaker).await
+            {
+                Ok(range_logs) => logs.extend(range_logs),
+                Err(e) if from < to && e.downcast_ref().is_some_and(is_response_too_large) => {
+                    let mid = from + (to - from) / 2;
+                    debug!("log response for blocks {}-{} too large, splitting it: {}", from, to, e);
+                    ranges.push((mid + 1, to));
+                    ranges.push((from, mid));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        anyhow::Ok(logs)
+    }
+
+    // Fetch logs with retry functionality. Every attempt waits for a slot of the rate, and a
+    // 429 response lowers it for all requests sharing it
     async fn get_logs_with_retry<P, T, N>(
         provider: Arc<P>,
         filter: &Filter,
+        rate: &AdaptiveRate,
+        breaker: &CircuitBreaker,
     ) -> anyhow::Result<Vec<Log>>
     where
         P: Provider<T, N> + 'static,
@@ -373,11 +893,23 @@ impl Rpc {
         let mut backoff = INITIAL_BACKOFF;
 
         loop {
-            match provider.get_logs(filter).await {
+            breaker.acquire().await;
+            rate.tick().await;
+            sync_metrics::rpc_call("eth_getLogs");
+            let logs = provider.get_logs(filter).await;
+            // a response too large for the node is no sign of degradation
+            breaker.record(logs.as_ref().is_err_and(|e| !is_response_too_large(e)));
+            if logs.is_err() {
+                sync_metrics::rpc_error("eth_getLogs");
+            }
+            match logs {
                 Ok(logs) => {
                     return anyhow::Ok(logs);
                 }
+                // the same range keeps failing, only a smaller one can succeed
+                Err(e) if is_response_too_large(&e) => return Err(anyhow!(e)),
                 Err(e) => {
+                    rate.record(&e);
                     if retry_count >= MAX_RETRIES {
                         return Err(anyhow!(e));
                     }
@@ -391,25 +923,65 @@ impl Rpc {
         }
     }
 
-    fn get_event_config(_pool_type: PoolType, is_initial_sync: bool) -> EventConfig {
-        // Only V3 pools are supported now
+    // Fetch the latest block number with the same retries and backoff as the log queries
+    pub(crate) async fn get_block_number_with_retry<P, T, N>(
+        provider: &P,
+        breaker: &CircuitBreaker,
+    ) -> Result<u64, PoolSyncError>
+    where
+        P: Provider<T, N>,
+        T: Transport + Clone,
+        N: Network,
+    {
+        let mut retry_count = 0;
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            breaker.acquire().await;
+            sync_metrics::rpc_call("eth_blockNumber");
+            let block = provider.get_block_number().await;
+            breaker.record(block.is_err());
+            if block.is_err() {
+                sync_metrics::rpc_error("eth_blockNumber");
+            }
+            match block {
+                Ok(block) => return Ok(block),
+                Err(e) if retry_count >= MAX_RETRIES => {
+                    return Err(PoolSyncError::RpcError(format!(
+                        "eth_blockNumber failed after {} retries: {}",
+                        MAX_RETRIES, e
+                    )));
+                }
+                Err(_) => {
+                    let jitter = rand::thread_rng().gen_range(0..=100);
+                    tokio::time::sleep(Duration::from_millis(backoff + jitter)).await;
+                    retry_count += 1;
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+
+    fn get_event_config(fetcher: &dyn PoolFetcher, is_initial_sync: bool) -> EventConfig {
+        let events = fetcher.data_event_signatures().into_iter().map(String::from);
         if is_initial_sync {
+            // the populated state already reflects the latest swaps and reserves, only the
+            // position events are needed to rebuild the ticks, and the initialization of
+            // pools read before they had a price
             EventConfig {
-                events: &[DataEvents::Mint::SIGNATURE, DataEvents::Burn::SIGNATURE],
+                events: events
+                    .filter(|event| {
+                        event != DataEvents::Swap::SIGNATURE && event != DataEvents::Sync::SIGNATURE
+                    })
+                    .collect(),
                 step_size: 1500,
                 description: "Tick sync",
-                requires_initial_sync: false, // Always fetch these
             }
         } else {
             EventConfig {
-                events: &[
-                    DataEvents::Mint::SIGNATURE,
-                    DataEvents::Burn::SIGNATURE,
-                    DataEvents::Swap::SIGNATURE,
-                ],
+                events: events.collect(),
                 step_size: 50,
                 description: "Full sync",
-                requires_initial_sync: true, // Always fetch these
             }
         }
     }
@@ -439,3 +1011,11 @@ impl Rpc {
         block_ranges
     }
 }
+
+/// Whether an error was caused by the sync running out of its retry budget
+pub(crate) fn is_retry_budget_exceeded(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<PoolSyncError>(),
+        Some(PoolSyncError::RetryBudgetExceeded)
+    )
+}
@@ -0,0 +1,52 @@
+//! Sync Metrics
+//!
+//! This module reports the progress of a sync through the `metrics` crate, so it can be
+//! exported to Prometheus or any other recorder installed by the application. Every
+//! function is a no-op unless the `metrics` feature is enabled.
+
+use std::time::Duration;
+
+use crate::pools::PoolType;
+
+/// Counter of pools found by discovery, labelled with `pool_type`
+pub const METRIC_POOLS_DISCOVERED: &str = "pool_sync_pools_discovered";
+/// Counter of pools populated and kept by a sync, labelled with `pool_type`
+pub const METRIC_POOLS_POPULATED: &str = "pool_sync_pools_populated";
+/// Counter of rpc requests including retries, labelled with `method`
+pub const METRIC_RPC_CALLS: &str = "pool_sync_rpc_calls";
+/// Counter of failed rpc requests, labelled with `method`
+pub const METRIC_RPC_ERRORS: &str = "pool_sync_rpc_errors";
+/// Histogram of the seconds taken to fetch the logs of one block range chunk
+pub const METRIC_CHUNK_DURATION: &str = "pool_sync_chunk_duration_seconds";
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn pools_discovered(pool_type: PoolType, count: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(METRIC_POOLS_DISCOVERED, "pool_type" => pool_type.to_string())
+        .increment(count as u64);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn pools_populated(pool_type: PoolType, count: usize) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(METRIC_POOLS_POPULATED, "pool_type" => pool_type.to_string())
+        .increment(count as u64);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn rpc_call(method: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(METRIC_RPC_CALLS, "method" => method).increment(1);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn rpc_error(method: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(METRIC_RPC_ERRORS, "method" => method).increment(1);
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn chunk_duration(elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    ::metrics::histogram!(METRIC_CHUNK_DURATION).record(elapsed.as_secs_f64());
+}
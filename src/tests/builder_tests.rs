@@ -0,0 +1,161 @@
+#[cfg(test)]
+mod builder_test {
+    use crate::builder::{check_strategy, resolve_rate_limit};
+    use crate::tests::log_capture;
+    use crate::{Chain, PoolSync, PoolSyncError, PoolType, PopulationStrategy};
+    use alloy::primitives::Address;
+    use log::Level;
+
+    #[test]
+    fn test_build_with_zero_factory() {
+        // the factory of a local deployment left unset
+        let chain = Chain::Local {
+            chain_id: 31337,
+            factories: &[(PoolType::UniswapV3, Address::ZERO)],
+        };
+        let result = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(chain)
+            .build();
+        assert!(matches!(
+            result,
+            Err(PoolSyncError::MissingFactoryAddress(PoolType::UniswapV3, _))
+        ));
+    }
+
+    #[test]
+    fn test_build_without_pools() {
+        let result = PoolSync::builder().chain(Chain::Mantle).build();
+        assert!(matches!(result, Err(PoolSyncError::NoPoolTypesAdded)));
+    }
+
+    #[test]
+    fn test_unsupported_strategy() {
+        // a chain whose nodes reject state-override deploys
+        let result = check_strategy(
+            Chain::Mantle,
+            PopulationStrategy::DataSync,
+            &[PopulationStrategy::RpcBatch],
+        );
+        assert!(matches!(
+            result,
+            Err(PoolSyncError::UnsupportedStrategy {
+                chain: Chain::Mantle,
+                strategy: PopulationStrategy::DataSync,
+            })
+        ));
+
+        // Mantle serves both
+        for strategy in [PopulationStrategy::DataSync, PopulationStrategy::RpcBatch] {
+            let result = PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .population_strategy(strategy)
+                .build();
+            assert!(result.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_mantle_supported_pools() {
+        let pools = Chain::Mantle.supported_pools();
+        assert_eq!(
+            pools,
+            vec![PoolType::UniswapV3, PoolType::MerchantMoe, PoolType::Agni]
+        );
+        assert!(pools.iter().all(|pool| Chain::Mantle.supported(pool)));
+    }
+
+    /// Looks the variables up in `vars` instead of the process environment, which other
+    /// tests read concurrently through `build`
+    fn lookup<'a>(
+        vars: &'a [(&'a str, &'a str)],
+    ) -> impl Fn(&str) -> Result<Option<String>, PoolSyncError> + 'a {
+        move |key| {
+            Ok(vars
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_from_env() {
+        let pool_sync = PoolSync::from_vars(&lookup(&[
+            ("POOLSYNC_CHAIN", "mantle"),
+            ("POOLSYNC_POOLS", "uniswapv3, Agni"),
+            ("POOLSYNC_RATE_LIMIT", "25"),
+            ("POOLSYNC_START_BLOCK", "100"),
+            ("POOLSYNC_END_BLOCK", "200"),
+        ]))
+        .unwrap();
+        assert_eq!(pool_sync.chain, Chain::Mantle);
+        let mut pool_types: Vec<PoolType> = pool_sync.fetchers.keys().copied().collect();
+        pool_types.sort();
+        assert_eq!(pool_types, vec![PoolType::UniswapV3, PoolType::Agni]);
+        assert_eq!(pool_sync.rate_limit, 25);
+        assert_eq!(pool_sync.start_block, Some(100));
+        assert_eq!(pool_sync.end_block, Some(200));
+
+        let error = |vars: &[(&str, &str)]| match PoolSync::from_vars(&lookup(vars)) {
+            Err(PoolSyncError::EnvConfigError(message)) => message,
+            other => panic!("expected an env config error, got {:?}", other.err()),
+        };
+        assert!(error(&[("POOLSYNC_POOLS", "Agni")]).contains("POOLSYNC_CHAIN"));
+        assert!(error(&[("POOLSYNC_CHAIN", "base"), ("POOLSYNC_POOLS", "Agni")]).contains("base"));
+        assert!(error(&[("POOLSYNC_CHAIN", "mantle")]).contains("POOLSYNC_POOLS"));
+        assert!(error(&[
+            ("POOLSYNC_CHAIN", "mantle"),
+            ("POOLSYNC_POOLS", "Agni,Curve")
+        ])
+        .contains("Curve"));
+        assert!(error(&[
+            ("POOLSYNC_CHAIN", "mantle"),
+            ("POOLSYNC_POOLS", "Agni"),
+            ("POOLSYNC_END_BLOCK", "latest"),
+        ])
+        .contains("POOLSYNC_END_BLOCK"));
+    }
+
+    #[test]
+    fn test_resolve_rate_limit() {
+        // the environment overrides the builder's rate limit, which overrides the default
+        let env = [("POOLSYNC_RATE_LIMIT", "5")];
+        assert_eq!(resolve_rate_limit(&lookup(&env), Some(100)).unwrap(), 5);
+        assert_eq!(resolve_rate_limit(&lookup(&env), None).unwrap(), 5);
+        assert_eq!(resolve_rate_limit(&lookup(&[]), Some(100)).unwrap(), 100);
+        assert_eq!(resolve_rate_limit(&lookup(&[]), None).unwrap(), 20);
+
+        let invalid = [("POOLSYNC_RATE_LIMIT", "fast")];
+        assert!(matches!(
+            resolve_rate_limit(&lookup(&invalid), None),
+            Err(PoolSyncError::EnvConfigError(message)) if message.contains("POOLSYNC_RATE_LIMIT")
+        ));
+
+        log_capture::init();
+        assert_eq!(resolve_rate_limit(&lookup(&[]), Some(0)).unwrap(), 10000);
+        assert!(!log_capture::captured(Level::Warn, "without a rate limit").is_empty());
+    }
+
+    #[test]
+    fn test_chain_try_from_str() {
+        for chain in Chain::ALL {
+            let name = chain.to_string();
+            assert_eq!(Chain::try_from(name.as_str()).unwrap(), chain);
+            assert_eq!(
+                Chain::try_from(name.to_uppercase().as_str()).unwrap(),
+                chain
+            );
+        }
+        assert_eq!(Chain::try_from("mantle").unwrap(), Chain::Mantle);
+
+        match Chain::try_from("base") {
+            Err(e @ PoolSyncError::UnknownChain(_)) => {
+                let message = e.to_string();
+                assert!(message.contains("\"base\""));
+                assert!(message.contains("Mantle"));
+            }
+            other => panic!("expected an unknown chain error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod cache_test {
+    use crate::cache::{cache_file_path, read_cache_file, write_cache_file, PoolCache};
+    use crate::pools::pool_structures::tick_bitmap_codec;
+    use crate::tests::fixtures::*;
+    use crate::{Chain, PoolInfo, PoolSync, PoolType};
+    use alloy::primitives::{Bytes, U256};
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    /// 200 words where every other one was flipped back to zero, the rest with a bit set
+    /// near both ends of the word
+    fn sparse_bitmap() -> HashMap<i16, U256> {
+        (-100i16..100)
+            .map(|position| {
+                let word = match position % 2 {
+                    0 => U256::ZERO,
+                    _ => (U256::from(1u64) << 255) | U256::from(position.unsigned_abs()),
+                };
+                (position, word)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_load_cached_pools() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PoolCache {
+            last_synced_block: 100,
+            pool_type: PoolType::UniswapV3,
+            pools: vec![
+                v3_pool(PoolType::UniswapV3, addr(10), addr(1), addr(2)),
+                v3_pool(PoolType::UniswapV3, addr(11), addr(1), addr(3)),
+            ],
+            is_initial_sync: false,
+            next_creation_index: 2,
+            pending: Vec::new(),
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pools(&[PoolType::UniswapV3, PoolType::Agni])
+            .chain(Chain::Mantle)
+            .cache_dir(dir.path())
+            .build()
+            .unwrap();
+
+        // Agni has no cache file yet and contributes nothing
+        let mut pools = pool_sync.load_cached_pools().unwrap();
+        pools.sort_by_key(|pool| pool.address());
+        assert_eq!(pools.len(), 2);
+        assert_eq!(pools[0].address(), addr(10));
+        assert_eq!(pools[1].address(), addr(11));
+        assert!(pools
+            .iter()
+            .all(|pool| pool.pool_type() == PoolType::UniswapV3));
+    }
+
+    #[test]
+    fn test_tick_bitmap_codec_round_trip() {
+        let bitmap = sparse_bitmap();
+        let encoded = tick_bitmap_codec::encode(&bitmap);
+        assert_eq!(encoded.len(), 100 * 34);
+
+        let decoded = tick_bitmap_codec::decode(&encoded).unwrap();
+        let non_zero: HashMap<i16, U256> = bitmap
+            .into_iter()
+            .filter(|(_, word)| !word.is_zero())
+            .collect();
+        assert_eq!(decoded, non_zero);
+        assert!(tick_bitmap_codec::decode(&encoded[1..]).is_err());
+
+        // the cached form is smaller than the plain map of every word
+        let packed = Bytes::from(encoded).to_string();
+        let plain = serde_json::to_string(&sparse_bitmap()).unwrap();
+        assert!(
+            packed.len() < plain.len(),
+            "packed {} bytes, plain {} bytes",
+            packed.len(),
+            plain.len()
+        );
+    }
+
+    #[test]
+    fn test_cached_tick_bitmap_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pool = v3_pool(PoolType::Agni, addr(10), addr(1), addr(2));
+        pool.get_v3_mut().unwrap().tick_bitmap = sparse_bitmap();
+        let cache = PoolCache {
+            last_synced_block: 100,
+            pool_type: PoolType::Agni,
+            pools: vec![pool],
+            is_initial_sync: false,
+            next_creation_index: 1,
+            pending: Vec::new(),
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+
+        // only the cache file packs the bitmap, the pool itself serializes the plain map
+        let path = cache_file_path(dir.path(), &PoolType::Agni, Chain::Mantle);
+        let stored: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(stored["pools"][0]["Agni"]["tick_bitmap"].is_string());
+        assert!(serde_json::to_value(&cache.pools[0]).unwrap()["Agni"]["tick_bitmap"].is_object());
+
+        let reloaded = read_cache_file(&PoolType::Agni, Chain::Mantle, dir.path()).unwrap();
+        let bitmap = &reloaded.pools[0].get_v3().unwrap().tick_bitmap;
+        assert_eq!(bitmap.len(), 100);
+        assert_eq!(bitmap[&-99], (U256::from(1u64) << 255) | U256::from(99u64));
+
+        // cache files written with the plain map still load
+        std::fs::write(&path, serde_json::to_string(&cache).unwrap()).unwrap();
+        let legacy = read_cache_file(&PoolType::Agni, Chain::Mantle, dir.path()).unwrap();
+        assert_eq!(legacy.pools[0].get_v3().unwrap().tick_bitmap.len(), 200);
+    }
+}
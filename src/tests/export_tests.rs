@@ -0,0 +1,43 @@
+#[cfg(test)]
+mod export_test {
+    use serde_json::Value;
+
+    use crate::tests::fixtures::*;
+    use crate::{export_pools_json, KeyCase, Pool, PoolType};
+
+    #[test]
+    fn test_camel_case_export() {
+        let mut pool = v3_pool(PoolType::Agni, addr(10), addr(1), addr(2));
+        if let Pool::Agni(v3) = &mut pool {
+            v3.ticks.insert(-60, Default::default());
+            v3.extra.insert("fee_protocol".to_string(), "17".to_string());
+        }
+        let pools = vec![pool];
+
+        let exported: Value =
+            serde_json::from_str(&export_pools_json(&pools, KeyCase::Camel).unwrap()).unwrap();
+        let v3 = &exported[0]["Agni"];
+        for key in [
+            "token0Address",
+            "token1Address",
+            "token0Name",
+            "token0Decimals",
+            "sqrtPrice",
+            "tickSpacing",
+            "tickBitmap",
+            "lastUpdateBlock",
+        ] {
+            assert!(v3.get(key).is_some(), "missing {}", key);
+        }
+        assert!(v3.get("sqrt_price").is_none());
+        // the keys of the maps a pool holds are data, not field names
+        assert!(v3["ticks"]["-60"].get("liquidity_gross").is_some());
+        assert_eq!(v3["extra"]["fee_protocol"], "17");
+
+        // the default export keeps the cache layout
+        let exported: Value =
+            serde_json::from_str(&export_pools_json(&pools, KeyCase::Snake).unwrap()).unwrap();
+        assert_eq!(exported, serde_json::to_value(&pools).unwrap());
+        assert!(exported[0]["Agni"].get("sqrt_price").is_some());
+    }
+}
@@ -0,0 +1,628 @@
+#[cfg(test)]
+mod fetcher_test {
+    use alloy::dyn_abi::DynSolType;
+    use alloy::primitives::aliases::U112;
+    use alloy::primitives::{address, keccak256, Address, Log, B256, U256};
+    use alloy::providers::ProviderBuilder;
+    use alloy::rpc::types::Filter;
+    use alloy::sol_types::SolEvent;
+    use indicatif::ProgressBar;
+    use log::Level;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio::time::{Duration, Instant};
+
+    use crate::errors::PoolSyncError;
+    use crate::events::DataEvents;
+    use crate::pools::assign_creation_indices;
+    use crate::pools::gen::UniswapV3Factory;
+    use crate::pools::pool_fetchers::{AgniV3Fetcher, MerchantMoeV2Fetcher, UniswapV3Fetcher};
+    use crate::pools::pool_structures::v2_structure::MerchantMoeV2Pool;
+    use crate::pools::{
+        validate_event_signature, validate_factory_address, PoolFamily, PoolFetcher,
+    };
+    use crate::rpc::{AdaptiveRate, CircuitBreaker, CircuitBreakerConfig};
+    use crate::tests::fixtures::*;
+    use crate::tests::log_capture;
+    use crate::tests::mock_rpc::*;
+    use crate::{Chain, Pool, PoolInfo, PoolType, Rpc};
+
+    fn pool_created_topic() -> B256 {
+        keccak256("PoolCreated(address,address,uint24,int24,address)")
+    }
+
+    /// Fetcher whose creation event signature can be set per test
+    struct SignatureFetcher(&'static str);
+
+    impl PoolFetcher for SignatureFetcher {
+        fn pool_type(&self) -> PoolType {
+            PoolType::UniswapV3
+        }
+
+        fn family(&self) -> PoolFamily {
+            PoolFamily::V3
+        }
+
+        fn factory_address(&self, _chain: Chain) -> Address {
+            address!("0d922Fb1Bc191F64970ac40376643808b4B74Df9")
+        }
+
+        fn pair_created_signature(&self) -> &str {
+            self.0
+        }
+
+        fn log_to_address(&self, _log: &Log) -> Address {
+            Address::ZERO
+        }
+
+        fn get_pool_repr(&self) -> DynSolType {
+            DynSolType::Address
+        }
+    }
+
+    /// V3 fetcher replaying a custom list of data events
+    struct CustomEventsFetcher(Vec<&'static str>);
+
+    impl PoolFetcher for CustomEventsFetcher {
+        fn pool_type(&self) -> PoolType {
+            PoolType::UniswapV3
+        }
+
+        fn family(&self) -> PoolFamily {
+            PoolFamily::V3
+        }
+
+        fn factory_address(&self, chain: Chain) -> Address {
+            UniswapV3Fetcher.factory_address(chain)
+        }
+
+        fn pair_created_signature(&self) -> &str {
+            UniswapV3Fetcher.pair_created_signature()
+        }
+
+        fn log_to_address(&self, log: &Log) -> Address {
+            UniswapV3Fetcher.log_to_address(log)
+        }
+
+        fn get_pool_repr(&self) -> DynSolType {
+            UniswapV3Fetcher.get_pool_repr()
+        }
+
+        fn data_event_signatures(&self) -> Vec<&str> {
+            self.0.clone()
+        }
+    }
+
+    /// The topic0 alternatives of an `eth_getLogs` filter
+    fn queried_topics(params: &Value) -> Vec<B256> {
+        match &params[0]["topics"][0] {
+            Value::Array(topics) => topics
+                .iter()
+                .map(|topic| serde_json::from_value(topic.clone()).unwrap())
+                .collect(),
+            Value::Null => Vec::new(),
+            topic => vec![serde_json::from_value(topic.clone()).unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_builtin_signatures_are_valid() {
+        let topic = validate_event_signature(&UniswapV3Fetcher).unwrap();
+        assert_eq!(topic, pool_created_topic());
+    }
+
+    #[test]
+    fn test_wrong_signature_is_rejected() {
+        // `uint` is not canonical and would hash to a topic the factory never emits
+        let fetcher = SignatureFetcher("PoolCreated(address,address,uint,int24,address)");
+        assert!(matches!(
+            validate_event_signature(&fetcher),
+            Err(PoolSyncError::InvalidEventSignature(PoolType::UniswapV3, _))
+        ));
+
+        let fetcher = SignatureFetcher("PoolCreated(address, address)");
+        assert!(validate_event_signature(&fetcher).is_err());
+    }
+
+    #[test]
+    fn test_factory_addresses() {
+        // the factories deployed on Mantle, parsed with their checksum
+        let deployed = |address: &str| Address::parse_checksummed(address, None).unwrap();
+        let fetchers: [(&dyn PoolFetcher, &str); 3] = [
+            (
+                &UniswapV3Fetcher,
+                "0x0d922Fb1Bc191F64970ac40376643808b4B74Df9",
+            ),
+            (&AgniV3Fetcher, "0x25780dc8Fc3cfBD75F33bFDAB65e969b603b2035"),
+            (
+                &MerchantMoeV2Fetcher,
+                "0x5bEf015CA9424A7C07B68490616a4C1F094BEdEc",
+            ),
+        ];
+        for (fetcher, address) in fetchers {
+            assert_eq!(
+                validate_factory_address(fetcher, Chain::Mantle).unwrap(),
+                deployed(address),
+                "{}",
+                fetcher.pool_type()
+            );
+        }
+    }
+
+    #[test]
+    fn test_local_factory_address() {
+        // a local node without the factory has none
+        let local = Chain::Local {
+            chain_id: 31337,
+            factories: &[],
+        };
+        assert!(matches!(
+            validate_factory_address(&AgniV3Fetcher, local),
+            Err(PoolSyncError::MissingFactoryAddress(PoolType::Agni, _))
+        ));
+
+        // the one it was given is used
+        const FACTORY: Address = address!("00000000000000000000000000000000000000aa");
+        let local = Chain::Local {
+            chain_id: 31337,
+            factories: &[(PoolType::Agni, FACTORY)],
+        };
+        assert_eq!(
+            validate_factory_address(&AgniV3Fetcher, local).unwrap(),
+            FACTORY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_detects_mismatched_topic() {
+        // the factory emits a real PoolCreated event
+        let factory = address!("0d922Fb1Bc191F64970ac40376643808b4B74Df9");
+        let mock = MockRpc::start(move |method, _| match method {
+            "eth_getLogs" => {
+                MockResponse::Result(json!([log_json(factory, &[pool_created_topic()], &[], 10)]))
+            }
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let correct = Rpc::probe_event_signature(
+            0,
+            100,
+            provider.clone(),
+            Arc::new(UniswapV3Fetcher),
+            Chain::Mantle,
+        )
+        .await
+        .unwrap();
+        assert!(correct);
+
+        // a well formed but wrong signature never matches the emitted topic
+        let wrong = Rpc::probe_event_signature(
+            0,
+            100,
+            provider,
+            Arc::new(SignatureFetcher(
+                "PoolCreated(address,address,uint24,int24)",
+            )),
+            Chain::Mantle,
+        )
+        .await
+        .unwrap();
+        assert!(!wrong);
+        assert_eq!(mock.calls("eth_getLogs"), 2);
+        assert_ne!(
+            validate_event_signature(&SignatureFetcher(
+                "PoolCreated(address,address,uint24,int24)"
+            ))
+            .unwrap(),
+            B256::ZERO
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_creation_indices_follow_discovery_order() {
+        // one pool per 500 block chunk, earlier chunks answer last
+        let factory = address!("0d922Fb1Bc191F64970ac40376643808b4B74Df9");
+        let mock = MockRpc::start(move |method, params| match method {
+            "eth_getLogs" => {
+                let from = u64::from_str_radix(
+                    params[0]["fromBlock"]
+                        .as_str()
+                        .unwrap()
+                        .trim_start_matches("0x"),
+                    16,
+                )
+                .unwrap();
+                std::thread::sleep(std::time::Duration::from_millis((2000 - from) / 20));
+                let event = UniswapV3Factory::PoolCreated {
+                    token0: addr(1),
+                    token1: addr(2),
+                    fee: Default::default(),
+                    tickSpacing: Default::default(),
+                    pool: addr(1000 + from),
+                };
+                MockResponse::Result(json!([event_log(factory, &event, from)]))
+            }
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let addrs = Rpc::fetch_pool_addrs(
+            0,
+            1999,
+            provider,
+            Arc::new(UniswapV3Fetcher),
+            Chain::Mantle,
+            100,
+            &AdaptiveRate::new(100),
+            &CircuitBreaker::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(addrs, vec![addr(1000), addr(1500), addr(2000), addr(2500)]);
+
+        // population returns pools in any order, indices continue from the cache
+        let mut pools: Vec<Pool> = [2500, 1000, 2000, 1500]
+            .into_iter()
+            .map(|n| v3_pool(PoolType::UniswapV3, addr(n), addr(1), addr(2)))
+            .collect();
+        let indices: HashMap<Address, u64> = addrs
+            .iter()
+            .enumerate()
+            .map(|(i, address)| (*address, 7 + i as u64))
+            .collect();
+        assign_creation_indices(&mut pools, &indices);
+        pools.sort_by_key(|pool| pool.creation_index());
+        let ordered: Vec<Address> = pools.iter().map(|pool| pool.address()).collect();
+        assert_eq!(ordered, addrs);
+        assert_eq!(pools[0].creation_index(), 7);
+        assert_eq!(pools[3].creation_index(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_custom_data_event_signatures() {
+        let topics = Arc::new(Mutex::new(Vec::new()));
+        let recorded = topics.clone();
+        let mock = MockRpc::start(move |method, params| match method {
+            "eth_getLogs" => {
+                recorded.lock().unwrap().push(queried_topics(params));
+                MockResponse::Result(json!([]))
+            }
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let collect = "Collect(address,address,int24,int24,uint128,uint128)";
+        let fetcher = Arc::new(CustomEventsFetcher(vec![
+            collect,
+            DataEvents::Swap::SIGNATURE,
+        ]));
+        let mut pools = vec![v3_pool(PoolType::UniswapV3, addr(10), addr(1), addr(2))];
+        Rpc::populate_liquidity(
+            10,
+            20,
+            &mut pools,
+            provider.clone(),
+            fetcher.clone(),
+            10,
+            &AdaptiveRate::new(10),
+            &CircuitBreaker::default(),
+            false,
+        )
+        .await
+        .unwrap();
+        let mut expected = vec![keccak256(collect), DataEvents::Swap::SIGNATURE_HASH];
+        expected.sort();
+        assert!(!topics.lock().unwrap().is_empty());
+        for mut queried in topics.lock().unwrap().drain(..) {
+            queried.sort();
+            assert_eq!(queried, expected);
+        }
+
+        // the initial sync skips swaps, the populated state already reflects them
+        Rpc::populate_liquidity(
+            10,
+            20,
+            &mut pools,
+            provider,
+            fetcher,
+            10,
+            &AdaptiveRate::new(10),
+            &CircuitBreaker::default(),
+            true,
+        )
+        .await
+        .unwrap();
+        for queried in topics.lock().unwrap().iter() {
+            assert_eq!(queried, &vec![keccak256(collect)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_v2_pools_replay_sync_events() {
+        let pair = addr(10);
+        let mock = MockRpc::start(move |method, params| match method {
+            "eth_getLogs" => {
+                assert_eq!(
+                    queried_topics(params),
+                    vec![DataEvents::Sync::SIGNATURE_HASH]
+                );
+                let sync = DataEvents::Sync {
+                    reserve0: U112::from(500u64),
+                    reserve1: U112::from(700u64),
+                };
+                MockResponse::Result(json!([event_log(pair, &sync, 15)]))
+            }
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let mut pools = vec![Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                address: pair,
+                token0: addr(1),
+                token1: addr(2),
+                ..Default::default()
+            },
+        )];
+        Rpc::populate_liquidity(
+            15,
+            15,
+            &mut pools,
+            provider,
+            Arc::new(MerchantMoeV2Fetcher),
+            10,
+            &AdaptiveRate::new(10),
+            &CircuitBreaker::default(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let pool = pools[0].get_v2().unwrap();
+        assert_eq!(pool.token0_reserves, U256::from(500u64));
+        assert_eq!(pool.token1_reserves, U256::from(700u64));
+        assert_eq!(pool.last_update_block, 15);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_adapts_to_http_429() {
+        let rate = AdaptiveRate::new(8);
+        assert_eq!(rate.current_rate(), Some(8.0));
+        assert_eq!(rate.throttle(), Some(4.0));
+        assert_eq!(rate.throttle(), Some(2.0));
+        // the rate doubles back every 10s without a 429, up to the limit
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!((rate.current_rate().unwrap() - 4.0).abs() < 1e-9);
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(rate.current_rate(), Some(8.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_trips_and_probes() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            error_rate: 0.5,
+            window: 4,
+            cooldown: Duration::from_secs(30),
+        });
+        // under the window size nothing trips
+        breaker.record(false);
+        breaker.record(true);
+        breaker.record(true);
+        assert!(!breaker.is_open());
+        // 2 of the last 4 failed
+        breaker.record(false);
+        assert!(breaker.is_open());
+
+        let start = Instant::now();
+        breaker.acquire().await;
+        assert!(start.elapsed() >= Duration::from_secs(30));
+
+        // the first request after the cooldown is the probe, the others wait for it
+        let waiting = tokio::spawn({
+            let breaker = breaker.clone();
+            async move { breaker.acquire().await }
+        });
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(!waiting.is_finished());
+        breaker.record(false);
+        waiting.await.unwrap();
+        assert!(!breaker.is_open());
+
+        // a failed probe starts another cooldown
+        for _ in 0..4 {
+            breaker.record(true);
+        }
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        breaker.acquire().await;
+        breaker.record(true);
+        let start = Instant::now();
+        breaker.acquire().await;
+        assert!(start.elapsed() >= Duration::from_secs(30));
+
+        // a cancelled probe never reports, the next request probes once a cooldown passed
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        let probe = tokio::spawn({
+            let breaker = breaker.clone();
+            async move {
+                breaker.acquire().await;
+                std::future::pending::<()>().await
+            }
+        });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        probe.abort();
+        let start = Instant::now();
+        tokio::time::timeout(Duration::from_secs(60), breaker.acquire())
+            .await
+            .expect("the breaker waits for the cancelled probe forever");
+        assert!(start.elapsed() >= Duration::from_secs(29));
+        breaker.record(false);
+        assert!(!breaker.is_open());
+
+        // the default breaker never holds requests back
+        let disabled = CircuitBreaker::default();
+        disabled.record(true);
+        assert!(!disabled.is_open());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_log_fetch_pauses_on_error_spike() {
+        log_capture::init();
+        let responses = AtomicUsize::new(0);
+        let mock = MockRpc::start(move |method, _| match method {
+            "eth_getLogs" if responses.fetch_add(1, Ordering::SeqCst) < 3 => {
+                MockResponse::Http(503)
+            }
+            "eth_getLogs" => MockResponse::Result(json!([])),
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            error_rate: 0.5,
+            window: 2,
+            cooldown: Duration::from_secs(60),
+        });
+
+        let start = Instant::now();
+        let logs = Rpc::fetch_event_logs(
+            50,
+            50,
+            500,
+            provider,
+            10,
+            &AdaptiveRate::new(10),
+            &breaker,
+            Arc::new(ProgressBar::hidden()),
+            Filter::new(),
+        )
+        .await
+        .unwrap();
+        assert!(logs.is_empty());
+        assert_eq!(mock.calls("eth_getLogs"), 4);
+        // the second failure trips the breaker and the failed probe trips it again, each
+        // holding the retries back for the cooldown instead of the few seconds of backoff.
+        // Paused time also jumps ahead while the mock answers, so only the lower bound holds
+        assert!(start.elapsed() >= Duration::from_secs(120));
+        assert_eq!(
+            log_capture::captured(Level::Warn, "pausing requests for 60s").len(),
+            2
+        );
+        assert!(!breaker.is_open());
+    }
+
+    #[tokio::test]
+    async fn test_log_fetch_slows_down_on_http_429() {
+        log_capture::init();
+        let responses = AtomicUsize::new(0);
+        let mock = MockRpc::start(move |method, _| match method {
+            "eth_getLogs" if responses.fetch_add(1, Ordering::SeqCst) < 2 => {
+                MockResponse::Http(429)
+            }
+            "eth_getLogs" => MockResponse::Result(json!([])),
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let logs = Rpc::fetch_event_logs(
+            50,
+            50,
+            500,
+            provider,
+            10,
+            &AdaptiveRate::new(10),
+            &CircuitBreaker::default(),
+            Arc::new(ProgressBar::hidden()),
+            Filter::new(),
+        )
+        .await
+        .unwrap();
+        assert!(logs.is_empty());
+        assert_eq!(mock.calls("eth_getLogs"), 3);
+        // each 429 halves the rate, which recovers slightly during the retry backoff
+        let lowered = log_capture::captured(Level::Warn, "HTTP 429, lowering the request rate");
+        let rates: Vec<f64> = lowered
+            .iter()
+            .map(|message| {
+                let rate = message.rsplit(' ').next().unwrap();
+                rate.trim_end_matches("/s").parse().unwrap()
+            })
+            .collect();
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates[0], 5.0);
+        assert!(rates[1] > 2.5 && rates[1] < 3.0, "{:?}", rates);
+    }
+
+    #[tokio::test]
+    async fn test_log_fetch_splits_size_capped_ranges() {
+        // the node answers at most 4 blocks at once, like a node capping the results
+        let factory = UniswapV3Fetcher.factory_address(Chain::Mantle);
+        let created = [100, 103, 111, 117, 124, 130];
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let queried = ranges.clone();
+        let mock = MockRpc::start(move |method, params| match method {
+            "eth_getLogs" => {
+                let block = |key: &str| {
+                    let hex = params[0][key].as_str().unwrap().trim_start_matches("0x");
+                    u64::from_str_radix(hex, 16).unwrap()
+                };
+                let (from, to) = (block("fromBlock"), block("toBlock"));
+                queried.lock().unwrap().push((from, to));
+                if to - from >= 4 {
+                    return MockResponse::Error(
+                        -32005,
+                        "query returned more than 10000 results".into(),
+                    );
+                }
+                let logs: Vec<_> = created
+                    .iter()
+                    .filter(|block| (from..=to).contains(*block))
+                    .map(|block| {
+                        let event = UniswapV3Factory::PoolCreated {
+                            token0: addr(1),
+                            token1: addr(2),
+                            fee: Default::default(),
+                            tickSpacing: Default::default(),
+                            pool: addr(*block),
+                        };
+                        event_log(factory, &event, *block)
+                    })
+                    .collect();
+                MockResponse::Result(json!(logs))
+            }
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let addrs = Rpc::fetch_pool_addrs(
+            100,
+            130,
+            provider,
+            Arc::new(UniswapV3Fetcher),
+            Chain::Mantle,
+            10,
+            &AdaptiveRate::new(10),
+            &CircuitBreaker::default(),
+        )
+        .await
+        .unwrap();
+        let expected: Vec<Address> = created.iter().map(|block| addr(*block)).collect();
+        assert_eq!(addrs, expected);
+        // each capped range was split in two instead of being retried
+        let mut ranges = ranges.lock().unwrap().clone();
+        let queries = ranges.len();
+        ranges.sort();
+        ranges.dedup();
+        assert_eq!(ranges.len(), queries);
+        assert!(queries > 8);
+    }
+}
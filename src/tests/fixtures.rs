@@ -0,0 +1,37 @@
+//! Shared constructors for pools and logs used across the unit tests
+
+use alloy::primitives::{Address, U160};
+use alloy::rpc::types::Log;
+use alloy::sol_types::SolEvent;
+
+use crate::{Pool, PoolType, UniswapV3Pool};
+
+/// A deterministic address derived from a small integer
+pub fn addr(n: u64) -> Address {
+    Address::from(U160::from(n))
+}
+
+/// A bare V3 pool of the given type with only its addresses set
+pub fn v3_pool(pool_type: PoolType, address: Address, token0: Address, token1: Address) -> Pool {
+    Pool::new_v3(
+        pool_type,
+        UniswapV3Pool {
+            address,
+            token0,
+            token1,
+            ..Default::default()
+        },
+    )
+}
+
+/// An rpc log carrying the encoded event, emitted by `address` at `block_number`
+pub fn event_log<E: SolEvent>(address: Address, event: &E, block_number: u64) -> Log {
+    Log {
+        inner: alloy::primitives::Log {
+            address,
+            data: event.encode_log_data(),
+        },
+        block_number: Some(block_number),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,53 @@
+//! Process wide logger that records every message so tests can assert on log output
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::{Mutex, OnceLock};
+
+struct CaptureLogger {
+    records: Mutex<Vec<(Level, String)>>,
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.records
+            .lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: OnceLock<CaptureLogger> = OnceLock::new();
+
+/// Install the capturing logger, a no-op after the first call
+pub fn init() {
+    let logger = LOGGER.get_or_init(|| CaptureLogger {
+        records: Mutex::new(Vec::new()),
+    });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+/// Captured messages at `level` containing `needle`. Tests share the logger, so filter on
+/// something unique to the test
+pub fn captured(level: Level, needle: &str) -> Vec<String> {
+    LOGGER
+        .get()
+        .map(|logger| {
+            logger
+                .records
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(lvl, message)| *lvl == level && message.contains(needle))
+                .map(|(_, message)| message.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
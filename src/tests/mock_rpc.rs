@@ -0,0 +1,225 @@
+//! Minimal JSON-RPC over HTTP mock used by the unit tests
+//!
+//! Every request (single or batched) is routed to a handler closure keyed on the
+//! RPC method, so tests can script provider behaviour without a live node.
+
+use alloy::primitives::{Address, Bytes, B256};
+use alloy::sol_types::SolValue;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The scripted outcome of a single RPC call
+pub enum MockResponse {
+    /// A successful JSON-RPC result
+    Result(Value),
+    /// A JSON-RPC error object with the given code and message
+    Error(i64, String),
+    /// A bare http response with the given status and no JSON-RPC body, failing the whole
+    /// request when it is part of a batch
+    Http(u16),
+}
+
+type Handler = dyn Fn(&str, &Value) -> MockResponse + Send + Sync;
+
+/// The raw header lines of every http request received
+type Headers = Arc<Mutex<Vec<String>>>;
+
+/// A running mock endpoint, shut down when dropped
+pub struct MockRpc {
+    url: String,
+    calls: Arc<Mutex<HashMap<String, usize>>>,
+    headers: Headers,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MockRpc {
+    /// Start a mock endpoint on a random local port
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &Value) -> MockResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let calls = Arc::new(Mutex::new(HashMap::new()));
+        let headers = Headers::default();
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let task_calls = calls.clone();
+        let task_headers = headers.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let calls = task_calls.clone();
+                let headers = task_headers.clone();
+                tokio::spawn(serve_connection(stream, handler, calls, headers));
+            }
+        });
+
+        Self {
+            url,
+            calls,
+            headers,
+            task,
+        }
+    }
+
+    /// The http url of the endpoint
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Number of times the given method has been called
+    pub fn calls(&self, method: &str) -> usize {
+        self.calls.lock().unwrap().get(method).copied().unwrap_or(0)
+    }
+
+    /// Values of the named header across every request received, matched case-insensitively
+    pub fn header_values(&self, name: &str) -> Vec<String> {
+        self.headers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case(name)
+                    .then(|| value.trim().to_string())
+            })
+            .collect()
+    }
+}
+
+impl Drop for MockRpc {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    handler: Arc<Handler>,
+    calls: Arc<Mutex<HashMap<String, usize>>>,
+    received_headers: Headers,
+) {
+    let mut buf = Vec::new();
+    loop {
+        // read until the end of the headers
+        let header_end = loop {
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            let mut chunk = [0u8; 4096];
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        };
+
+        let raw_headers = String::from_utf8_lossy(&buf[..header_end]).to_string();
+        received_headers
+            .lock()
+            .unwrap()
+            .extend(raw_headers.lines().skip(1).map(str::to_string));
+        let headers = raw_headers.to_lowercase();
+        let content_length = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .and_then(|len| len.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+
+        while buf.len() < header_end + content_length {
+            let mut chunk = [0u8; 4096];
+            match stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+
+        let body: Value =
+            serde_json::from_slice(&buf[header_end..header_end + content_length]).unwrap();
+        buf.drain(..header_end + content_length);
+
+        let response = match body {
+            Value::Array(requests) => requests
+                .iter()
+                .map(|request| respond(request, &handler, &calls))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            request => respond(&request, &handler, &calls),
+        };
+
+        let reply = match response {
+            Ok(response) => {
+                let body = response.to_string();
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            }
+            Err(status) => format!("HTTP/1.1 {} Mock\r\ncontent-length: 0\r\n\r\n", status),
+        };
+        if stream.write_all(reply.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+// The JSON-RPC response to a request, or the http status to fail it with
+fn respond(
+    request: &Value,
+    handler: &Arc<Handler>,
+    calls: &Arc<Mutex<HashMap<String, usize>>>,
+) -> Result<Value, u16> {
+    let method = request["method"].as_str().unwrap_or_default();
+    *calls.lock().unwrap().entry(method.to_string()).or_default() += 1;
+
+    match handler(method, &request["params"]) {
+        MockResponse::Result(result) => Ok(json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": result,
+        })),
+        MockResponse::Error(code, message) => Ok(json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "error": { "code": code, "message": message },
+        })),
+        MockResponse::Http(status) => Err(status),
+    }
+}
+
+/// Encode a log in the shape returned by `eth_getLogs`
+pub fn log_json(address: Address, topics: &[B256], data: &[u8], block_number: u64) -> Value {
+    json!({
+        "address": address,
+        "topics": topics,
+        "data": Bytes::copy_from_slice(data),
+        "blockNumber": format!("{:#x}", block_number),
+        "blockHash": B256::ZERO,
+        "transactionHash": B256::ZERO,
+        "transactionIndex": "0x0",
+        "logIndex": "0x0",
+        "removed": false,
+    })
+}
+
+/// The `to` address (`None` for a deploy) and calldata of an `eth_call` request
+pub fn call_target(params: &Value) -> (Option<Address>, Bytes) {
+    let call = &params[0];
+    let to = serde_json::from_value(call["to"].clone()).ok();
+    let input = call
+        .get("input")
+        .or_else(|| call.get("data"))
+        .map(|input| serde_json::from_value(input.clone()).unwrap())
+        .unwrap_or_default();
+    (to, input)
+}
+
+/// ABI encode a value as the return data of an `eth_call`
+pub fn call_result<V: SolValue>(value: V) -> Value {
+    json!(Bytes::from(value.abi_encode()))
+}
@@ -1,2 +1,15 @@
 mod abi_gen;
-mod data_tests;
\ No newline at end of file
+mod builder_tests;
+mod cache_tests;
+mod data_tests;
+mod export_tests;
+mod fetcher_tests;
+mod pool_tests;
+mod population_tests;
+mod sync_tests;
+#[cfg(test)]
+mod fixtures;
+#[cfg(test)]
+mod log_capture;
+#[cfg(test)]
+mod mock_rpc;
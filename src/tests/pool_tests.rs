@@ -0,0 +1,1273 @@
+#[cfg(test)]
+mod pool_test {
+    use alloy::dyn_abi::DynSolValue;
+    use alloy::primitives::aliases::{I24, U112};
+    use alloy::primitives::{address, Address, I256, U160, U256};
+
+    use crate::events::DataEvents;
+    use crate::pools::pool_structures::v2_structure::{process_sync_data, MerchantMoeV2Pool};
+    use crate::pools::pool_structures::v3_structure::{
+        flip_tick, modify_position, process_tick_data,
+    };
+    use crate::tests::fixtures::*;
+    use crate::{
+        consolidated_price, group_by_pair, pools_by_family, pools_changed_since, pools_with_fee,
+        quote_route, Chain, Pool, PoolFamily, PoolInfo, PoolSyncError, PoolType, UniswapV3Pool,
+        MANTLE_BLOCK_TIME,
+    };
+    use proptest::prelude::*;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+    use uniswap_v3_math::tick_math;
+
+    /// The tuple layout returned by the V3 data-sync contract
+    fn v3_tuple(decimals0: u64, decimals1: u64, sqrt_price: U256) -> Vec<DynSolValue> {
+        vec![
+            DynSolValue::Address(addr(100)),
+            DynSolValue::Address(addr(1)),
+            DynSolValue::Uint(U256::from(decimals0), 8),
+            DynSolValue::Address(addr(2)),
+            DynSolValue::Uint(U256::from(decimals1), 8),
+            DynSolValue::Uint(U256::from(1_000u64), 128),
+            DynSolValue::Uint(sqrt_price, 160),
+            DynSolValue::Int(I256::ZERO, 24),
+            DynSolValue::Int(I256::try_from(10).unwrap(), 24),
+            DynSolValue::Uint(U256::from(3000u64), 24),
+        ]
+    }
+
+    fn mint(tick_lower: i32, tick_upper: i32, amount: u128) -> DataEvents::Mint {
+        DataEvents::Mint {
+            sender: addr(1),
+            owner: addr(1),
+            tickLower: I24::try_from(tick_lower).unwrap(),
+            tickUpper: I24::try_from(tick_upper).unwrap(),
+            amount,
+            amount0: U256::ZERO,
+            amount1: U256::ZERO,
+        }
+    }
+
+    fn swap(tick: i32, liquidity: u128) -> DataEvents::Swap {
+        DataEvents::Swap {
+            sender: addr(1),
+            recipient: addr(1),
+            amount0: Default::default(),
+            amount1: Default::default(),
+            sqrtPriceX96: U160::from(1u64) << 96,
+            liquidity,
+            tick: I24::try_from(tick).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_last_update_block_tracks_latest_event() {
+        let mut pool = v3_pool(PoolType::UniswapV3, addr(100), addr(1), addr(2));
+        let address = pool.address();
+        let v3 = pool.get_v3_mut().unwrap();
+        v3.tick_spacing = 10;
+        assert_eq!(v3.last_update_block, 0);
+
+        process_tick_data(
+            v3,
+            event_log(address, &mint(-10, 10, 100), 5),
+            PoolType::UniswapV3,
+            true,
+        );
+        assert_eq!(v3.last_update_block, 5);
+
+        process_tick_data(
+            v3,
+            event_log(address, &swap(3, 100), 12),
+            PoolType::UniswapV3,
+            false,
+        );
+        assert_eq!(v3.last_update_block, 12);
+
+        // an older event never moves the marker backwards
+        process_tick_data(
+            v3,
+            event_log(address, &mint(-20, 20, 5), 8),
+            PoolType::UniswapV3,
+            false,
+        );
+        assert_eq!(pool.last_update_block(), 12);
+
+        let mut v2 = MerchantMoeV2Pool::default();
+        let sync = DataEvents::Sync {
+            reserve0: U112::from(10u64),
+            reserve1: U112::from(20u64),
+        };
+        process_sync_data(
+            &mut v2,
+            event_log(addr(200), &sync, 42),
+            PoolType::MerchantMoe,
+        );
+        assert_eq!(Pool::MerchantMoe(v2).last_update_block(), 42);
+    }
+
+    #[test]
+    fn test_unknown_decimals_propagate_to_prices() {
+        // sqrt price of 1 with 18/6 decimals is a price of 1e12
+        let one = U256::from(1u64) << 96;
+        let pool = UniswapV3Pool::from(v3_tuple(18, 6, one).as_slice());
+        assert_eq!(pool.token0_decimals, Some(18));
+        let price = pool.price_token0_in_token1().unwrap();
+        assert!((price - 1e12).abs() / 1e12 < 1e-9);
+
+        // an out of range value is marked unknown instead of assuming 18
+        let pool = UniswapV3Pool::from(v3_tuple(300, 6, one).as_slice());
+        assert_eq!(pool.token0_decimals, None);
+        assert_eq!(pool.price_token0_in_token1(), None);
+
+        let pool = Pool::new_v3(PoolType::Agni, pool);
+        assert_eq!(pool.token0_decimals(), None);
+        assert_eq!(pool.price_token1_in_token0(), None);
+
+        let v2 = MerchantMoeV2Pool {
+            token0_decimals: Some(18),
+            token1_decimals: None,
+            token0_reserves: U256::from(10u64),
+            token1_reserves: U256::from(20u64),
+            ..Default::default()
+        };
+        assert_eq!(v2.price_token0_in_token1(), None);
+    }
+
+    #[test]
+    fn test_virtual_reserves() {
+        let q96 = U256::from(1) << 96;
+        let liquidity = 1_000_000_000_000_000_000u128;
+        let mut pool = UniswapV3Pool {
+            liquidity,
+            sqrt_price: q96,
+            ..Default::default()
+        };
+        // at a price of 1 both reserves equal the liquidity
+        assert_eq!(
+            pool.virtual_reserves(),
+            (U256::from(liquidity), U256::from(liquidity))
+        );
+
+        // a price of 4 halves reserve0 and doubles reserve1, keeping x * y = L^2
+        pool.sqrt_price = q96 * U256::from(2);
+        let (reserve0, reserve1) = pool.virtual_reserves();
+        assert_eq!(reserve0, U256::from(liquidity / 2));
+        assert_eq!(reserve1, U256::from(liquidity * 2));
+        assert_eq!(
+            reserve0 * reserve1,
+            U256::from(liquidity).pow(U256::from(2))
+        );
+
+        pool.sqrt_price = U256::ZERO;
+        assert_eq!(pool.virtual_reserves(), (U256::ZERO, U256::ZERO));
+    }
+
+    #[test]
+    fn test_tick_for_price_round_trip() {
+        let mut pool = UniswapV3Pool {
+            token0_decimals: Some(18),
+            token1_decimals: Some(6),
+            tick_spacing: 60,
+            ..Default::default()
+        };
+        let price_at = |pool: &mut UniswapV3Pool, tick: i32| {
+            pool.sqrt_price = tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+            pool.price_token0_in_token1().unwrap()
+        };
+
+        for target in [1e12, 2_500e12, 3.1e9, 0.0004] {
+            // rounding to the spacing moves the price by at most half a spacing of ticks
+            let tick = pool.tick_for_price(target).unwrap();
+            assert_eq!(tick % 60, 0);
+            let price = price_at(&mut pool, tick);
+            assert!(
+                (price / target).ln().abs() <= 30.0 * 1.0001f64.ln(),
+                "{} {}",
+                target,
+                price
+            );
+
+            // the exact sqrt price round trips within float precision
+            pool.sqrt_price = pool.sqrt_price_for_price(target).unwrap();
+            let price = pool.price_token0_in_token1().unwrap();
+            assert!((price - target).abs() / target < 1e-9);
+        }
+
+        // an exact tick maps back to itself
+        let price = price_at(&mut pool, -600);
+        assert_eq!(pool.tick_for_price(price), Some(-600));
+
+        // prices beyond the representable range give the outermost usable tick
+        assert_eq!(pool.tick_for_price(1e300), Some(887220));
+        assert_eq!(pool.sqrt_price_for_price(1e300), None);
+        assert_eq!(pool.tick_for_price(0.0), None);
+        pool.token1_decimals = None;
+        assert_eq!(pool.tick_for_price(1e12), None);
+    }
+
+    #[test]
+    fn test_base_token() {
+        let bases = Chain::Mantle.default_base_tokens();
+        let wmnt = address!("78c1b0C915c4FAA5FffA6CAbf0219DA63d7f4cb8");
+        let usdc = address!("09Bc4E0D864854c6aFB6eB9A9cdF58aC190D0dF9");
+
+        let pool = v3_pool(PoolType::Agni, addr(100), addr(1), wmnt);
+        assert_eq!(pool.base_token(&bases), Some(wmnt));
+
+        // neither or both tokens in the set is ambiguous
+        let pool = v3_pool(PoolType::Agni, addr(101), addr(1), addr(2));
+        assert_eq!(pool.base_token(&bases), None);
+        let pool = v3_pool(PoolType::Agni, addr(102), wmnt, usdc);
+        assert_eq!(pool.base_token(&bases), None);
+    }
+
+    #[test]
+    fn test_next_initialized_tick_across_words() {
+        // spacing 10 puts these ticks in words -2, 0 and 1 of the bitmap
+        let mut pool = UniswapV3Pool {
+            tick_spacing: 10,
+            ..Default::default()
+        };
+        assert_eq!(pool.next_initialized_tick(0, true), None);
+        for tick in [-2600, 50, 3000] {
+            flip_tick(&mut pool, tick, 10);
+        }
+
+        assert_eq!(pool.next_initialized_tick(0, true), Some(-2600));
+        assert_eq!(pool.next_initialized_tick(-5, true), Some(-2600));
+        assert_eq!(pool.next_initialized_tick(0, false), Some(50));
+        assert_eq!(pool.next_initialized_tick(50, true), Some(50));
+        assert_eq!(pool.next_initialized_tick(50, false), Some(3000));
+        assert_eq!(pool.next_initialized_tick(2999, false), Some(3000));
+        assert_eq!(pool.next_initialized_tick(-2600, false), Some(50));
+        assert_eq!(pool.next_initialized_tick(3000, false), None);
+        assert_eq!(pool.next_initialized_tick(-2601, true), None);
+    }
+
+    #[test]
+    fn test_truncate_ticks_keeps_nearest() {
+        let mut pool = UniswapV3Pool {
+            tick_spacing: 10,
+            tick: 25,
+            ..Default::default()
+        };
+        // positions [-100, 100], [-50, 50], [0, 30] and [20, 300]
+        for (lower, upper) in [(-100, 100), (-50, 50), (0, 30), (20, 300)] {
+            modify_position(&mut pool, lower, upper, 1000, true);
+        }
+        assert_eq!(pool.ticks.len(), 8);
+        assert!(!pool.truncate_ticks(8));
+        assert!(!pool.truncated);
+
+        assert!(pool.truncate_ticks(4));
+        assert!(pool.truncated);
+        let mut kept: Vec<i32> = pool.ticks.keys().copied().collect();
+        kept.sort();
+        assert_eq!(kept, vec![0, 20, 30, 50]);
+
+        // the bitmap only reflects the remaining ticks
+        assert_eq!(pool.next_initialized_tick(25, true), Some(20));
+        assert_eq!(pool.next_initialized_tick(-1, true), None);
+        assert_eq!(pool.next_initialized_tick(50, false), None);
+    }
+
+    #[test]
+    fn test_liquidity_within_range() {
+        let mut pool = UniswapV3Pool {
+            tick_spacing: 10,
+            tick: 5,
+            liquidity: 1500,
+            ..Default::default()
+        };
+        modify_position(&mut pool, -100, 100, 1000, true);
+        modify_position(&mut pool, -20, 20, 500, true);
+
+        assert_eq!(pool.liquidity_within_range(-20, 20), 1500 * 4);
+        assert_eq!(pool.liquidity_within_range(-100, -20), 1000 * 8);
+        assert_eq!(
+            pool.liquidity_within_range(-40, 40),
+            1000 * 2 + 1500 * 4 + 1000 * 2
+        );
+        assert_eq!(pool.liquidity_within_range(100, 200), 0);
+        assert_eq!(pool.liquidity_within_range(20, -20), 0);
+    }
+
+    #[test]
+    fn test_liquidity_distribution() {
+        let mut pool = UniswapV3Pool {
+            tick_spacing: 10,
+            tick: 5,
+            liquidity: 100,
+            ..Default::default()
+        };
+        // active liquidity is 100 over [-20, 10), 140 over [10, 30) and 40 over [30, 50)
+        modify_position(&mut pool, -20, 30, 100, true);
+        modify_position(&mut pool, 10, 50, 40, true);
+
+        let distribution = pool.liquidity_distribution(20);
+        assert_eq!(
+            distribution,
+            vec![(-20, 100 * 2), (0, 100 + 140), (20, 140 + 40), (40, 40),]
+        );
+        let total: u128 = distribution.iter().map(|(_, depth)| depth).sum();
+        assert_eq!(total, pool.liquidity_within_range(-20, 60));
+
+        // one bucket per spacing matches the per tick depth
+        for (lower, depth) in pool.liquidity_distribution(10) {
+            assert_eq!(depth, pool.liquidity_within_range(lower, lower + 10));
+        }
+        assert!(pool.liquidity_distribution(0).is_empty());
+        assert!(UniswapV3Pool::default()
+            .liquidity_distribution(20)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_agni_lp_fee() {
+        let pool = UniswapV3Pool {
+            fee: 2500,
+            protocol_fee: (3300 << 16) | 3200,
+            ..Default::default()
+        };
+        // agni takes a share in 1/10000ths of the fee per direction
+        assert_eq!(pool.lp_fee(PoolType::Agni, true), 1700);
+        assert_eq!(pool.lp_fee(PoolType::Agni, false), 1675);
+
+        // MerchantMoe has no protocol fee
+        let pair = Pool::new_v2(PoolType::MerchantMoe, MerchantMoeV2Pool::default());
+        assert_eq!(pair.lp_fee(true), 3000);
+    }
+
+    #[test]
+    fn test_group_by_pair_across_protocols() {
+        let (a, b, c) = (addr(1), addr(2), addr(3));
+        let pools = vec![
+            v3_pool(PoolType::UniswapV3, addr(10), a, b),
+            v3_pool(PoolType::Agni, addr(11), a, b),
+            Pool::new_v2(
+                PoolType::MerchantMoe,
+                MerchantMoeV2Pool {
+                    address: addr(12),
+                    // listed in the opposite order
+                    token0: b,
+                    token1: a,
+                    ..Default::default()
+                },
+            ),
+            v3_pool(PoolType::UniswapV3, addr(13), a, c),
+        ];
+
+        let groups = group_by_pair(&pools);
+        assert_eq!(groups.len(), 2);
+        let types: Vec<PoolType> = groups[&(a, b)]
+            .iter()
+            .map(|pool| pool.pool_type())
+            .collect();
+        assert_eq!(
+            types,
+            vec![PoolType::UniswapV3, PoolType::Agni, PoolType::MerchantMoe]
+        );
+        assert_eq!(groups[&(a, c)].len(), 1);
+        assert!(!groups.contains_key(&(b, a)));
+    }
+
+    #[test]
+    fn test_pools_by_family() {
+        let v2 = |address| {
+            Pool::new_v2(
+                PoolType::MerchantMoe,
+                MerchantMoeV2Pool {
+                    address,
+                    ..Default::default()
+                },
+            )
+        };
+        let pools = vec![
+            v3_pool(PoolType::UniswapV3, addr(10), addr(1), addr(2)),
+            v2(addr(11)),
+            v3_pool(PoolType::Agni, addr(12), addr(1), addr(2)),
+            v2(addr(13)),
+        ];
+
+        let addresses = |family| -> Vec<Address> {
+            pools_by_family(&pools, family)
+                .iter()
+                .map(|pool| pool.address())
+                .collect()
+        };
+        assert_eq!(addresses(PoolFamily::V3), vec![addr(10), addr(12)]);
+        assert_eq!(addresses(PoolFamily::V2), vec![addr(11), addr(13)]);
+        assert!(pools_by_family(&[], PoolFamily::V3).is_empty());
+    }
+
+    #[test]
+    fn test_quote_route_v3_then_v2() {
+        let (a, b, c) = (addr(1), addr(2), addr(3));
+        let liquidity = 10u128.pow(18);
+
+        // a/b at price 1 with all liquidity in [-1000, 1000)
+        let mut v3 = UniswapV3Pool {
+            address: addr(10),
+            token0: a,
+            token1: b,
+            liquidity,
+            sqrt_price: U256::from(1u64) << 96,
+            fee: 3000,
+            tick_spacing: 10,
+            ..Default::default()
+        };
+        modify_position(&mut v3, -1000, 1000, liquidity as i128, true);
+        let v3 = Pool::new_v3(PoolType::UniswapV3, v3);
+
+        // b/c at price 2
+        let v2 = Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                address: addr(11),
+                token0: b,
+                token1: c,
+                token0_reserves: U256::from(10u128.pow(21)),
+                token1_reserves: U256::from(2 * 10u128.pow(21)),
+                ..Default::default()
+            },
+        );
+
+        let amount_in = 10f64.powi(15);
+        let quoted = quote_route(&[&v3, &v2], U256::from(10u128.pow(15)), &[a, b, c]).unwrap();
+
+        // constant liquidity: out = L * x / (L + x), then x * 2R / (R + x) on the pair
+        let x = amount_in * 0.997;
+        let hop1 = liquidity as f64 * x / (liquidity as f64 + x);
+        let x = hop1 * 0.997;
+        let expected = x * 2e21 / (1e21 + x);
+        let quoted = f64::from(quoted);
+        assert!(
+            (quoted - expected).abs() / expected < 1e-9,
+            "{} vs {}",
+            quoted,
+            expected
+        );
+
+        // the path has to follow the pools
+        assert!(quote_route(&[&v3, &v2], U256::from(1u64), &[a, c, b]).is_err());
+        assert!(quote_route(&[&v3], U256::from(1u64), &[a, b, c]).is_err());
+    }
+
+    #[test]
+    fn test_fee_fraction() {
+        let v3 = |pool_type, fee| {
+            let pool = UniswapV3Pool {
+                fee,
+                ..Default::default()
+            };
+            Pool::new_v3(pool_type, pool)
+        };
+        assert_eq!(v3(PoolType::UniswapV3, 3000).fee_fraction(), 0.003);
+        assert_eq!(v3(PoolType::UniswapV3, 500).fee_fraction(), 0.0005);
+        assert_eq!(v3(PoolType::Agni, 2500).fee_fraction(), 0.0025);
+        assert_eq!(v3(PoolType::Agni, 100).fee_fraction(), 0.0001);
+        assert_eq!(
+            Pool::MerchantMoe(MerchantMoeV2Pool::default()).fee_fraction(),
+            0.003
+        );
+    }
+
+    #[test]
+    fn test_pool_id() {
+        let address = address!("5BEF015CA9424A7C07B68490616A4C1F094BEDEC");
+        let pools = [
+            v3_pool(PoolType::UniswapV3, address, addr(1), addr(2)),
+            v3_pool(PoolType::Agni, address, addr(1), addr(2)),
+            Pool::new_v2(
+                PoolType::MerchantMoe,
+                MerchantMoeV2Pool {
+                    address,
+                    ..Default::default()
+                },
+            ),
+        ];
+        let ids: Vec<String> = pools
+            .iter()
+            .map(|pool| pool.pool_id(Chain::Mantle))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                "mantle:uniswapv3:0x5bef015ca9424a7c07b68490616a4c1f094bedec",
+                "mantle:agni:0x5bef015ca9424a7c07b68490616a4c1f094bedec",
+                "mantle:merchantmoe:0x5bef015ca9424a7c07b68490616a4c1f094bedec",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pool_age() {
+        let mut pool = v3_pool(PoolType::UniswapV3, addr(10), addr(1), addr(2));
+        // pools cached before creation blocks were tracked have no age
+        assert_eq!(pool.age_blocks(1_000), None);
+        assert_eq!(pool.age_duration(1_000), None);
+
+        pool.set_created_at_block(400);
+        assert_eq!(pool.age_blocks(1_000), Some(600));
+        assert_eq!(pool.age_duration(1_000), Some(Duration::from_secs(1_200)));
+        assert_eq!(pool.age_blocks(400), Some(0));
+        assert_eq!(pool.age_duration(400), Some(Duration::ZERO));
+        assert_eq!(pool.age_blocks(399), None);
+
+        let v2 = Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                created_at_block: Some(100),
+                ..Default::default()
+            },
+        );
+        assert_eq!(v2.age_blocks(130), Some(30));
+        assert_eq!(v2.age_duration(130), Some(MANTLE_BLOCK_TIME * 30));
+    }
+
+    #[test]
+    fn test_price_impact() {
+        let (a, b) = (addr(1), addr(2));
+        let liquidity = 10u128.pow(18);
+
+        // a/b at price 1 with all liquidity in [-1000, 1000)
+        let mut v3 = UniswapV3Pool {
+            address: addr(10),
+            token0: a,
+            token1: b,
+            liquidity,
+            sqrt_price: U256::from(1u64) << 96,
+            fee: 3000,
+            tick_spacing: 10,
+            ..Default::default()
+        };
+        modify_position(&mut v3, -1000, 1000, liquidity as i128, true);
+
+        // constant liquidity: out = L * x / (L + x) against a spot price of 1
+        for amount_in in [10f64.powi(12), 10f64.powi(15)] {
+            let x = amount_in * 0.997;
+            let out = liquidity as f64 * x / (liquidity as f64 + x);
+            let expected = (1.0 - out / amount_in) * 100.0;
+            for token_in in [a, b] {
+                let impact = v3
+                    .price_impact(token_in, U256::from(amount_in as u128))
+                    .unwrap();
+                assert!(
+                    (impact - expected).abs() < 1e-6,
+                    "{} vs {}",
+                    impact,
+                    expected
+                );
+            }
+        }
+        // a bigger trade moves the price further
+        let small = v3.price_impact(a, U256::from(10u128.pow(12))).unwrap();
+        let large = v3.price_impact(a, U256::from(10u128.pow(16))).unwrap();
+        assert!(small > 0.3 && small < large);
+        assert!(v3.price_impact(addr(3), U256::from(1u64)).is_err());
+
+        // b/c at price 2, trading 1% of the b reserve
+        let v2 = MerchantMoeV2Pool {
+            address: addr(11),
+            token0: b,
+            token1: addr(3),
+            token0_reserves: U256::from(10u128.pow(21)),
+            token1_reserves: U256::from(2 * 10u128.pow(21)),
+            ..Default::default()
+        };
+        let x = 1e19 * 0.997;
+        let out = x * 2e21 / (1e21 + x);
+        let expected = (1.0 - out / 1e19 / 2.0) * 100.0;
+        let impact = v2.price_impact(b, U256::from(10u128.pow(19))).unwrap();
+        assert!(
+            (impact - expected).abs() < 1e-9,
+            "{} vs {}",
+            impact,
+            expected
+        );
+        assert!(MerchantMoeV2Pool::default()
+            .price_impact(addr(0), U256::from(1u64))
+            .is_err());
+    }
+
+    #[test]
+    fn test_fee_amount_for_swap() {
+        let (a, b) = (addr(1), addr(2));
+        let liquidity = 10u128.pow(18);
+        let mut v3 = UniswapV3Pool {
+            address: addr(10),
+            token0: a,
+            token1: b,
+            liquidity,
+            sqrt_price: U256::from(1u64) << 96,
+            fee: 3000,
+            tick_spacing: 10,
+            ..Default::default()
+        };
+        modify_position(&mut v3, -1000, 1000, liquidity as i128, true);
+        // a second position in [-20, 20) doubles the active liquidity
+        modify_position(&mut v3, -20, 20, liquidity as i128, false);
+
+        // within the current tick range the fee is amount_in * 0.3%
+        let amount_in = U256::from(10u128.pow(12));
+        for token_in in [a, b] {
+            assert_eq!(
+                v3.fee_amount_for_swap(token_in, amount_in).unwrap(),
+                U256::from(3 * 10u128.pow(9))
+            );
+        }
+        // crossing ticks the fee of every step adds up, each rounded up by at most one unit
+        let amount_in = 3 * 10u128.pow(16);
+        let fee = v3.fee_amount_for_swap(a, U256::from(amount_in)).unwrap();
+        let expected = U256::from(amount_in * 3 / 1000);
+        assert!(fee >= expected && fee <= expected + U256::from(3u64));
+        assert!(v3
+            .fee_amount_for_swap(addr(3), U256::from(amount_in))
+            .is_err());
+
+        let v2 = Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                address: addr(11),
+                token0: a,
+                token1: b,
+                token0_reserves: U256::from(10u128.pow(21)),
+                token1_reserves: U256::from(10u128.pow(21)),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            v2.fee_amount_for_swap(b, U256::from(10u128.pow(12)))
+                .unwrap(),
+            U256::from(3 * 10u128.pow(9))
+        );
+        assert!(MerchantMoeV2Pool::default()
+            .fee_amount_for_swap(Address::ZERO, U256::from(1u64))
+            .is_err());
+    }
+
+    #[test]
+    fn test_default_fallbacks_flagged() {
+        let data = v3_tuple(18, 6, U256::from(1u64) << 96);
+        assert!(!UniswapV3Pool::from(data.as_slice()).has_default_fallbacks());
+
+        // a fee too wide for a u32 and a tick too wide for an i32
+        let mut data = v3_tuple(18, 6, U256::from(1u64) << 96);
+        data[9] = DynSolValue::Uint(U256::from(u64::MAX), 24);
+        data[7] = DynSolValue::Int(I256::try_from(i64::MAX).unwrap(), 24);
+        let pool = UniswapV3Pool::from(data.as_slice());
+        assert!(pool.has_default_fallbacks());
+        assert_eq!(
+            pool.decode_fallbacks,
+            UniswapV3Pool::FALLBACK_FEE | UniswapV3Pool::FALLBACK_TICK
+        );
+        assert_eq!((pool.fee, pool.tick), (3000, 0));
+        assert_eq!(pool.tick_spacing, 10);
+    }
+
+    #[test]
+    fn test_consolidated_price() {
+        let (base, quote) = (addr(1), addr(2));
+        // base trades at 4 quote in a deep V3 pool and at 1 quote in a shallow V2 pool
+        let deep = Pool::new_v3(
+            PoolType::UniswapV3,
+            UniswapV3Pool {
+                address: addr(10),
+                token0: base,
+                token1: quote,
+                token0_decimals: Some(18),
+                token1_decimals: Some(18),
+                sqrt_price: U256::from(2u64) << 96,
+                liquidity: 3_000,
+                ..Default::default()
+            },
+        );
+        let shallow = Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                address: addr(11),
+                // listed in the opposite order
+                token0: quote,
+                token1: base,
+                token0_decimals: Some(18),
+                token1_decimals: Some(18),
+                token0_reserves: U256::from(1_000u64),
+                token1_reserves: U256::from(1_000u64),
+                ..Default::default()
+            },
+        );
+        let other_pair = v3_pool(PoolType::Agni, addr(12), base, addr(3));
+
+        // weights 3000 and 1000: 4^0.75 * 1^0.25
+        let price = consolidated_price(&[&deep, &shallow, &other_pair], base, quote).unwrap();
+        assert!((price - 4f64.powf(0.75)).abs() < 1e-9);
+        let inverse = consolidated_price(&[&deep, &shallow], quote, base).unwrap();
+        assert!((price * inverse - 1.0).abs() < 1e-9);
+
+        assert_eq!(consolidated_price(&[&other_pair], base, quote), None);
+        assert_eq!(consolidated_price(&[], base, quote), None);
+    }
+
+    /// A mint (`None`) or a burn of some previously minted position (`Some(index)`)
+    fn position_event() -> impl Strategy<Value = (Option<usize>, i32, i32, u128)> {
+        (
+            prop::option::of(any::<usize>()),
+            -50i32..50,
+            1i32..20,
+            1u128..1_000_000_000_000_000_000,
+        )
+    }
+
+    /// Checks the tick state of the pool against the positions that are still open
+    fn assert_tick_invariants(pool: &UniswapV3Pool, positions: &[(i32, i32, u128)]) {
+        let mut expected_gross: HashMap<i32, u128> = HashMap::new();
+        for &(lower, upper, liquidity) in positions {
+            *expected_gross.entry(lower).or_default() += liquidity;
+            *expected_gross.entry(upper).or_default() += liquidity;
+        }
+        expected_gross.retain(|_, gross| *gross > 0);
+
+        // Every open position adds to the net at its lower tick and removes it at its upper tick
+        let net: i128 = pool.ticks.values().map(|info| info.liquidity_net).sum();
+        assert_eq!(net, 0);
+
+        let ticks: HashSet<i32> = pool.ticks.keys().copied().collect();
+        assert_eq!(ticks, expected_gross.keys().copied().collect());
+        for (tick, info) in &pool.ticks {
+            assert_eq!(info.liquidity_gross, expected_gross[tick]);
+            assert!(info.liquidity_gross >= info.liquidity_net.unsigned_abs());
+        }
+
+        // The bitmap has a bit set for exactly the initialized ticks
+        let mut bitmap_ticks = HashSet::new();
+        for (word, bits) in &pool.tick_bitmap {
+            for bit in 0..256 {
+                if bits.bit(bit) {
+                    bitmap_ticks.insert((*word as i32 * 256 + bit as i32) * pool.tick_spacing);
+                }
+            }
+        }
+        assert_eq!(bitmap_ticks, ticks);
+
+        let active: u128 = positions
+            .iter()
+            .filter(|(lower, upper, _)| pool.tick >= *lower && pool.tick < *upper)
+            .map(|(_, _, liquidity)| liquidity)
+            .sum();
+        assert_eq!(pool.liquidity, active);
+    }
+
+    proptest! {
+        #[test]
+        fn test_mint_burn_sequences_keep_ticks_consistent(
+            events in prop::collection::vec(position_event(), 1..64),
+        ) {
+            let mut pool = UniswapV3Pool {
+                tick_spacing: 60,
+                tick: 7,
+                ..Default::default()
+            };
+            let mut positions: Vec<(i32, i32, u128)> = Vec::new();
+
+            for (burn, lower, width, amount) in events {
+                match burn {
+                    Some(index) if !positions.is_empty() => {
+                        let index = index % positions.len();
+                        let (lower, upper, liquidity) = positions[index];
+                        let burned = amount.min(liquidity);
+                        modify_position(&mut pool, lower, upper, -(burned as i128), false);
+                        positions[index].2 -= burned;
+                    }
+                    _ => {
+                        let (lower, upper) = (lower * 60, (lower + width) * 60);
+                        modify_position(&mut pool, lower, upper, amount as i128, false);
+                        positions.push((lower, upper, amount));
+                    }
+                }
+                assert_tick_invariants(&pool, &positions);
+            }
+
+            // Burning every position leaves no ticks behind
+            for (lower, upper, liquidity) in positions.iter_mut() {
+                modify_position(&mut pool, *lower, *upper, -(*liquidity as i128), false);
+                *liquidity = 0;
+            }
+            assert_tick_invariants(&pool, &positions);
+            prop_assert!(pool.ticks.is_empty());
+            prop_assert!(pool.tick_bitmap.values().all(|word| word.is_zero()));
+            prop_assert_eq!(pool.liquidity, 0);
+        }
+    }
+
+    #[test]
+    fn test_pools_changed_since() {
+        let v3 = |address, last_update_block| {
+            Pool::new_v3(
+                PoolType::UniswapV3,
+                UniswapV3Pool {
+                    address,
+                    last_update_block,
+                    ..Default::default()
+                },
+            )
+        };
+        let v2 = |address, last_update_block| {
+            Pool::new_v2(
+                PoolType::MerchantMoe,
+                MerchantMoeV2Pool {
+                    address,
+                    last_update_block,
+                    ..Default::default()
+                },
+            )
+        };
+        let pools = vec![
+            v3(addr(10), 0),
+            v2(addr(11), 150),
+            v3(addr(12), 100),
+            v3(addr(13), 250),
+            v2(addr(14), 99),
+        ];
+
+        let changed = |block| -> Vec<Address> {
+            pools_changed_since(&pools, block)
+                .iter()
+                .map(|pool| pool.address())
+                .collect()
+        };
+        // a pool updated in the given block is not newer than it
+        assert_eq!(changed(100), vec![addr(11), addr(13)]);
+        assert_eq!(changed(0), vec![addr(11), addr(12), addr(13), addr(14)]);
+        assert!(changed(250).is_empty());
+    }
+
+    #[test]
+    fn test_pool_from_json_offline() {
+        let (a, b) = (addr(1), addr(2));
+        let liquidity = 10u128.pow(18);
+        let mut v3 = UniswapV3Pool {
+            address: addr(10),
+            token0: a,
+            token1: b,
+            token0_decimals: Some(18),
+            token1_decimals: Some(18),
+            liquidity: 0,
+            sqrt_price: U256::from(1u64) << 96,
+            fee: 3000,
+            tick_spacing: 10,
+            ..Default::default()
+        };
+        // a narrow position on top of a wide one, so larger swaps cross ticks
+        modify_position(&mut v3, -1000, 1000, liquidity as i128, false);
+        modify_position(&mut v3, -20, 20, liquidity as i128, false);
+        let pool = Pool::new_v3(PoolType::UniswapV3, v3);
+
+        let json = serde_json::to_string(&pool).unwrap();
+        let loaded = Pool::from_json_str(&json).unwrap();
+        assert_eq!(loaded.pool_type(), PoolType::UniswapV3);
+        assert_eq!(
+            loaded.price_token0_in_token1(),
+            pool.price_token0_in_token1()
+        );
+        for token_in in [a, b] {
+            for amount_in in [10u128.pow(12), 3 * 10u128.pow(16)] {
+                let amount_in = U256::from(amount_in);
+                let out = loaded.simulate_swap(token_in, amount_in).unwrap();
+                assert!(out > U256::ZERO);
+                assert_eq!(out, pool.simulate_swap(token_in, amount_in).unwrap());
+            }
+        }
+
+        assert!(matches!(
+            Pool::from_json_str("{\"NotAPool\": {}}"),
+            Err(PoolSyncError::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn test_position_fees() {
+        let q96 = U256::from(1u64) << 96;
+        let sqrt_price = |price: f64| U160::from(price * 2f64.powi(96));
+        let swap_to = |pool, price: f64, block| {
+            let mut event = swap(0, 0);
+            event.sqrtPriceX96 = sqrt_price(price);
+            event_log(pool, &event, block)
+        };
+        let pool = UniswapV3Pool {
+            address: addr(10),
+            sqrt_price: q96,
+            fee: 3000,
+            ..Default::default()
+        };
+        let liquidity = 1_994_000_000_000_000_000_000u128;
+        let swaps = vec![
+            // up from 1 to 1.5: the position provides 0.5 * L = 997e18 of token1
+            swap_to(addr(10), 1.5, 1),
+            // another pool's swap is ignored
+            swap_to(addr(11), 0.1, 2),
+            // down to 0.5, leaving the range at its lower bound
+            swap_to(addr(10), 0.5, 3),
+        ];
+
+        let (fees0, fees1) = pool
+            .position_fees(&swaps, -12000, 12000, liquidity)
+            .unwrap();
+        // 997e18 * 3000 / 997000
+        assert_eq!(fees1, U256::from(3_000_000_000_000_000_000u128));
+        let sqrt_lower = 1.0001f64.powf(-6000.0);
+        let input0 = liquidity as f64 * (1.0 / sqrt_lower - 1.0 / 1.5);
+        let expected0 = input0 * 3000.0 / 997000.0;
+        let fees0 = f64::from(fees0);
+        assert!(
+            (fees0 - expected0).abs() / expected0 < 1e-9,
+            "{} vs {}",
+            fees0,
+            expected0
+        );
+
+        // a position the price never reaches earns nothing
+        assert_eq!(
+            pool.position_fees(&swaps, 30000, 36000, liquidity).unwrap(),
+            (U256::ZERO, U256::ZERO)
+        );
+        // fees scale with the position's liquidity
+        let (_, half_fees1) = pool
+            .position_fees(&swaps, -12000, 12000, liquidity / 2)
+            .unwrap();
+        assert_eq!(half_fees1, U256::from(1_500_000_000_000_000_000u128));
+    }
+
+    #[test]
+    fn test_initialize_sets_missing_price() {
+        let address = addr(100);
+        let mut v3 = UniswapV3Pool {
+            address,
+            ..Default::default()
+        };
+        let sqrt_price = tick_math::get_sqrt_ratio_at_tick(-200).unwrap();
+        let initialize = DataEvents::Initialize {
+            sqrtPriceX96: U160::from(sqrt_price),
+            tick: I24::try_from(-200).unwrap(),
+        };
+
+        // a pool that was never swapped gets its price from the initialization alone
+        process_tick_data(
+            &mut v3,
+            event_log(address, &initialize, 7),
+            PoolType::UniswapV3,
+            true,
+        );
+        assert_eq!(v3.sqrt_price, sqrt_price);
+        assert_eq!(v3.tick, -200);
+        assert_eq!(v3.last_update_block, 7);
+
+        // a later price is never rolled back by replaying the initialization
+        process_tick_data(
+            &mut v3,
+            event_log(address, &swap(0, 0), 9),
+            PoolType::UniswapV3,
+            false,
+        );
+        process_tick_data(
+            &mut v3,
+            event_log(address, &initialize, 7),
+            PoolType::UniswapV3,
+            false,
+        );
+        assert_eq!(v3.sqrt_price, U256::from(1u64) << 96);
+        assert_eq!(v3.tick, 0);
+    }
+
+    #[test]
+    fn test_pools_with_fee() {
+        let v3 = |address, fee| {
+            Pool::new_v3(
+                PoolType::UniswapV3,
+                UniswapV3Pool {
+                    address,
+                    fee,
+                    ..Default::default()
+                },
+            )
+        };
+        let v2 = Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                address: addr(14),
+                ..Default::default()
+            },
+        );
+        let pools = vec![
+            v3(addr(10), 3000),
+            v3(addr(11), 500),
+            v2,
+            v3(addr(12), 10000),
+        ];
+
+        let tier = |fee| -> Vec<Address> {
+            pools_with_fee(&pools, fee)
+                .into_iter()
+                .map(Pool::address)
+                .collect()
+        };
+        assert_eq!(tier(500), vec![addr(11)]);
+        assert_eq!(tier(3000), vec![addr(10), addr(14)]);
+        assert_eq!(tier(10000), vec![addr(12)]);
+        assert!(tier(100).is_empty());
+    }
+
+    #[test]
+    fn test_variant_names_and_fields() {
+        let v3 = UniswapV3Pool {
+            token0_decimals: Some(18),
+            token1_decimals: Some(6),
+            ..Default::default()
+        };
+        let pools = [
+            Pool::new_v3(PoolType::UniswapV3, v3.clone()),
+            Pool::new_v3(PoolType::Agni, v3),
+            Pool::new_v2(PoolType::MerchantMoe, MerchantMoeV2Pool::default()),
+        ];
+        let names: Vec<&str> = pools.iter().map(Pool::variant_name).collect();
+        assert_eq!(names, vec!["UniswapV3", "Agni", "MerchantMoe"]);
+        for pool in &pools {
+            assert_eq!(pool.variant_name(), pool.pool_type().to_string());
+        }
+
+        let v3_fields = vec![
+            "address",
+            "creation_index",
+            "decode_fallbacks",
+            "extra",
+            "fee",
+            "last_update_block",
+            "liquidity",
+            "protocol_fee",
+            "sqrt_price",
+            "tick",
+            "tick_bitmap",
+            "tick_spacing",
+            "ticks",
+            "token0",
+            "token0_decimals",
+            "token0_name",
+            "token1",
+            "token1_decimals",
+            "token1_name",
+            "truncated",
+        ];
+        assert_eq!(pools[0].field_names(), v3_fields);
+        assert_eq!(pools[1].field_names(), v3_fields);
+        // unknown decimals and the unset optional fields are left out
+        assert_eq!(
+            pools[2].field_names(),
+            vec![
+                "address",
+                "creation_index",
+                "extra",
+                "last_update_block",
+                "token0",
+                "token0_name",
+                "token0_reserves",
+                "token1",
+                "token1_name",
+                "token1_reserves",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pool_type_family() {
+        let v2_tuple = vec![
+            DynSolValue::Address(addr(100)),
+            DynSolValue::Address(addr(1)),
+            DynSolValue::Address(addr(2)),
+            DynSolValue::Uint(U256::from(18u64), 8),
+            DynSolValue::Uint(U256::from(6u64), 8),
+            DynSolValue::Uint(U256::from(1000u64), 112),
+            DynSolValue::Uint(U256::from(2000u64), 112),
+        ];
+        let v3_tuple = v3_tuple(18, 6, U256::from(1u64) << 96);
+
+        for pool_type in PoolType::ALL {
+            let family = pool_type.family();
+            assert_eq!(pool_type.is_v3(), family == PoolFamily::V3);
+            assert_eq!(pool_type.is_v2(), family == PoolFamily::V2);
+
+            // the declared family decides which layout the pool is built from
+            let data = match family {
+                PoolFamily::V3 => &v3_tuple,
+                PoolFamily::V2 => &v2_tuple,
+                PoolFamily::Other => unreachable!("built-in fetchers declare V2 or V3"),
+            };
+            let pool = pool_type.build_pool(data);
+            assert_eq!(pool.pool_type(), pool_type);
+            assert_eq!(pool.is_v3(), family == PoolFamily::V3);
+            assert_eq!(pool.address(), addr(100));
+        }
+        assert_eq!(PoolType::Agni.family(), PoolFamily::V3);
+        assert_eq!(PoolType::MerchantMoe.family(), PoolFamily::V2);
+        // a family without a layout builds nothing
+        assert!(PoolFamily::Other
+            .build_pool(PoolType::UniswapV3, &v3_tuple)
+            .is_none());
+    }
+
+    #[test]
+    fn test_truncated_data_sync_tuple() {
+        let v3 = v3_tuple(18, 6, U256::from(1u64) << 96);
+        let v2 = [
+            DynSolValue::Address(addr(100)),
+            DynSolValue::Address(addr(1)),
+            DynSolValue::Address(addr(2)),
+            DynSolValue::Uint(U256::from(18u64), 8),
+            DynSolValue::Uint(U256::from(6u64), 8),
+            DynSolValue::Uint(U256::from(1000u64), 112),
+            DynSolValue::Uint(U256::from(2000u64), 112),
+        ];
+        assert!(PoolType::UniswapV3.build_pool(&v3).is_valid());
+        assert!(PoolType::MerchantMoe.build_pool(&v2).is_valid());
+
+        // a tuple cut short anywhere gives an invalid pool instead of a panic
+        for len in 0..10 {
+            assert!(!PoolType::UniswapV3.build_pool(&v3[..len]).is_valid());
+        }
+        for len in 0..7 {
+            assert!(!PoolType::MerchantMoe.build_pool(&v2[..len]).is_valid());
+        }
+    }
+
+    #[test]
+    fn test_invariant_k() {
+        let pool = |reserve0: U256, reserve1: U256| MerchantMoeV2Pool {
+            token0_reserves: reserve0,
+            token1_reserves: reserve1,
+            ..Default::default()
+        };
+        let k = pool(U256::from(1_000u64), U256::from(2_500u64)).invariant_k();
+        assert_eq!(k, Some(U256::from(2_500_000u64)));
+        assert_eq!(
+            pool(U256::ZERO, U256::from(7u64)).invariant_k(),
+            Some(U256::ZERO)
+        );
+
+        // the largest uint112 reserves still fit
+        let max = U256::from(U112::MAX);
+        assert_eq!(pool(max, max).invariant_k(), Some(max * max));
+        assert_eq!(pool(U256::MAX, U256::from(2u64)).invariant_k(), None);
+    }
+
+    #[test]
+    fn test_execution_price() {
+        let (a, b) = (addr(1), addr(2));
+        let amount = U256::from(10u128.pow(6));
+
+        // a/b at price 1 with 18 and 6 decimals, so 1e12 in display units
+        let v3 = UniswapV3Pool {
+            address: addr(10),
+            token0: a,
+            token1: b,
+            token0_decimals: Some(18),
+            token1_decimals: Some(6),
+            sqrt_price: U256::from(1u64) << 96,
+            fee: 500,
+            ..Default::default()
+        };
+        let spot = v3.price_token0_in_token1().unwrap();
+        let price = v3.execution_price(a, amount).unwrap();
+        assert!((price / spot - 0.9995).abs() < 1e-12);
+        let price = v3.execution_price(b, amount).unwrap();
+        assert!((price / v3.price_token1_in_token0().unwrap() - 0.9995).abs() < 1e-12);
+        assert_eq!(v3.execution_price(addr(3), amount), None);
+        assert_eq!(v3.execution_price(a, U256::ZERO), None);
+
+        // b/c at price 2, the fee and the curve both lower the price
+        let v2 = MerchantMoeV2Pool {
+            address: addr(11),
+            token0: b,
+            token1: addr(3),
+            token0_decimals: Some(6),
+            token1_decimals: Some(6),
+            token0_reserves: U256::from(10u128.pow(21)),
+            token1_reserves: U256::from(2 * 10u128.pow(21)),
+            ..Default::default()
+        };
+        let spot = v2.price_token0_in_token1().unwrap();
+        let small = v2.execution_price(b, amount).unwrap();
+        assert!((small / spot - 0.997).abs() < 1e-9);
+        let large = v2.execution_price(b, U256::from(10u128.pow(19))).unwrap();
+        let x = 1e19 * 0.997;
+        assert!((large - x * 2e21 / (1e21 + x) / 1e19).abs() < 1e-12);
+        assert!(large < small);
+        let reverse = v2.execution_price(addr(3), amount).unwrap();
+        assert!((reverse / v2.price_token1_in_token0().unwrap() - 0.997).abs() < 1e-9);
+        assert_eq!(
+            MerchantMoeV2Pool::default().execution_price(addr(0), amount),
+            None
+        );
+
+        // the enum forwards to both variants
+        let pool = Pool::new_v3(PoolType::UniswapV3, v3.clone());
+        assert_eq!(
+            pool.execution_price(a, amount),
+            v3.execution_price(a, amount)
+        );
+        let pool = Pool::new_v2(PoolType::MerchantMoe, v2.clone());
+        assert_eq!(pool.execution_price(b, amount), Some(small));
+    }
+
+    #[test]
+    fn test_min_amount_out() {
+        let (a, b) = (addr(1), addr(2));
+        let liquidity = 10u128.pow(18);
+        let mut v3 = UniswapV3Pool {
+            address: addr(10),
+            token0: a,
+            token1: b,
+            liquidity,
+            sqrt_price: U256::from(1u64) << 96,
+            fee: 3000,
+            tick_spacing: 10,
+            ..Default::default()
+        };
+        modify_position(&mut v3, -1000, 1000, liquidity as i128, true);
+        let amount_in = U256::from(10u128.pow(15));
+        let out = v3.simulate_swap(true, amount_in).unwrap();
+        assert_eq!(v3.min_amount_out(a, amount_in, 0).unwrap(), out);
+        assert_eq!(
+            v3.min_amount_out(a, amount_in, 50).unwrap(),
+            out * U256::from(9950u64) / U256::from(10_000u64)
+        );
+        assert_eq!(v3.min_amount_out(a, amount_in, 10_000).unwrap(), U256::ZERO);
+        assert!(matches!(
+            v3.min_amount_out(a, amount_in, 10_001),
+            Err(PoolSyncError::SwapSimulationError(_))
+        ));
+        assert!(v3.min_amount_out(addr(3), amount_in, 50).is_err());
+
+        let v2 = MerchantMoeV2Pool {
+            address: addr(11),
+            token0: b,
+            token1: addr(3),
+            token0_reserves: U256::from(10u128.pow(21)),
+            token1_reserves: U256::from(2 * 10u128.pow(21)),
+            ..Default::default()
+        };
+        let out = v2.simulate_swap(false, amount_in).unwrap();
+        // 1% slippage rounds down
+        assert_eq!(
+            v2.min_amount_out(addr(3), amount_in, 100).unwrap(),
+            out * U256::from(99u64) / U256::from(100u64)
+        );
+        assert!(MerchantMoeV2Pool::default()
+            .min_amount_out(addr(0), amount_in, 50)
+            .is_err());
+
+        let pool = Pool::new_v2(PoolType::MerchantMoe, v2);
+        assert_eq!(
+            pool.min_amount_out(addr(3), amount_in, 100).unwrap(),
+            out * U256::from(99u64) / U256::from(100u64)
+        );
+    }
+}
@@ -0,0 +1,1044 @@
+#[cfg(test)]
+mod population_test {
+    use alloy::dyn_abi::{DynSolType, DynSolValue};
+    use alloy::eips::BlockNumberOrTag;
+    use alloy::primitives::{Address, FixedBytes, Log, I256, U160, U256};
+    use alloy::providers::ProviderBuilder;
+    use alloy::sol_types::SolCall;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use crate::cache::TokenCache;
+    use crate::pools::gen::{V3PoolState, ERC20};
+    use crate::pools::pool_builder::{
+        build_pools, populate_token_metadata, resolve_token_name, CallLimiter,
+        DataSyncRevertPolicy, PopulationConfig, PopulationStrategy, RetryBudget,
+        UNKNOWN_TOKEN_NAME,
+    };
+    use crate::pools::pool_fetchers::{MerchantMoeV2Fetcher, UniswapV3Fetcher};
+    use crate::pools::pool_structures::v3_structure::TickInfo;
+    use crate::pools::{validate_pool_family, PoolFamily, PoolFetcher};
+    use crate::rpc::{AdaptiveRate, CircuitBreaker};
+    use crate::tests::fixtures::*;
+    use crate::tests::mock_rpc::*;
+    use crate::{Chain, Pool, PoolInfo, PoolSyncError, PoolType, Rpc};
+
+    /// Answers a single V3 pool (addr 100, tokens addr 1 / addr 2) through both the
+    /// data-sync deploy call and the pool's own view functions
+    fn v3_chain(method: &str, params: &Value) -> MockResponse {
+        v3_chain_with_liquidity(5000, method, params)
+    }
+
+    fn v3_chain_with_liquidity(liquidity: u64, method: &str, params: &Value) -> MockResponse {
+        let (pool, token0, token1) = (addr(100), addr(1), addr(2));
+        let sqrt_price = U256::from(1u64) << 96;
+        if method != "eth_call" {
+            return MockResponse::Error(-32601, "method not found".into());
+        }
+
+        let (to, input) = call_target(params);
+        let selector: [u8; 4] = input[..4].try_into().unwrap_or_default();
+        let result = match to {
+            None => {
+                let tuple = DynSolValue::Tuple(vec![
+                    DynSolValue::Address(pool),
+                    DynSolValue::Address(token0),
+                    DynSolValue::Uint(U256::from(18u64), 8),
+                    DynSolValue::Address(token1),
+                    DynSolValue::Uint(U256::from(6u64), 8),
+                    DynSolValue::Uint(U256::from(liquidity), 128),
+                    DynSolValue::Uint(sqrt_price, 160),
+                    DynSolValue::Int(I256::try_from(-5).unwrap(), 24),
+                    DynSolValue::Int(I256::try_from(60).unwrap(), 24),
+                    DynSolValue::Uint(U256::from(3000u64), 24),
+                    DynSolValue::Int(I256::ZERO, 128),
+                ]);
+                DynSolValue::Array(vec![tuple]).abi_encode().into()
+            }
+            Some(to) if to == pool => match selector {
+                V3PoolState::token0Call::SELECTOR => call_result(token0),
+                V3PoolState::token1Call::SELECTOR => call_result(token1),
+                // full slot0 with a feeProtocol of 1/4 on both sides
+                V3PoolState::slot0Call::SELECTOR => call_result((
+                    sqrt_price,
+                    I256::try_from(-5).unwrap(),
+                    U256::ZERO,
+                    U256::from(1u64),
+                    U256::from(1u64),
+                    U256::from(0x44u64),
+                    true,
+                )),
+                V3PoolState::liquidityCall::SELECTOR => call_result(U256::from(liquidity)),
+                V3PoolState::tickSpacingCall::SELECTOR => call_result(U256::from(60u64)),
+                V3PoolState::feeCall::SELECTOR => call_result(U256::from(3000u64)),
+                _ => return MockResponse::Error(3, "execution reverted".into()),
+            },
+            Some(token) => match selector {
+                ERC20::decimalsCall::SELECTOR if token == token0 => call_result(U256::from(18u64)),
+                ERC20::decimalsCall::SELECTOR => call_result(U256::from(6u64)),
+                ERC20::symbolCall::SELECTOR if token == token0 => call_result("T0".to_string()),
+                ERC20::symbolCall::SELECTOR => call_result("T1".to_string()),
+                ERC20::totalSupplyCall::SELECTOR if token == token0 => {
+                    call_result(U256::from(10u128.pow(27)))
+                }
+                ERC20::totalSupplyCall::SELECTOR => call_result(U256::from(5 * 10u64.pow(12))),
+                _ => return MockResponse::Error(3, "execution reverted".into()),
+            },
+        };
+        MockResponse::Result(result)
+    }
+
+    async fn populate(url: &str, strategy: PopulationStrategy, protocol_fee: bool) -> Vec<Pool> {
+        let config = PopulationConfig {
+            strategy,
+            token_concurrency: 4,
+            token_name_attempts: 6,
+            protocol_fee,
+            total_supply: false,
+            block: None,
+            retry_budget: RetryBudget::default(),
+            circuit_breaker: CircuitBreaker::default(),
+            debug_raw: false,
+            on_data_sync_revert: DataSyncRevertPolicy::default(),
+            known_tokens: Arc::default(),
+            chunk_size: None,
+            chunk_concurrency: 1,
+            limiter: CallLimiter::default(),
+            rate: AdaptiveRate::default(),
+        };
+        populate_with(url, config).await
+    }
+
+    async fn populate_with(url: &str, config: PopulationConfig) -> Vec<Pool> {
+        let provider = Arc::new(ProviderBuilder::new().on_http(url.parse().unwrap()));
+        let (pools, failed) = build_pools(
+            &provider,
+            vec![addr(100)],
+            PoolType::UniswapV3,
+            &UniswapV3Fetcher,
+            Chain::Mantle,
+            config,
+        )
+        .await
+        .unwrap();
+        assert!(failed.is_empty());
+        pools
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_token_names() {
+        // every token answers `symbol()` with TKN<n> based on its address
+        let mock = MockRpc::start(|method, params| match method {
+            "eth_call" => {
+                let (to, _) = call_target(params);
+                let n = U160::from_be_slice(to.unwrap().as_slice());
+                MockResponse::Result(call_result(format!("TKN{}", n)))
+            }
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let mut pools: Vec<Pool> = (1..=20)
+            .map(|n| v3_pool(PoolType::UniswapV3, addr(1000 + n), addr(n), addr(n + 1)))
+            .collect();
+        populate_token_metadata(
+            &provider,
+            &mut pools,
+            4,
+            6,
+            false,
+            BlockNumberOrTag::Latest,
+            &TokenCache::new(),
+            &AdaptiveRate::default(),
+        )
+        .await;
+
+        for (n, pool) in (1..=20).zip(&pools) {
+            assert_eq!(pool.token0_name(), format!("TKN{}", n));
+            assert_eq!(pool.token1_name(), format!("TKN{}", n + 1));
+        }
+        // shared tokens are only fetched once
+        assert_eq!(mock.calls("eth_call"), 21);
+    }
+
+    #[tokio::test]
+    async fn test_rpc_batch_matches_data_sync() {
+        let mock = MockRpc::start(v3_chain).await;
+        let data_sync = populate(&mock.url(), PopulationStrategy::DataSync, false).await;
+        let rpc_batch = populate(&mock.url(), PopulationStrategy::RpcBatch, false).await;
+
+        assert_eq!(data_sync.len(), 1);
+        assert_eq!(rpc_batch.len(), 1);
+        let (expected, actual) = (
+            data_sync[0].get_v3().unwrap(),
+            rpc_batch[0].get_v3().unwrap(),
+        );
+        assert_eq!(actual.address, expected.address);
+        assert_eq!(actual.token0, expected.token0);
+        assert_eq!(actual.token1, expected.token1);
+        assert_eq!(actual.token0_decimals, Some(18));
+        assert_eq!(actual.token1_decimals, expected.token1_decimals);
+        assert_eq!(actual.liquidity, expected.liquidity);
+        assert_eq!(actual.sqrt_price, expected.sqrt_price);
+        assert_eq!(actual.tick, -5);
+        assert_eq!(actual.tick, expected.tick);
+        assert_eq!(actual.tick_spacing, expected.tick_spacing);
+        assert_eq!(actual.fee, expected.fee);
+        assert_eq!(rpc_batch[0].token0_name(), "T0");
+        assert_eq!(data_sync[0].token1_name(), "T1");
+    }
+
+    #[tokio::test]
+    async fn test_protocol_fee_read_when_enabled() {
+        let mock = MockRpc::start(v3_chain).await;
+
+        let pools = populate(&mock.url(), PopulationStrategy::DataSync, false).await;
+        assert_eq!(pools[0].get_v3().unwrap().protocol_fee, 0);
+        assert_eq!(pools[0].lp_fee(true), 3000);
+
+        let pools = populate(&mock.url(), PopulationStrategy::DataSync, true).await;
+        assert_eq!(pools[0].get_v3().unwrap().protocol_fee, 0x44);
+        // uniswap takes fee / 4 of every swap
+        assert_eq!(pools[0].lp_fee(true), 2250);
+        assert_eq!(pools[0].lp_fee(false), 2250);
+        assert_eq!(pools[0].fee(), 3000);
+    }
+
+    #[tokio::test]
+    async fn test_total_supply_read_when_enabled() {
+        // the block each totalSupply() and symbol() call is made at
+        let blocks = Arc::new(Mutex::new(HashMap::new()));
+        let seen = blocks.clone();
+        let mock = MockRpc::start(move |method, params| {
+            let (to, input) = call_target(params);
+            if let (Some(_), Some(selector)) = (to, input.get(..4)) {
+                let selector: [u8; 4] = selector.try_into().unwrap();
+                if [
+                    ERC20::totalSupplyCall::SELECTOR,
+                    ERC20::symbolCall::SELECTOR,
+                ]
+                .contains(&selector)
+                {
+                    seen.lock().unwrap().insert(selector, params[1].clone());
+                }
+            }
+            v3_chain(method, params)
+        })
+        .await;
+
+        let pools = populate(&mock.url(), PopulationStrategy::DataSync, false).await;
+        assert_eq!(pools[0].token0_total_supply(), None);
+        assert_eq!(pools[0].token1_total_supply(), None);
+
+        for strategy in [PopulationStrategy::DataSync, PopulationStrategy::RpcBatch] {
+            let config = PopulationConfig {
+                strategy,
+                token_concurrency: 4,
+                token_name_attempts: 6,
+                protocol_fee: false,
+                total_supply: true,
+                block: Some(100),
+                retry_budget: RetryBudget::default(),
+                circuit_breaker: CircuitBreaker::default(),
+                debug_raw: false,
+                on_data_sync_revert: DataSyncRevertPolicy::default(),
+                known_tokens: Arc::default(),
+                chunk_size: None,
+                chunk_concurrency: 1,
+                limiter: CallLimiter::default(),
+                rate: AdaptiveRate::default(),
+            };
+            let pools = populate_with(&mock.url(), config).await;
+            assert_eq!(pools[0].token0_name(), "T0");
+            assert_eq!(
+                pools[0].token0_total_supply(),
+                Some(U256::from(10u128.pow(27)))
+            );
+            assert_eq!(
+                pools[0].token1_total_supply(),
+                Some(U256::from(5 * 10u64.pow(12)))
+            );
+            // the supplies are read at the block the pool state is, the names at the tip
+            let called_at = blocks.lock().unwrap();
+            assert_eq!(called_at[&ERC20::totalSupplyCall::SELECTOR], "0x64");
+            assert_eq!(called_at[&ERC20::symbolCall::SELECTOR], "latest");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_symbol_retried_after_transient_failure() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let seen = attempts.clone();
+        let mock = MockRpc::start(move |method, _params| match method {
+            "eth_call" if seen.fetch_add(1, Ordering::SeqCst) == 0 => {
+                MockResponse::Error(-32000, "header not found".into())
+            }
+            "eth_call" => MockResponse::Result(call_result("WMNT".to_string())),
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        // the first symbol() call fails once, whichever token it is for
+        let mut pools = vec![v3_pool(PoolType::UniswapV3, addr(100), addr(1), addr(2))];
+        populate_token_metadata(
+            &provider,
+            &mut pools,
+            1,
+            6,
+            false,
+            BlockNumberOrTag::Latest,
+            &TokenCache::new(),
+            &AdaptiveRate::default(),
+        )
+        .await;
+
+        assert_eq!(pools[0].token0_name(), "WMNT");
+        assert_eq!(pools[0].token1_name(), "WMNT");
+        assert_eq!(mock.calls("eth_call"), 3);
+    }
+
+    #[tokio::test]
+    async fn test_identical_tokens_filtered() {
+        // the data-sync call returns a healthy pool and one trading a token against itself
+        let mock = MockRpc::start(|method, params| {
+            let (to, _) = call_target(params);
+            match (method, to) {
+                ("eth_call", None) => {
+                    let pool = |address, token0, token1| {
+                        DynSolValue::Tuple(vec![
+                            DynSolValue::Address(address),
+                            DynSolValue::Address(token0),
+                            DynSolValue::Uint(U256::from(18u64), 8),
+                            DynSolValue::Address(token1),
+                            DynSolValue::Uint(U256::from(18u64), 8),
+                            DynSolValue::Uint(U256::from(5000u64), 128),
+                            DynSolValue::Uint(U256::from(1u64) << 96, 160),
+                            DynSolValue::Int(I256::ZERO, 24),
+                            DynSolValue::Int(I256::try_from(60).unwrap(), 24),
+                            DynSolValue::Uint(U256::from(3000u64), 24),
+                            DynSolValue::Int(I256::ZERO, 128),
+                        ])
+                    };
+                    let pools = DynSolValue::Array(vec![
+                        pool(addr(100), addr(1), addr(2)),
+                        pool(addr(101), addr(3), addr(3)),
+                    ]);
+                    MockResponse::Result(pools.abi_encode().into())
+                }
+                ("eth_call", Some(_)) => MockResponse::Result(call_result("TKN".to_string())),
+                _ => MockResponse::Error(-32601, "method not found".into()),
+            }
+        })
+        .await;
+
+        let pools = populate(&mock.url(), PopulationStrategy::DataSync, false).await;
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].address(), addr(100));
+
+        let malformed = v3_pool(PoolType::UniswapV3, addr(101), addr(3), addr(3));
+        assert!(!malformed.is_valid());
+    }
+
+    #[tokio::test]
+    async fn test_population_reads_pinned_block() {
+        // the pool had 5000 liquidity at block 100 and 9000 at the tip
+        let mock = MockRpc::start(|method, params| {
+            let liquidity = match params[1].as_str() {
+                Some("0x64") => 5000,
+                Some("latest") => 9000,
+                other => panic!("unexpected block {:?}", other),
+            };
+            v3_chain_with_liquidity(liquidity, method, params)
+        })
+        .await;
+
+        for strategy in [PopulationStrategy::DataSync, PopulationStrategy::RpcBatch] {
+            let config = PopulationConfig {
+                strategy,
+                token_concurrency: 4,
+                token_name_attempts: 6,
+                protocol_fee: true,
+                total_supply: false,
+                block: Some(100),
+                retry_budget: RetryBudget::default(),
+                circuit_breaker: CircuitBreaker::default(),
+                debug_raw: false,
+                on_data_sync_revert: DataSyncRevertPolicy::default(),
+                known_tokens: Arc::default(),
+                chunk_size: None,
+                chunk_concurrency: 1,
+                limiter: CallLimiter::default(),
+                rate: AdaptiveRate::default(),
+            };
+            let historical = populate_with(&mock.url(), config.clone()).await;
+            let tip = populate_with(
+                &mock.url(),
+                PopulationConfig {
+                    block: None,
+                    ..config
+                },
+            )
+            .await;
+
+            assert_eq!(historical[0].get_v3().unwrap().liquidity, 5000);
+            assert_eq!(tip[0].get_v3().unwrap().liquidity, 9000);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_name_fallbacks() {
+        // 1: string symbol, 2: bytes32 symbol, 3: reverting symbol, 4: empty symbol,
+        // 5: reverts on everything
+        let mock = MockRpc::start(|method, params| {
+            if method != "eth_call" {
+                return MockResponse::Error(-32601, "method not found".into());
+            }
+            let (to, input) = call_target(params);
+            let token = U160::from_be_slice(to.unwrap().as_slice()).to::<u64>();
+            let selector: [u8; 4] = input[..4].try_into().unwrap();
+            let revert = MockResponse::Error(3, "execution reverted".into());
+            let result = match (token, selector) {
+                (1, ERC20::symbolCall::SELECTOR) => call_result("WMNT".to_string()),
+                (2, ERC20::symbolCall::SELECTOR) => {
+                    call_result(FixedBytes::<32>::right_padding_from(b"MKR"))
+                }
+                (3 | 4, ERC20::nameCall::SELECTOR) => call_result("Named Token".to_string()),
+                (4, ERC20::symbolCall::SELECTOR) => call_result(String::new()),
+                _ => return revert,
+            };
+            MockResponse::Result(result)
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let rate = AdaptiveRate::default();
+        let name = |n| resolve_token_name(&provider, addr(n), 3, &rate);
+        assert_eq!(name(1).await.as_deref(), Some("WMNT"));
+        assert_eq!(name(2).await.as_deref(), Some("MKR"));
+        assert_eq!(name(3).await.as_deref(), Some("Named Token"));
+        assert_eq!(name(4).await.as_deref(), Some("Named Token"));
+        assert_eq!(name(5).await.as_deref(), Some(UNKNOWN_TOKEN_NAME));
+        // reverts are never retried
+        assert_eq!(mock.calls("eth_call"), 1 + 1 + 2 + 2 + 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_name_attempts_exhausted() {
+        let mock = MockRpc::start(|_method, _params| {
+            MockResponse::Error(-32000, "header not found".into())
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let mut pools = vec![v3_pool(PoolType::UniswapV3, addr(100), addr(1), addr(1))];
+        populate_token_metadata(
+            &provider,
+            &mut pools,
+            1,
+            2,
+            false,
+            BlockNumberOrTag::Latest,
+            &TokenCache::new(),
+            &AdaptiveRate::default(),
+        )
+        .await;
+
+        // an unreachable rpc leaves the name unset rather than guessing a placeholder
+        assert_eq!(mock.calls("eth_call"), 2);
+        assert_eq!(pools[0].token0_name(), "");
+    }
+
+    #[tokio::test]
+    async fn test_verify_against_chain() {
+        let tip = MockRpc::start(v3_chain).await;
+        let pools = populate(&tip.url(), PopulationStrategy::RpcBatch, false).await;
+
+        // the pool is read at the block passed in
+        let mock = MockRpc::start(|method, params| {
+            assert_eq!(params[1], "0x64");
+            v3_chain(method, params)
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let mut pool = pools[0].get_v3().unwrap().clone();
+        let report = pool.verify_against_chain(&provider, 100).await.unwrap();
+        assert!(report.is_consistent(), "{:?}", report);
+        assert_eq!(report.liquidity.on_chain, 5000);
+        assert_eq!(report.tick.on_chain, -5);
+
+        // a replay that drifted from the chain
+        pool.liquidity = 4000;
+        pool.tick = -6;
+        let report = pool.verify_against_chain(&provider, 100).await.unwrap();
+        assert!(!report.is_consistent());
+        assert_eq!(report.mismatches(), vec!["tick", "liquidity"]);
+        assert_eq!(report.liquidity.local, 4000);
+    }
+
+    #[tokio::test]
+    async fn test_state_at_block() {
+        let tip = MockRpc::start(v3_chain).await;
+        let pools = populate(&tip.url(), PopulationStrategy::RpcBatch, false).await;
+
+        // liquidity was added between blocks 100 and 200
+        let mock = MockRpc::start(|method, params| match params[1].as_str() {
+            Some("0x64") => v3_chain_with_liquidity(5000, method, params),
+            Some("0xc8") => v3_chain_with_liquidity(9000, method, params),
+            other => panic!("unexpected block {:?}", other),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let mut pool = pools[0].get_v3().unwrap().clone();
+        pool.ticks.insert(
+            -60,
+            TickInfo {
+                liquidity_net: 5000,
+                initialized: true,
+                liquidity_gross: 5000,
+            },
+        );
+
+        let before = pool.state_at_block(&provider, 100).await.unwrap();
+        let after = pool.state_at_block(&provider, 200).await.unwrap();
+        assert_eq!((before.liquidity, before.last_update_block), (5000, 100));
+        assert_eq!((after.liquidity, after.last_update_block), (9000, 200));
+        assert_eq!(after.tick, -5);
+        assert_eq!(after.sqrt_price, U256::from(1u64) << 96);
+        // the rest of the pool is carried over
+        assert_eq!(after.ticks.len(), 1);
+        assert_eq!(after.token0_name, pool.token0_name);
+        assert_eq!(mock.calls("eth_call"), 4);
+    }
+
+    /// A V3 fork whose data-sync output is captured as extras
+    struct ExtraFetcher;
+
+    impl PoolFetcher for ExtraFetcher {
+        fn pool_type(&self) -> PoolType {
+            UniswapV3Fetcher.pool_type()
+        }
+
+        fn family(&self) -> PoolFamily {
+            UniswapV3Fetcher.family()
+        }
+
+        fn factory_address(&self, chain: Chain) -> Address {
+            UniswapV3Fetcher.factory_address(chain)
+        }
+
+        fn pair_created_signature(&self) -> &str {
+            UniswapV3Fetcher.pair_created_signature()
+        }
+
+        fn log_to_address(&self, log: &Log) -> Address {
+            UniswapV3Fetcher.log_to_address(log)
+        }
+
+        fn get_pool_repr(&self) -> DynSolType {
+            UniswapV3Fetcher.get_pool_repr()
+        }
+
+        fn decode_extra(&self, data: &[DynSolValue]) -> Option<HashMap<String, DynSolValue>> {
+            Some(HashMap::from([
+                ("tick_spacing".to_string(), data[8].clone()),
+                ("token1".to_string(), data[3].clone()),
+            ]))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_extra() {
+        let mock = MockRpc::start(v3_chain).await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let config = PopulationConfig {
+            strategy: PopulationStrategy::DataSync,
+            token_concurrency: 4,
+            token_name_attempts: 6,
+            protocol_fee: false,
+            total_supply: false,
+            block: None,
+            retry_budget: RetryBudget::default(),
+            circuit_breaker: CircuitBreaker::default(),
+            debug_raw: false,
+            on_data_sync_revert: DataSyncRevertPolicy::default(),
+            known_tokens: Arc::default(),
+            chunk_size: None,
+            chunk_concurrency: 1,
+            limiter: CallLimiter::default(),
+            rate: AdaptiveRate::default(),
+        };
+        let (pools, _) = build_pools(
+            &provider,
+            vec![addr(100)],
+            PoolType::UniswapV3,
+            &ExtraFetcher,
+            Chain::Mantle,
+            config.clone(),
+        )
+        .await
+        .unwrap();
+
+        let extra = pools[0].extra();
+        assert_eq!(extra.len(), 2);
+        assert_eq!(extra["tick_spacing"], "60");
+        assert_eq!(extra["token1"], addr(2).to_string());
+
+        // the default fetchers capture nothing
+        let pools = populate_with(&mock.url(), config).await;
+        assert!(pools[0].extra().is_empty());
+    }
+
+    /// A UniswapV3 fetcher declaring its own family
+    struct FamilyFetcher(PoolFamily);
+
+    impl PoolFetcher for FamilyFetcher {
+        fn pool_type(&self) -> PoolType {
+            UniswapV3Fetcher.pool_type()
+        }
+
+        fn family(&self) -> PoolFamily {
+            self.0
+        }
+
+        fn factory_address(&self, chain: Chain) -> Address {
+            UniswapV3Fetcher.factory_address(chain)
+        }
+
+        fn pair_created_signature(&self) -> &str {
+            UniswapV3Fetcher.pair_created_signature()
+        }
+
+        fn log_to_address(&self, log: &Log) -> Address {
+            UniswapV3Fetcher.log_to_address(log)
+        }
+
+        fn get_pool_repr(&self) -> DynSolType {
+            UniswapV3Fetcher.get_pool_repr()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetcher_family() {
+        let mock = MockRpc::start(v3_chain).await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let (v3, v2, other) = (
+            FamilyFetcher(PoolFamily::V3),
+            FamilyFetcher(PoolFamily::V2),
+            FamilyFetcher(PoolFamily::Other),
+        );
+        let build = |fetcher| {
+            build_pools(
+                &provider,
+                vec![addr(100)],
+                PoolType::UniswapV3,
+                fetcher,
+                Chain::Mantle,
+                PopulationConfig {
+                    strategy: PopulationStrategy::DataSync,
+                    token_concurrency: 4,
+                    token_name_attempts: 6,
+                    protocol_fee: false,
+                    total_supply: false,
+                    block: None,
+                    retry_budget: RetryBudget::default(),
+                    circuit_breaker: CircuitBreaker::default(),
+                    debug_raw: false,
+                    on_data_sync_revert: DataSyncRevertPolicy::default(),
+                    known_tokens: Arc::default(),
+                    chunk_size: None,
+                    chunk_concurrency: 1,
+                    limiter: CallLimiter::default(),
+                    rate: AdaptiveRate::default(),
+                },
+            )
+        };
+
+        // the family the fetcher declares decides the layout
+        let (pools, failed) = build(&v3).await.unwrap();
+        assert!(failed.is_empty());
+        assert_eq!(pools.len(), 1);
+        assert!(pools[0].is_v3());
+        assert_eq!(pools[0].address(), addr(100));
+
+        // a family that cannot build the pool type is rejected before any read
+        let calls = mock.calls("eth_call");
+        for fetcher in [&other, &v2] {
+            let err = build(fetcher).await.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<PoolSyncError>(),
+                Some(PoolSyncError::UnsupportedPoolFamily(PoolType::UniswapV3, _))
+            ));
+            assert!(validate_pool_family(fetcher).is_err());
+        }
+        assert_eq!(mock.calls("eth_call"), calls);
+
+        // the built-in fetchers keep the family of their pool type
+        assert_eq!(validate_pool_family(&v3).unwrap(), PoolFamily::V3);
+        assert_eq!(UniswapV3Fetcher.family(), PoolFamily::V3);
+        assert_eq!(MerchantMoeV2Fetcher.family(), PoolFamily::V2);
+    }
+
+    #[tokio::test]
+    async fn test_population_shares_adaptive_rate() {
+        // the first data-sync deploy and the first symbol() call answer HTTP 429
+        let deploys = Arc::new(AtomicUsize::new(1));
+        let symbols = Arc::new(AtomicUsize::new(1));
+        let (deploys_left, symbols_left) = (deploys.clone(), symbols.clone());
+        let mock = MockRpc::start(move |method, params| {
+            let (to, input) = call_target(params);
+            let remaining = match to {
+                None => &deploys_left,
+                Some(_) if input.starts_with(&ERC20::symbolCall::SELECTOR) => &symbols_left,
+                _ => return v3_chain(method, params),
+            };
+            match remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)) {
+                Ok(_) => MockResponse::Http(429),
+                Err(_) => v3_chain(method, params),
+            }
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let rate = AdaptiveRate::new(8);
+        let (pools, failed) = build_pools(
+            &provider,
+            vec![addr(100)],
+            PoolType::UniswapV3,
+            &UniswapV3Fetcher,
+            Chain::Mantle,
+            PopulationConfig {
+                strategy: PopulationStrategy::DataSync,
+                token_concurrency: 4,
+                token_name_attempts: 6,
+                protocol_fee: false,
+                total_supply: false,
+                block: None,
+                retry_budget: RetryBudget::default(),
+                circuit_breaker: CircuitBreaker::default(),
+                debug_raw: false,
+                on_data_sync_revert: DataSyncRevertPolicy::default(),
+                known_tokens: Arc::default(),
+                chunk_size: None,
+                chunk_concurrency: 1,
+                limiter: CallLimiter::default(),
+                rate: rate.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        assert!(failed.is_empty());
+        assert_eq!(pools[0].token0_name(), "T0");
+        assert_eq!(
+            (
+                deploys.load(Ordering::SeqCst),
+                symbols.load(Ordering::SeqCst)
+            ),
+            (0, 0)
+        );
+
+        // both 429s lowered the rate shared with the log queries, a single one would have
+        // left it near 4/s
+        let current = rate.current_rate().unwrap();
+        assert!(current < 3.5, "{}", current);
+    }
+
+    #[tokio::test]
+    async fn test_data_sync_deploy_revert() {
+        // the provider rejects the deploy but answers the pool's own view functions
+        let deploy_count = Arc::new(AtomicUsize::new(0));
+        let counted = deploy_count.clone();
+        let mock = MockRpc::start(move |method, params| match call_target(params) {
+            (None, _) if method == "eth_call" => {
+                counted.fetch_add(1, Ordering::SeqCst);
+                MockResponse::Error(3, "execution reverted: out of gas".into())
+            }
+            _ => v3_chain(method, params),
+        })
+        .await;
+        let deploys = || deploy_count.load(Ordering::SeqCst);
+        let config = |on_data_sync_revert| PopulationConfig {
+            strategy: PopulationStrategy::DataSync,
+            token_concurrency: 4,
+            token_name_attempts: 6,
+            protocol_fee: false,
+            total_supply: false,
+            block: None,
+            retry_budget: RetryBudget::default(),
+            circuit_breaker: CircuitBreaker::default(),
+            debug_raw: false,
+            on_data_sync_revert,
+            known_tokens: Arc::default(),
+            chunk_size: None,
+            chunk_concurrency: 1,
+            limiter: CallLimiter::default(),
+            rate: AdaptiveRate::default(),
+        };
+
+        // the batch is read again with rpc calls, without retrying the deploy
+        let pools = populate_with(
+            &mock.url(),
+            config(DataSyncRevertPolicy::FallbackToRpcBatch),
+        )
+        .await;
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].get_v3().unwrap().liquidity, 5000);
+        assert_eq!(deploys(), 1);
+
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let error = build_pools(
+            &provider,
+            vec![addr(100)],
+            PoolType::UniswapV3,
+            &UniswapV3Fetcher,
+            Chain::Mantle,
+            config(DataSyncRevertPolicy::Fail),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PoolSyncError>(),
+            Some(PoolSyncError::DataSyncUnsupported(PoolType::UniswapV3, _))
+        ));
+        assert_eq!(deploys(), 2);
+    }
+
+    /// Answers a data-sync deploy with the V3 state of every pool of `pools` named in its
+    /// input, pool `n` having liquidity `1000 + n` and tick `n`
+    fn data_sync_response(pools: &[Address], input: &[u8]) -> MockResponse {
+        let tuples = pools
+            .iter()
+            .enumerate()
+            .filter(|(_, pool)| input.windows(20).any(|window| window == pool.as_slice()))
+            .map(|(n, pool)| {
+                DynSolValue::Tuple(vec![
+                    DynSolValue::Address(*pool),
+                    DynSolValue::Address(addr(1)),
+                    DynSolValue::Uint(U256::from(18u64), 8),
+                    DynSolValue::Address(addr(2)),
+                    DynSolValue::Uint(U256::from(6u64), 8),
+                    DynSolValue::Uint(U256::from(1000 + n), 128),
+                    DynSolValue::Uint(U256::from(1u64) << 96, 160),
+                    DynSolValue::Int(I256::try_from(n).unwrap(), 24),
+                    DynSolValue::Int(I256::try_from(60).unwrap(), 24),
+                    DynSolValue::Uint(U256::from(3000u64), 24),
+                    DynSolValue::Int(I256::ZERO, 128),
+                ])
+            })
+            .collect();
+        MockResponse::Result(DynSolValue::Array(tuples).abi_encode().into())
+    }
+
+    #[tokio::test]
+    async fn test_chunked_population_matches_single_call() {
+        let deploys = Arc::new(AtomicUsize::new(0));
+        let seen = deploys.clone();
+        let pools: Vec<Address> = (0..10).map(|n| addr(100 + n)).collect();
+        let requested = pools.clone();
+        let mock = MockRpc::start(move |method, params| {
+            if method != "eth_call" {
+                return MockResponse::Error(-32601, "method not found".into());
+            }
+            match call_target(params) {
+                (None, input) => {
+                    seen.fetch_add(1, Ordering::SeqCst);
+                    data_sync_response(&requested, &input)
+                }
+                (Some(_), _) => MockResponse::Result(call_result("TKN".to_string())),
+            }
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+        let build = |chunk_size, chunk_concurrency| {
+            let config = PopulationConfig {
+                strategy: PopulationStrategy::DataSync,
+                token_concurrency: 4,
+                token_name_attempts: 1,
+                protocol_fee: false,
+                total_supply: false,
+                block: None,
+                retry_budget: RetryBudget::default(),
+                circuit_breaker: CircuitBreaker::default(),
+                debug_raw: false,
+                on_data_sync_revert: DataSyncRevertPolicy::default(),
+                known_tokens: Arc::default(),
+                chunk_size,
+                chunk_concurrency,
+                limiter: CallLimiter::default(),
+                rate: AdaptiveRate::default(),
+            };
+            build_pools(
+                &provider,
+                pools.clone(),
+                PoolType::UniswapV3,
+                &UniswapV3Fetcher,
+                Chain::Mantle,
+                config,
+            )
+        };
+
+        let (single, _) = build(None, 1).await.unwrap();
+        assert_eq!(deploys.swap(0, Ordering::SeqCst), 1);
+        let (chunked, _) = build(Some(3), 2).await.unwrap();
+        assert_eq!(deploys.load(Ordering::SeqCst), 4);
+
+        let addresses: Vec<Address> = chunked.iter().map(Pool::address).collect();
+        assert_eq!(addresses, pools);
+        assert_eq!(
+            serde_json::to_value(&chunked).unwrap(),
+            serde_json::to_value(&single).unwrap()
+        );
+    }
+
+    fn chunk_config(chunk_size: usize, chunk_concurrency: usize) -> PopulationConfig {
+        PopulationConfig {
+            strategy: PopulationStrategy::DataSync,
+            token_concurrency: 4,
+            token_name_attempts: 1,
+            protocol_fee: false,
+            total_supply: false,
+            block: None,
+            retry_budget: RetryBudget::default(),
+            circuit_breaker: CircuitBreaker::default(),
+            debug_raw: false,
+            on_data_sync_revert: DataSyncRevertPolicy::default(),
+            known_tokens: Arc::default(),
+            chunk_size: Some(chunk_size),
+            chunk_concurrency,
+            limiter: CallLimiter::default(),
+            rate: AdaptiveRate::default(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_chunk_keeps_the_others() {
+        // every read of a chunk holding pool 103 fails
+        let pools: Vec<Address> = (0..10).map(|n| addr(100 + n)).collect();
+        let requested = pools.clone();
+        let mock = MockRpc::start(move |method, params| match call_target(params) {
+            (None, input) if method == "eth_call" => {
+                if input
+                    .windows(20)
+                    .any(|window| window == addr(103).as_slice())
+                {
+                    MockResponse::Error(-32000, "execution timeout".into())
+                } else {
+                    data_sync_response(&requested, &input)
+                }
+            }
+            (Some(_), _) => MockResponse::Result(call_result("TKN".to_string())),
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        let (populated, failed) = build_pools(
+            &provider,
+            pools.clone(),
+            PoolType::UniswapV3,
+            &UniswapV3Fetcher,
+            Chain::Mantle,
+            chunk_config(3, 2),
+        )
+        .await
+        .unwrap();
+        let addresses: Vec<Address> = populated.iter().map(Pool::address).collect();
+        let expected: Vec<Address> = [100, 101, 102, 106, 107, 108, 109]
+            .into_iter()
+            .map(addr)
+            .collect();
+        assert_eq!(addresses, expected);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, vec![addr(103), addr(104), addr(105)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_chunks_share_the_rate_limit() {
+        // deploys in flight, each held open long enough for the others to overlap it
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let peak_seen = peak.clone();
+        let pools: Vec<Address> = (0..100).map(|n| addr(1000 + n)).collect();
+        let requested = pools.clone();
+        let mock = MockRpc::start(move |method, params| match call_target(params) {
+            (None, input) if method == "eth_call" => {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak_seen.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                data_sync_response(&requested, &input)
+            }
+            (Some(_), _) => MockResponse::Result(call_result("TKN".to_string())),
+            _ => MockResponse::Error(-32601, "method not found".into()),
+        })
+        .await;
+        let provider = Arc::new(ProviderBuilder::new().on_http(mock.url().parse().unwrap()));
+
+        // two batches of up to 50 pools, each split into chunks of 10 read 3 at a time
+        let (populated, failed) = Rpc::populate_pools(
+            pools.clone(),
+            provider,
+            PoolType::UniswapV3,
+            Arc::new(UniswapV3Fetcher),
+            3,
+            Chain::Mantle,
+            chunk_config(10, 3),
+        )
+        .await
+        .unwrap();
+        assert_eq!(populated.len(), 100);
+        assert!(failed.is_empty());
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[tokio::test]
+    async fn test_debug_raw_tuples() {
+        let mock = MockRpc::start(v3_chain).await;
+        let pools = populate(&mock.url(), PopulationStrategy::DataSync, false).await;
+        assert_eq!(pools[0].debug_raw(), None);
+        // pools without raw values leave the field out of the cache
+        let json = serde_json::to_value(&pools[0]).unwrap();
+        assert!(json["UniswapV3"].get("address").is_some());
+        assert!(json["UniswapV3"].get("debug_raw").is_none());
+
+        let config = PopulationConfig {
+            strategy: PopulationStrategy::DataSync,
+            token_concurrency: 4,
+            token_name_attempts: 6,
+            protocol_fee: false,
+            total_supply: false,
+            block: None,
+            retry_budget: RetryBudget::default(),
+            circuit_breaker: CircuitBreaker::default(),
+            debug_raw: true,
+            on_data_sync_revert: DataSyncRevertPolicy::default(),
+            known_tokens: Arc::default(),
+            chunk_size: None,
+            chunk_concurrency: 1,
+            limiter: CallLimiter::default(),
+            rate: AdaptiveRate::default(),
+        };
+        let pools = populate_with(&mock.url(), config).await;
+        let sqrt_price = (U256::from(1u64) << 96usize).to_string();
+        let expected = [
+            addr(100).to_string(),
+            addr(1).to_string(),
+            "18".to_string(),
+            addr(2).to_string(),
+            "6".to_string(),
+            "5000".to_string(),
+            sqrt_price,
+            "-5".to_string(),
+            "60".to_string(),
+            "3000".to_string(),
+            "0".to_string(),
+        ];
+        assert_eq!(pools[0].debug_raw(), Some(&expected[..]));
+        // the raw values survive the cache round trip
+        let json = serde_json::to_string(&pools[0]).unwrap();
+        let cached: Pool = serde_json::from_str(&json).unwrap();
+        assert_eq!(cached.debug_raw(), Some(&expected[..]));
+    }
+}
@@ -0,0 +1,2378 @@
+#[cfg(test)]
+mod sync_test {
+    use crate::cache::{
+        cache_file_path, checkpoint_file_path, epoch_files, read_cache_file, write_cache_file,
+        CacheFileInfo, CacheStore, FileCacheStore, PendingPool, PoolCache, PopulationState,
+        TokenMetadata,
+    };
+    use crate::events::DataEvents;
+    use crate::pool_sync::{resolve_address_collisions, resolve_start_block};
+    use crate::pools::gen::{UniswapV3Factory, V2PairState, V3PoolState, ERC20};
+    use crate::pools::pool_fetchers::UniswapV3Fetcher;
+    use crate::pools::pool_structures::v2_structure::MerchantMoeV2Pool;
+    use crate::pools::PoolFetcher;
+    use crate::tests::fixtures::*;
+    use crate::tests::log_capture;
+    use crate::tests::mock_rpc::*;
+    use crate::{
+        Chain, CollisionPolicy, Pool, PoolCreation, PoolInfo, PoolSync, PoolSyncError, PoolType,
+        RpcConfig, SyncProgress, UniswapV3Pool,
+    };
+    use alloy::dyn_abi::DynSolValue;
+    use alloy::primitives::aliases::I24;
+    use alloy::primitives::{address, Address, B256, I256, U256};
+    use alloy::sol_types::SolCall;
+    use futures::StreamExt;
+    use log::Level;
+    use serde_json::json;
+    use serde_json::Value;
+    use std::collections::{HashMap, HashSet};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn empty_chain(method: &str, _params: &serde_json::Value) -> MockResponse {
+        match method {
+            "eth_blockNumber" => MockResponse::Result(json!("0xc8")),
+            "eth_getLogs" => MockResponse::Result(json!([])),
+            other => MockResponse::Error(-32601, format!("unexpected method {}", other)),
+        }
+    }
+
+    fn mock_sync(
+        archive: &MockRpc,
+        full: &MockRpc,
+        cache_dir: &Path,
+        start: u64,
+        end: u64,
+    ) -> PoolSync {
+        PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(start, end)
+            .cache_dir(cache_dir)
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(full.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_configured_urls() {
+        let archive = MockRpc::start(empty_chain).await;
+        let full = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let pool_sync = mock_sync(&archive, &full, dir.path(), 100, 200);
+
+        let (pools, last_synced_block) = pool_sync.sync_pools().await.unwrap();
+        assert!(pools.is_empty());
+        assert_eq!(last_synced_block, 200);
+        assert!(archive.calls("eth_getLogs") > 0);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_timing_logged() {
+        log_capture::init();
+        let archive = MockRpc::start(empty_chain).await;
+        let full = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        mock_sync(&archive, &full, dir.path(), 4242, 4300)
+            .sync_pools()
+            .await
+            .unwrap();
+
+        let timings = log_capture::captured(Level::Debug, "blocks 4242-4300");
+        assert_eq!(timings.len(), 1);
+        assert!(timings[0].contains("fetch_pool_addrs took"));
+        assert!(timings[0].contains("populate_liquidity took"));
+    }
+
+    #[test]
+    fn test_start_block_precedence() {
+        // cache only
+        assert_eq!(resolve_start_block(500, None), 501);
+        // start_block ahead of the cache raises the start
+        assert_eq!(resolve_start_block(500, Some(800)), 800);
+        // a cache past start_block resumes from the cache
+        assert_eq!(resolve_start_block(500, Some(100)), 501);
+    }
+
+    #[tokio::test]
+    async fn test_force_start_block_ignores_cache() {
+        let from_blocks = Arc::new(Mutex::new(Vec::new()));
+        let recorded = from_blocks.clone();
+        let archive = MockRpc::start(move |method, params| {
+            if method == "eth_getLogs" {
+                let from = params[0]["fromBlock"].as_str().unwrap();
+                let from = u64::from_str_radix(from.trim_start_matches("0x"), 16).unwrap();
+                recorded.lock().unwrap().push(from);
+            }
+            empty_chain(method, params)
+        })
+        .await;
+        let full = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PoolCache {
+            last_synced_block: 500,
+            pool_type: PoolType::UniswapV3,
+            pools: vec![v3_pool(PoolType::UniswapV3, addr(10), addr(1), addr(2))],
+            is_initial_sync: false,
+            next_creation_index: 1,
+            pending: Vec::new(),
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+
+        // start_block and the cache would both resume at 501, force wins over both
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(300, 600)
+            .force_start_block(100)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(full.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(pool_sync.force_start_block, Some(100));
+
+        let (pools, last_synced_block) = pool_sync.sync_pools().await.unwrap();
+        assert!(pools.is_empty());
+        assert_eq!(last_synced_block, 600);
+        assert_eq!(from_blocks.lock().unwrap().iter().min(), Some(&100));
+
+        // a forced start at the genesis includes block 0
+        from_blocks.lock().unwrap().clear();
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .end_block(50)
+            .force_start_block(0)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(full.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+        let (_, last_synced_block) = pool_sync.sync_pools().await.unwrap();
+        assert_eq!(last_synced_block, 50);
+        assert_eq!(from_blocks.lock().unwrap().iter().min(), Some(&0));
+    }
+
+    #[tokio::test]
+    async fn test_liquidity_range_separate_from_discovery() {
+        // (fromBlock, toBlock, is discovery) of every log query
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let recorded = ranges.clone();
+        let discovered = discovered_chain(vec![(addr(10), addr(1), addr(2), 120)]);
+        let archive = MockRpc::start(move |method, params| {
+            if method == "eth_getLogs" {
+                let block = |key: &str| {
+                    let hex = params[0][key].as_str().unwrap().trim_start_matches("0x");
+                    u64::from_str_radix(hex, 16).unwrap()
+                };
+                let discovery = params[0].get("address").is_some();
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push((block("fromBlock"), block("toBlock"), discovery));
+            }
+            discovered(method, params)
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 400)
+            .liquidity_range(50, 300)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+        let (pools, _) = pool_sync.sync_pools().await.unwrap();
+        assert_eq!(pools.len(), 1);
+
+        let ranges = ranges.lock().unwrap();
+        let span = |discovery: bool| {
+            let matching = ranges.iter().filter(|range| range.2 == discovery);
+            let from = matching.clone().map(|range| range.0).min();
+            let to = matching.map(|range| range.1).max();
+            (from, to)
+        };
+        assert_eq!(span(true), (Some(100), Some(400)));
+        // the window is clamped to the blocks of the sync
+        assert_eq!(span(false), (Some(100), Some(300)));
+    }
+
+    /// Answers a chain whose V3 factory created each `(pool, token0, token1, block)`, with
+    /// the data-sync call returning the requested pools and each token's symbol being TKN
+    fn discovered_chain(
+        pools: Vec<(Address, Address, Address, u64)>,
+    ) -> impl Fn(&str, &Value) -> MockResponse + Send + Sync + 'static {
+        let factory = UniswapV3Fetcher.factory_address(Chain::Mantle);
+        move |method, params| match method {
+            "eth_getLogs" if params[0].get("address").is_some() => {
+                let block = |key: &str| {
+                    let hex = params[0][key].as_str().unwrap().trim_start_matches("0x");
+                    u64::from_str_radix(hex, 16).unwrap()
+                };
+                let (from, to) = (block("fromBlock"), block("toBlock"));
+                let logs: Vec<_> = pools
+                    .iter()
+                    .filter(|(_, _, _, created)| (from..=to).contains(created))
+                    .map(|(pool, token0, token1, created)| {
+                        let event = UniswapV3Factory::PoolCreated {
+                            token0: *token0,
+                            token1: *token1,
+                            fee: Default::default(),
+                            tickSpacing: Default::default(),
+                            pool: *pool,
+                        };
+                        event_log(factory, &event, *created)
+                    })
+                    .collect();
+                MockResponse::Result(json!(logs))
+            }
+            "eth_call" => match call_target(params) {
+                (None, input) => {
+                    // only the pools passed to the data-sync constructor
+                    let requested =
+                        |pool: &Address| input.windows(20).any(|window| window == pool.as_slice());
+                    let tuples = pools
+                        .iter()
+                        .filter(|(pool, _, _, _)| requested(pool))
+                        .map(|(pool, token0, token1, _)| {
+                            DynSolValue::Tuple(vec![
+                                DynSolValue::Address(*pool),
+                                DynSolValue::Address(*token0),
+                                DynSolValue::Uint(U256::from(18u64), 8),
+                                DynSolValue::Address(*token1),
+                                DynSolValue::Uint(U256::from(18u64), 8),
+                                DynSolValue::Uint(U256::from(1000u64), 128),
+                                DynSolValue::Uint(U256::from(1u64) << 96, 160),
+                                DynSolValue::Int(I256::ZERO, 24),
+                                DynSolValue::Int(I256::try_from(60).unwrap(), 24),
+                                DynSolValue::Uint(U256::from(3000u64), 24),
+                                DynSolValue::Int(I256::ZERO, 128),
+                            ])
+                        })
+                        .collect();
+                    MockResponse::Result(DynSolValue::Array(tuples).abi_encode().into())
+                }
+                (Some(_), _) => MockResponse::Result(call_result("TKN".to_string())),
+            },
+            other => empty_chain(other, params),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_drops_pools_before_caching() {
+        let archive = MockRpc::start(discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(3), addr(1), 130),
+            (addr(12), addr(4), addr(5), 140),
+        ]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .filter(Box::new(|pool: &Pool| {
+                pool.token0_address() == addr(1) || pool.token1_address() == addr(1)
+            }))
+            .build()
+            .unwrap();
+
+        let (mut pools, _) = pool_sync.sync_pools().await.unwrap();
+        pools.sort_by_key(|pool| pool.address());
+        let kept: Vec<Address> = pools.iter().map(|pool| pool.address()).collect();
+        assert_eq!(kept, vec![addr(10), addr(11)]);
+        assert_eq!(pool_sync.load_cached_pools().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_excluded_addresses_never_populated() {
+        let excluded = addr(11);
+        let requested = Arc::new(AtomicUsize::new(0));
+        let seen = requested.clone();
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (excluded, addr(1), addr(3), 130),
+            (addr(12), addr(1), addr(4), 140),
+        ]);
+        let archive = MockRpc::start(move |method, params| {
+            if method == "eth_call" {
+                let (_, input) = call_target(params);
+                if input
+                    .windows(20)
+                    .any(|window| window == excluded.as_slice())
+                {
+                    seen.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            chain(method, params)
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .exclude_addresses([excluded].into_iter().collect())
+            .build()
+            .unwrap();
+
+        let (mut pools, _) = pool_sync.sync_pools().await.unwrap();
+        pools.sort_by_key(|pool| pool.address());
+        let kept: Vec<Address> = pools.iter().map(|pool| pool.address()).collect();
+        assert_eq!(kept, vec![addr(10), addr(12)]);
+        assert_eq!(requested.load(Ordering::SeqCst), 0);
+        let cached = pool_sync.load_cached_pools().unwrap();
+        assert!(cached.iter().all(|pool| pool.address() != excluded));
+        assert_eq!(cached.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sync_pools_chronological() {
+        // addresses deliberately out of block order
+        let archive = MockRpc::start(discovered_chain(vec![
+            (addr(30), addr(1), addr(2), 180),
+            (addr(10), addr(1), addr(3), 120),
+            (addr(20), addr(1), addr(4), 150),
+        ]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        // cached pools from before creation blocks were tracked
+        let mut cached = vec![
+            v3_pool(PoolType::UniswapV3, addr(41), addr(5), addr(6)),
+            v3_pool(PoolType::UniswapV3, addr(40), addr(5), addr(7)),
+        ];
+        cached[0].set_creation_index(1);
+        let cache = PoolCache {
+            last_synced_block: 99,
+            pool_type: PoolType::UniswapV3,
+            pools: cached,
+            is_initial_sync: false,
+            next_creation_index: 2,
+            pending: Vec::new(),
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+
+        let (pools, _) = pool_sync.sync_pools_chronological().await.unwrap();
+        let order: Vec<(Address, Option<u64>)> = pools
+            .iter()
+            .map(|pool| (pool.address(), pool.created_at_block()))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (addr(40), None),
+                (addr(41), None),
+                (addr(10), Some(120)),
+                (addr(20), Some(150)),
+                (addr(30), Some(180)),
+            ]
+        );
+    }
+
+    // paused time skips the retry backoff
+    #[tokio::test(start_paused = true)]
+    async fn test_population_failures_reported() {
+        let chain = discovered_chain(vec![(addr(10), addr(1), addr(2), 120)]);
+        let archive = MockRpc::start(move |method, params| match method {
+            "eth_call" => MockResponse::Error(-32000, "execution timeout".into()),
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+
+        let (pools, last_synced_block, stats) = pool_sync.sync_pools_with_stats().await.unwrap();
+        assert!(pools.is_empty());
+        assert_eq!(last_synced_block, 200);
+        assert_eq!(stats.failures.len(), 1);
+        let failure = &stats.failures[0];
+        assert_eq!(failure.pool_type, PoolType::UniswapV3);
+        assert_eq!(failure.block_range, (100, 200));
+        assert_eq!(failure.addresses, vec![addr(10)]);
+        assert!(failure.error.contains("execution timeout"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_budget_aborts_sync() {
+        // every batch keeps failing, without a budget each would retry MAX_RETRIES times
+        let chain = discovered_chain(
+            (1..=3)
+                .map(|n| (addr(10 + n), addr(1), addr(1 + n), 100 + n))
+                .collect(),
+        );
+        let archive = MockRpc::start(move |method, params| match method {
+            "eth_call" => MockResponse::Error(-32000, "execution timeout".into()),
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .retry_budget(2)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+
+        let result = pool_sync.sync_pools().await;
+        assert!(matches!(result, Err(PoolSyncError::RetryBudgetExceeded)));
+        // the first attempt plus the two budgeted retries
+        assert_eq!(archive.calls("eth_call"), 3);
+        // an aborted sync leaves the cache untouched
+        assert!(pool_sync.load_cached_pools().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_pools_held_back() {
+        let tip = Arc::new(AtomicU64::new(200));
+        let chain_tip = tip.clone();
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), addr(3), 198),
+        ]);
+        let archive = MockRpc::start(move |method, params| match method {
+            "eth_blockNumber" => {
+                MockResponse::Result(json!(format!("{:#x}", chain_tip.load(Ordering::SeqCst))))
+            }
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = || {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .start_block(100)
+                .confirmations(5)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        // the pool created 2 blocks before the tip is not cached yet
+        let (pools, last_synced_block) = pool_sync().sync_pools().await.unwrap();
+        assert_eq!(last_synced_block, 195);
+        let addresses: Vec<Address> = pools.iter().map(Pool::address).collect();
+        assert_eq!(addresses, vec![addr(10)]);
+        assert_eq!(pool_sync().load_cached_pools().unwrap().len(), 1);
+
+        // once the tip moves on it is picked up
+        tip.store(210, Ordering::SeqCst);
+        let (pools, last_synced_block) = pool_sync().sync_pools().await.unwrap();
+        assert_eq!(last_synced_block, 205);
+        let mut addresses: Vec<Address> = pools.iter().map(Pool::address).collect();
+        addresses.sort();
+        assert_eq!(addresses, vec![addr(10), addr(11)]);
+    }
+
+    #[tokio::test]
+    async fn test_token_order_mismatch_logged() {
+        log_capture::init();
+        let pool = Address::repeat_byte(0x51);
+        let (token0, token1) = (addr(1), addr(2));
+        // the data-sync output lists the tokens the other way round from the factory event
+        let chain = discovered_chain(vec![(pool, token0, token1, 120)]);
+        let swapped = discovered_chain(vec![(pool, token1, token0, 120)]);
+        let archive = MockRpc::start(move |method, params| match method {
+            "eth_call" => swapped(method, params),
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let (pools, _) = mock_sync(&archive, &archive, dir.path(), 100, 200)
+            .sync_pools()
+            .await
+            .unwrap();
+        assert_eq!(pools.len(), 1);
+
+        let mismatches = log_capture::captured(Level::Warn, &pool.to_string());
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].contains(&format!(
+            "data-sync returned tokens ({}, {}) but the factory created it with ({}, {})",
+            token1, token0, token0, token1
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_sync_pools_batched() {
+        let archive = MockRpc::start(discovered_chain(
+            (1..=5)
+                .map(|n| (addr(10 + n), addr(1), addr(1 + n), 100 + n))
+                .collect(),
+        ))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+
+        let mut stream = Box::pin(pool_sync.sync_pools_batched(2));
+        let mut batches = vec![stream.next().await.unwrap().unwrap()];
+        // the sync waits for the first batch to be taken, so it has not written the cache yet
+        let cache_file = cache_file_path(dir.path(), &PoolType::UniswapV3, Chain::Mantle);
+        assert!(!cache_file.exists());
+        while let Some(batch) = stream.next().await {
+            batches.push(batch.unwrap());
+        }
+        assert!(cache_file.exists());
+        let sizes: Vec<usize> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+
+        let mut addresses: Vec<Address> = batches.iter().flatten().map(Pool::address).collect();
+        addresses.sort();
+        assert_eq!(addresses, (11..=15).map(addr).collect::<Vec<_>>());
+
+        // a sync over the cache hands out the cached pools once it completes
+        let pool_sync = mock_sync(&archive, &archive, dir.path(), 100, 300);
+        let batches: Vec<Vec<Pool>> = pool_sync
+            .sync_pools_batched(2)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let sizes: Vec<usize> = batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn test_collision_policies() {
+        // addr(10) was found by both v3 factories, addr(11) only by uniswap
+        let pools = || {
+            vec![
+                v3_pool(PoolType::Agni, addr(10), addr(1), addr(2)),
+                v3_pool(PoolType::UniswapV3, addr(11), addr(1), addr(3)),
+                v3_pool(PoolType::UniswapV3, addr(10), addr(1), addr(2)),
+            ]
+        };
+        let kept = |policy| {
+            let (pools, collisions) = resolve_address_collisions(pools(), policy);
+            assert_eq!(collisions.len(), 1);
+            assert_eq!(collisions[0].address, addr(10));
+            assert_eq!(
+                collisions[0].pool_types,
+                vec![PoolType::UniswapV3, PoolType::Agni]
+            );
+            pools
+                .iter()
+                .map(|pool| (pool.address(), pool.pool_type()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kept(CollisionPolicy::KeepAll).len(), 3);
+        assert_eq!(
+            kept(CollisionPolicy::KeepFirst),
+            vec![
+                (addr(11), PoolType::UniswapV3),
+                (addr(10), PoolType::UniswapV3)
+            ]
+        );
+        assert_eq!(
+            kept(CollisionPolicy::Prefer(PoolType::Agni)),
+            vec![(addr(10), PoolType::Agni), (addr(11), PoolType::UniswapV3)]
+        );
+        // not a colliding protocol, falls back to the first
+        assert_eq!(
+            kept(CollisionPolicy::Prefer(PoolType::MerchantMoe)),
+            kept(CollisionPolicy::KeepFirst)
+        );
+        assert_eq!(
+            kept(CollisionPolicy::DropAll),
+            vec![(addr(11), PoolType::UniswapV3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cross_protocol_collision_warned() {
+        log_capture::init();
+        let archive = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        let collided = Address::repeat_byte(0x42);
+        for pool_type in [PoolType::UniswapV3, PoolType::Agni] {
+            let cache = PoolCache {
+                last_synced_block: 200,
+                pool_type,
+                pools: vec![v3_pool(pool_type, collided, addr(1), addr(2))],
+                is_initial_sync: false,
+                next_creation_index: 1,
+                pending: Vec::new(),
+                discovered_block: None,
+            };
+            write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+        }
+
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .add_pool(PoolType::Agni)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .collision_policy(CollisionPolicy::Prefer(PoolType::Agni))
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+
+        let (pools, _, stats) = pool_sync.sync_pools_with_stats().await.unwrap();
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].pool_type(), PoolType::Agni);
+        assert_eq!(stats.collisions.len(), 1);
+        assert_eq!(
+            log_capture::captured(Level::Warn, &collided.to_string()).len(),
+            1
+        );
+
+        // the caches keep both pools
+        assert_eq!(pool_sync.load_cached_pools().unwrap().len(), 2);
+    }
+
+    async fn peak_concurrent_protocols(max_concurrent_protocols: usize) -> usize {
+        // log queries in flight per factory, a protocol may have several chunks in flight
+        let in_flight: Arc<Mutex<HashMap<String, usize>>> = Arc::default();
+        let peak = Arc::new(AtomicUsize::new(0));
+        let peak_seen = peak.clone();
+        let archive = MockRpc::start(move |method, params| {
+            // hold every factory log query open long enough for the others to overlap it
+            if let ("eth_getLogs", Some(factory)) = (method, params[0]["address"].as_str()) {
+                {
+                    let mut in_flight = in_flight.lock().unwrap();
+                    *in_flight.entry(factory.to_string()).or_default() += 1;
+                    peak_seen.fetch_max(
+                        in_flight.values().filter(|n| **n > 0).count(),
+                        Ordering::SeqCst,
+                    );
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                *in_flight.lock().unwrap().get_mut(factory).unwrap() -= 1;
+            }
+            empty_chain(method, params)
+        })
+        .await;
+        let full = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        PoolSync::builder()
+            .add_pools(&[PoolType::UniswapV3, PoolType::Agni, PoolType::MerchantMoe])
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .max_concurrent_protocols(max_concurrent_protocols)
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(full.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+        peak.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_concurrent_protocols() {
+        assert_eq!(peak_concurrent_protocols(1).await, 1);
+        assert_eq!(peak_concurrent_protocols(2).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_sink_writes_each_pool() {
+        let archive = MockRpc::start(discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(3), addr(1), 130),
+            (addr(12), addr(4), addr(5), 140),
+        ]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let sink = dir.path().join("pools.ndjson");
+
+        let (pools, _) = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .ndjson_sink(&sink)
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&sink).unwrap();
+        let mut written: Vec<Address> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<Pool>(line).unwrap().address())
+            .collect();
+        written.sort();
+        assert_eq!(written, vec![addr(10), addr(11), addr(12)]);
+        assert_eq!(written.len(), pools.len());
+    }
+
+    /// Syncs a cache at `cache_block` over `block_range(start, end)`, returning the
+    /// `(fromBlock, toBlock)` bounds of the factory queries and the returned block
+    async fn sync_over_cache(cache_block: u64, start: u64, end: u64) -> (Option<(u64, u64)>, u64) {
+        let factory = UniswapV3Fetcher.factory_address(Chain::Mantle);
+        let bounds: Arc<Mutex<Option<(u64, u64)>>> = Arc::default();
+        let recorded = bounds.clone();
+        let archive = MockRpc::start(move |method, params| {
+            if method == "eth_getLogs" && params[0]["address"] == json!(factory) {
+                let block = |key: &str| {
+                    let hex = params[0][key].as_str().unwrap().trim_start_matches("0x");
+                    u64::from_str_radix(hex, 16).unwrap()
+                };
+                let mut bounds = recorded.lock().unwrap();
+                let (from, to) = bounds.unwrap_or((u64::MAX, 0));
+                *bounds = Some((from.min(block("fromBlock")), to.max(block("toBlock"))));
+            }
+            empty_chain(method, params)
+        })
+        .await;
+        let full = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PoolCache {
+            last_synced_block: cache_block,
+            pool_type: PoolType::UniswapV3,
+            pools: vec![v3_pool(PoolType::UniswapV3, addr(10), addr(1), addr(2))],
+            is_initial_sync: false,
+            next_creation_index: 1,
+            pending: Vec::new(),
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+
+        let (pools, last_synced_block) = mock_sync(&archive, &full, dir.path(), start, end)
+            .sync_pools()
+            .await
+            .unwrap();
+        // the cached pool is kept whatever was fetched
+        assert_eq!(pools.len(), 1);
+        let bounds = *bounds.lock().unwrap();
+        (bounds, last_synced_block)
+    }
+
+    #[tokio::test]
+    async fn test_block_range_over_cache() {
+        // cache behind start, end above the cache: nothing below start is fetched
+        assert_eq!(
+            sync_over_cache(100, 500, 800).await,
+            (Some((500, 800)), 800)
+        );
+        // cache ahead of start, end above the cache: resumes from the cache
+        assert_eq!(
+            sync_over_cache(600, 500, 800).await,
+            (Some((601, 800)), 800)
+        );
+        // cache ahead of start, end below the cache: nothing left to fetch
+        assert_eq!(sync_over_cache(900, 500, 800).await, (None, 900));
+        // cache behind start, end below the cache: an empty range fetches nothing
+        assert_eq!(sync_over_cache(600, 700, 500).await, (None, 600));
+    }
+
+    #[tokio::test]
+    async fn test_symbol_regex_filter() {
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(3), addr(4), 130),
+            (addr(12), addr(4), addr(5), 140),
+        ]);
+        let symbols = HashMap::from([(addr(1), "PEPE"), (addr(5), "BabyPepe")]);
+        let archive = MockRpc::start(move |method, params| match call_target(params) {
+            (Some(token), _) if method == "eth_call" => {
+                let symbol = symbols.get(&token).copied().unwrap_or("USDC");
+                MockResponse::Result(call_result(symbol.to_string()))
+            }
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let builder = || {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, 200)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+        };
+        let (mut pools, _) = builder()
+            .symbol_regex("(?i)^(baby)?pepe$")
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+        pools.sort_by_key(|pool| pool.address());
+        let kept: Vec<Address> = pools.iter().map(|pool| pool.address()).collect();
+        assert_eq!(kept, vec![addr(10), addr(12)]);
+
+        assert!(matches!(
+            builder().symbol_regex("(pepe").build(),
+            Err(PoolSyncError::InvalidSymbolRegex(_))
+        ));
+    }
+
+    /// Answers a chain whose V3 factory created pool 10 at block 120 with liquidity 1000 at
+    /// tick 0, minting each `(block, tick_lower, tick_upper, amount)` position on it
+    fn minting_chain(
+        mints: Vec<(u64, i32, i32, u128)>,
+    ) -> impl Fn(&str, &Value) -> MockResponse + Send + Sync + 'static {
+        let discovered = discovered_chain(vec![(addr(10), addr(1), addr(2), 120)]);
+        move |method, params| match method {
+            "eth_getLogs" if params[0].get("address").is_none() => {
+                let block = |key: &str| {
+                    let hex = params[0][key].as_str().unwrap().trim_start_matches("0x");
+                    u64::from_str_radix(hex, 16).unwrap()
+                };
+                let logs: Vec<_> = mints
+                    .iter()
+                    .filter(|(minted, ..)| (block("fromBlock")..=block("toBlock")).contains(minted))
+                    .map(|(minted, lower, upper, amount)| {
+                        let event = DataEvents::Mint {
+                            sender: addr(1),
+                            owner: addr(1),
+                            tickLower: I24::try_from(*lower).unwrap(),
+                            tickUpper: I24::try_from(*upper).unwrap(),
+                            amount: *amount,
+                            amount0: U256::ZERO,
+                            amount1: U256::ZERO,
+                        };
+                        event_log(addr(10), &event, *minted)
+                    })
+                    .collect();
+                MockResponse::Result(json!(logs))
+            }
+            _ => discovered(method, params),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ticks_accumulate_across_resume() {
+        // pool 10 is created at block 120 with liquidity 1000 at tick 0, positions are
+        // minted at block 150 and, after the first sync, at block 250
+        let chain = || minting_chain(vec![(150, -60, 60, 500), (250, -120, 120, 300)]);
+        let dir = tempfile::tempdir().unwrap();
+        let sync = |archive: &MockRpc, end| {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, end)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        let first = MockRpc::start(chain()).await;
+        let (pools, _) = sync(&first, 200).sync_pools().await.unwrap();
+        let pool = pools[0].get_v3().unwrap();
+        // the snapshot already includes the first position
+        assert_eq!(pool.liquidity, 1000);
+        assert_eq!(pool.ticks[&-60].liquidity_net, 500);
+
+        let resumed = MockRpc::start(chain()).await;
+        let (pools, last_synced_block) = sync(&resumed, 300).sync_pools().await.unwrap();
+        assert_eq!(last_synced_block, 300);
+        // the cached pool is replayed from its state, never read from the contract again
+        assert_eq!(resumed.calls("eth_call"), 0);
+        let pool = pools[0].get_v3().unwrap();
+        assert_eq!(pool.liquidity, 1300);
+        let mut ticks: Vec<(i32, i128)> = pool
+            .ticks
+            .iter()
+            .map(|(tick, info)| (*tick, info.liquidity_net))
+            .collect();
+        ticks.sort();
+        assert_eq!(
+            ticks,
+            vec![(-120, 300), (-60, 500), (60, -500), (120, -300)]
+        );
+        assert_eq!(pool.last_update_block, 250);
+        assert_eq!(pool.next_initialized_tick(0, false), Some(60));
+    }
+
+    #[tokio::test]
+    async fn test_liquidity_range_replayed_once() {
+        // the window covers both positions, the second sync must not replay the first again
+        let chain = || minting_chain(vec![(150, -60, 60, 500), (250, -120, 120, 300)]);
+        let dir = tempfile::tempdir().unwrap();
+        let sync = |archive: &MockRpc, end| {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, end)
+                .liquidity_range(100, 300)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        let first = MockRpc::start(chain()).await;
+        sync(&first, 200).sync_pools().await.unwrap();
+        let second = MockRpc::start(chain()).await;
+        let (pools, _) = sync(&second, 300).sync_pools().await.unwrap();
+        let pool = pools[0].get_v3().unwrap();
+        assert_eq!(pool.liquidity, 1300);
+        assert_eq!(pool.ticks[&-60].liquidity_net, 500);
+        assert_eq!(pool.ticks[&-120].liquidity_net, 300);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resume_populates_pending_pools() {
+        let pools: Vec<_> = (1..=3)
+            .map(|n| (addr(10 + n), addr(1), addr(1 + n), 100 + n))
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+
+        // population fails after discovery, interrupting the sync
+        let chain = discovered_chain(pools.clone());
+        let failing = MockRpc::start(move |method, params| match method {
+            "eth_call" => MockResponse::Error(-32000, "execution timeout".into()),
+            _ => chain(method, params),
+        })
+        .await;
+        let result = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .retry_budget(0)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(failing.url()),
+                full_url: Some(failing.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await;
+        assert!(matches!(result, Err(PoolSyncError::RetryBudgetExceeded)));
+
+        // only the checkpoint was written, the cache file is left for the finished sync
+        let pool_type = &PoolType::UniswapV3;
+        assert!(!cache_file_path(dir.path(), pool_type, Chain::Mantle).exists());
+        assert!(checkpoint_file_path(dir.path(), pool_type, Chain::Mantle).exists());
+        let cache = read_cache_file(pool_type, Chain::Mantle, dir.path()).unwrap();
+        assert!(cache.pools.is_empty());
+        assert_eq!(cache.discovered_block, Some(200));
+        assert_eq!(cache.pending.len(), 3);
+        assert!(cache
+            .pending
+            .iter()
+            .all(|pending| pending.state == PopulationState::Discovered));
+
+        // the resumed sync populates the pending pools without rediscovering them
+        let factory_queries = Arc::new(AtomicUsize::new(0));
+        let queries = factory_queries.clone();
+        let chain = discovered_chain(pools.clone());
+        let healthy = MockRpc::start(move |method, params| {
+            if method == "eth_getLogs" && params[0].get("address").is_some() {
+                queries.fetch_add(1, Ordering::SeqCst);
+            }
+            chain(method, params)
+        })
+        .await;
+        let pool_sync = mock_sync(&healthy, &healthy, dir.path(), 100, 200);
+        let (mut synced, last_block) = pool_sync.sync_pools().await.unwrap();
+        assert_eq!(last_block, 200);
+        assert_eq!(factory_queries.load(Ordering::SeqCst), 0);
+
+        synced.sort_by_key(|pool| pool.creation_index());
+        let summary: Vec<_> = synced
+            .iter()
+            .map(|pool| {
+                (
+                    pool.address(),
+                    pool.creation_index(),
+                    pool.created_at_block(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (addr(11), 0, Some(101)),
+                (addr(12), 1, Some(102)),
+                (addr(13), 2, Some(103)),
+            ]
+        );
+
+        let cache = read_cache_file(&PoolType::UniswapV3, Chain::Mantle, dir.path()).unwrap();
+        assert!(cache.pending.is_empty());
+        assert_eq!(cache.discovered_block, None);
+        assert_eq!(cache.next_creation_index, 3);
+        assert!(!checkpoint_file_path(dir.path(), pool_type, Chain::Mantle).exists());
+    }
+
+    #[tokio::test]
+    async fn test_failed_pool_replayed_from_creation() {
+        // pool 10 was created at block 120 and failed population in the sync up to block 200,
+        // its position minted at block 150 is before the blocks of the retrying sync
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PoolCache {
+            last_synced_block: 200,
+            pool_type: PoolType::UniswapV3,
+            pools: Vec::new(),
+            is_initial_sync: false,
+            next_creation_index: 1,
+            pending: vec![PendingPool {
+                creation: PoolCreation {
+                    address: addr(10),
+                    block: 120,
+                    tokens: Some((addr(1), addr(2))),
+                    tx_hash: None,
+                    creator: None,
+                },
+                creation_index: 0,
+                state: PopulationState::Failed,
+            }],
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+
+        let archive = MockRpc::start(minting_chain(vec![(150, -60, 60, 500)])).await;
+        let pool_sync = mock_sync(&archive, &archive, dir.path(), 100, 300);
+        let (pools, _) = pool_sync.sync_pools().await.unwrap();
+        let pool = pools[0].get_v3().unwrap();
+        assert_eq!(pool.ticks[&-60].liquidity_net, 500);
+        assert_eq!(pool.ticks[&60].liquidity_net, -500);
+    }
+
+    #[tokio::test]
+    async fn test_liquidity_provider() {
+        // counts the factory and the liquidity log queries an endpoint receives
+        let counting = |counts: Arc<[AtomicUsize; 2]>| {
+            let chain = discovered_chain(vec![(addr(10), addr(1), addr(2), 120)]);
+            move |method: &str, params: &Value| {
+                if method == "eth_getLogs" {
+                    let factory = params[0].get("address").is_some();
+                    counts[usize::from(!factory)].fetch_add(1, Ordering::SeqCst);
+                }
+                chain(method, params)
+            }
+        };
+        let archive_counts = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+        let liquidity_counts = Arc::new([AtomicUsize::new(0), AtomicUsize::new(0)]);
+        let archive = MockRpc::start(counting(archive_counts.clone())).await;
+        let liquidity = MockRpc::start(counting(liquidity_counts.clone())).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let (pools, _) = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .liquidity_provider(liquidity.url())
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+        assert_eq!(pools.len(), 1);
+
+        // discovery stays on the archive node, the liquidity events move over
+        assert!(archive_counts[0].load(Ordering::SeqCst) > 0);
+        assert_eq!(archive_counts[1].load(Ordering::SeqCst), 0);
+        assert_eq!(liquidity_counts[0].load(Ordering::SeqCst), 0);
+        assert!(liquidity_counts[1].load(Ordering::SeqCst) > 0);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_prices_skips_discovery() {
+        let (v3, v2) = (addr(10), addr(20));
+        let full = MockRpc::start(move |method, params| match method {
+            "eth_blockNumber" => MockResponse::Result(json!("0x12c")),
+            "eth_call" => {
+                let (to, input) = call_target(params);
+                let selector: [u8; 4] = input[..4].try_into().unwrap();
+                match (to, selector) {
+                    (Some(to), V3PoolState::slot0Call::SELECTOR) if to == v3 => {
+                        MockResponse::Result(call_result((
+                            U256::from(2u64) << 96,
+                            I256::try_from(13863).unwrap(),
+                        )))
+                    }
+                    (Some(to), V3PoolState::liquidityCall::SELECTOR) if to == v3 => {
+                        MockResponse::Result(call_result(U256::from(7777u64)))
+                    }
+                    (Some(to), V2PairState::getReservesCall::SELECTOR) if to == v2 => {
+                        MockResponse::Result(call_result((
+                            U256::from(10u64),
+                            U256::from(40u64),
+                            U256::ZERO,
+                        )))
+                    }
+                    _ => MockResponse::Error(3, "execution reverted".into()),
+                }
+            }
+            other => MockResponse::Error(-32601, format!("unexpected method {}", other)),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let v2_pool = Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                address: v2,
+                token0: addr(1),
+                token1: addr(2),
+                ..Default::default()
+            },
+        );
+        for (pool_type, pool) in [
+            (
+                PoolType::UniswapV3,
+                v3_pool(PoolType::UniswapV3, v3, addr(1), addr(2)),
+            ),
+            (PoolType::MerchantMoe, v2_pool),
+        ] {
+            let cache = PoolCache {
+                last_synced_block: 100,
+                pool_type,
+                pools: vec![pool],
+                is_initial_sync: false,
+                next_creation_index: 1,
+                pending: Vec::new(),
+                discovered_block: None,
+            };
+            write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+        }
+
+        let pool_sync = PoolSync::builder()
+            .add_pools(&[PoolType::UniswapV3, PoolType::MerchantMoe])
+            .chain(Chain::Mantle)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(full.url()),
+                full_url: Some(full.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+        let pools = pool_sync.refresh_prices().await.unwrap();
+        assert_eq!(full.calls("eth_getLogs"), 0);
+
+        let v3_pool = pools.iter().find_map(|pool| pool.get_v3()).unwrap();
+        assert_eq!(v3_pool.sqrt_price, U256::from(2u64) << 96);
+        assert_eq!(v3_pool.tick, 13863);
+        assert_eq!(v3_pool.liquidity, 7777);
+        assert_eq!(v3_pool.last_update_block, 300);
+        let v2_pool = pools.iter().find_map(|pool| pool.get_v2()).unwrap();
+        assert_eq!(v2_pool.token0_reserves, U256::from(10u64));
+        assert_eq!(v2_pool.token1_reserves, U256::from(40u64));
+        assert_eq!(v2_pool.last_update_block, 300);
+
+        // the cache keeps the state its events were replayed onto
+        let cached = read_cache_file(&PoolType::UniswapV3, Chain::Mantle, dir.path()).unwrap();
+        assert_eq!(cached.pools[0].get_v3().unwrap().liquidity, 0);
+    }
+
+    #[tokio::test]
+    async fn test_sync_summary_logged() {
+        log_capture::init();
+        let archive = MockRpc::start(discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 7120),
+            (addr(11), addr(1), addr(3), 7130),
+        ]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        mock_sync(&archive, &archive, dir.path(), 7100, 7200)
+            .sync_pools()
+            .await
+            .unwrap();
+        let summaries = log_capture::captured(Level::Info, "7100-7200");
+        assert_eq!(summaries.len(), 1);
+        let row = summaries[0]
+            .lines()
+            .find(|line| line.contains("UniswapV3"))
+            .unwrap();
+        let cells: Vec<&str> = row
+            .split('|')
+            .map(str::trim)
+            .filter(|cell| !cell.is_empty())
+            .collect();
+        assert_eq!(cells[..4], ["UniswapV3", "2", "2", "7100-7200"]);
+
+        // a second sync has nothing left to fetch
+        mock_sync(&archive, &archive, dir.path(), 7100, 7200)
+            .sync_pools()
+            .await
+            .unwrap();
+        assert_eq!(
+            log_capture::captured(Level::Info, "up to date at 7200").len(),
+            1
+        );
+
+        // and the table can be turned off
+        let quiet = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(7300, 7400)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(quiet.url()),
+                full_url: Some(quiet.url()),
+                ws_url: None,
+            })
+            .log_summary(false)
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+        assert!(log_capture::captured(Level::Info, "7300-7400").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_local_chain_factories() {
+        const FACTORIES: &[(PoolType, Address)] = &[(
+            PoolType::UniswapV3,
+            address!("000000000000000000000000000000000000f00d"),
+        )];
+        let local = Chain::Local {
+            chain_id: 31337,
+            factories: FACTORIES,
+        };
+        let queried = Arc::new(Mutex::new(Vec::new()));
+        let factories = queried.clone();
+        let chain = discovered_chain(vec![(addr(10), addr(1), addr(2), 120)]);
+        let node = MockRpc::start(move |method, params| {
+            if let (Some(address), "eth_getLogs") = (params[0].get("address"), method) {
+                factories.lock().unwrap().push(address.clone());
+            }
+            chain(method, params)
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let builder = || {
+            PoolSync::builder()
+                .chain(local)
+                .block_range(100, 200)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(node.url()),
+                    full_url: Some(node.url()),
+                    ws_url: None,
+                })
+        };
+
+        // only the protocols with a local factory can be synced
+        assert!(matches!(
+            builder().add_pool(PoolType::Agni).build(),
+            Err(PoolSyncError::UnsupportedPoolType)
+        ));
+
+        let (pools, _) = builder()
+            .add_pool(PoolType::UniswapV3)
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+        assert_eq!(pools.len(), 1);
+        let queried = queried.lock().unwrap();
+        assert!(!queried.is_empty());
+        assert!(queried
+            .iter()
+            .all(|address| address == &json!(FACTORIES[0].1)));
+        assert!(dir.path().join("Local-31337_UniswapV3_cache.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_http_client_headers() {
+        let archive = MockRpc::start(empty_chain).await;
+        let full = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-api-key", "secret".parse().unwrap());
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+
+        PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .start_block(100)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(full.url()),
+                ws_url: None,
+            })
+            .http_client(client)
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+
+        // both providers send the client's headers with every request, the full node is
+        // asked for the latest block
+        for mock in [&archive, &full] {
+            let keys = mock.header_values("X-Api-Key");
+            assert!(!keys.is_empty());
+            assert!(keys.iter().all(|key| key == "secret"));
+        }
+        assert!(archive.calls("eth_getLogs") > 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_token_metadata_reported() {
+        let reverting = addr(3);
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), reverting, 130),
+        ]);
+        let archive = MockRpc::start(move |method, params| match call_target(params) {
+            (Some(token), _) if method == "eth_call" && token == reverting => {
+                MockResponse::Error(3, "execution reverted".into())
+            }
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let (pools, _, stats) = mock_sync(&archive, &archive, dir.path(), 100, 200)
+            .sync_pools_with_stats()
+            .await
+            .unwrap();
+        // the pool is kept, its token is listed for investigation
+        assert_eq!(pools.len(), 2);
+        assert_eq!(stats.failed_token_metadata, HashSet::from([reverting]));
+    }
+
+    #[tokio::test]
+    async fn test_capture_creators() {
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), addr(3), 130),
+        ]);
+        // each creation log carries its own transaction, sent by addr(70 + n). The first
+        // lookup is rate limited
+        let limited = Arc::new(AtomicBool::new(true));
+        let archive = MockRpc::start(move |method, params| match method {
+            "eth_getLogs" if params[0].get("address").is_some() => {
+                let MockResponse::Result(Value::Array(mut logs)) = chain(method, params) else {
+                    unreachable!()
+                };
+                for (n, log) in logs.iter_mut().enumerate() {
+                    log["transactionHash"] = json!(B256::with_last_byte(n as u8 + 1));
+                }
+                MockResponse::Result(json!(logs))
+            }
+            "eth_getTransactionByHash" if limited.swap(false, Ordering::SeqCst) => {
+                MockResponse::Http(429)
+            }
+            "eth_getTransactionByHash" => {
+                let hash: B256 = serde_json::from_value(params[0].clone()).unwrap();
+                MockResponse::Result(json!({
+                    "hash": hash,
+                    "from": addr(70 + hash[31] as u64),
+                }))
+            }
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = |capture| {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, 200)
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .capture_creators(capture)
+        };
+
+        let (pools, _) = pool_sync(false)
+            .cache_dir(dir.path().join("plain"))
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+        assert!(pools.iter().all(|pool| pool.creator().is_none()));
+        assert_eq!(archive.calls("eth_getTransactionByHash"), 0);
+
+        let (mut pools, _) = pool_sync(true)
+            .cache_dir(dir.path().join("creators"))
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+        pools.sort_by_key(|pool| pool.address());
+        // the rate limited batch is retried
+        let creators: Vec<_> = pools.iter().map(|pool| pool.creator()).collect();
+        assert_eq!(creators, vec![Some(addr(71)), Some(addr(72))]);
+    }
+
+    #[tokio::test]
+    async fn test_all_protocols_past_target() {
+        log_capture::init();
+        let archive = MockRpc::start(empty_chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        for (pool_type, block) in [(PoolType::UniswapV3, 950), (PoolType::Agni, 900)] {
+            let cache = PoolCache {
+                last_synced_block: block,
+                pool_type,
+                pools: vec![v3_pool(pool_type, addr(block), addr(1), addr(2))],
+                is_initial_sync: false,
+                next_creation_index: 1,
+                pending: Vec::new(),
+                discovered_block: None,
+            };
+            write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+        }
+
+        let (pools, last_synced_block) = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .add_pool(PoolType::Agni)
+            .chain(Chain::Mantle)
+            .block_range(100, 871)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap()
+            .sync_pools()
+            .await
+            .unwrap();
+
+        // nothing is fetched and the pools are as of the oldest cache, not block 0
+        assert_eq!(pools.len(), 2);
+        assert_eq!(last_synced_block, 900);
+        assert_eq!(archive.calls("eth_getLogs"), 0);
+        assert_eq!(
+            log_capture::captured(Level::Info, "already synced past target block 871").len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_factories() {
+        const BOGUS: Address = Address::repeat_byte(0xde);
+        const FACTORIES: &[(PoolType, Address)] = &[(PoolType::UniswapV3, BOGUS)];
+        let deployed = Arc::new(Mutex::new(false));
+        let has_code = deployed.clone();
+        let archive = MockRpc::start(move |method, params| match method {
+            "eth_getCode" => {
+                assert_eq!(params[0], json!(BOGUS));
+                let code = if *has_code.lock().unwrap() {
+                    "0x6080"
+                } else {
+                    "0x"
+                };
+                MockResponse::Result(json!(code))
+            }
+            _ => empty_chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Local {
+                chain_id: 31337,
+                factories: FACTORIES,
+            })
+            .block_range(100, 200)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(archive.url()),
+                full_url: Some(archive.url()),
+                ws_url: None,
+            })
+            .validate_factories(true)
+            .build()
+            .unwrap();
+
+        match pool_sync.sync_pools().await {
+            Err(PoolSyncError::NoCodeAtFactory { pool_type, address }) => {
+                assert_eq!(pool_type, PoolType::UniswapV3);
+                assert_eq!(address, BOGUS);
+            }
+            other => panic!("expected NoCodeAtFactory, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(archive.calls("eth_getLogs"), 0);
+
+        // once the factory is deployed the sync goes ahead
+        *deployed.lock().unwrap() = true;
+        pool_sync.sync_pools().await.unwrap();
+        assert!(archive.calls("eth_getLogs") > 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_epoch_files() {
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), addr(3), 140),
+            (addr(12), addr(1), addr(4), 180),
+        ]);
+        let archive = MockRpc::start(chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = |epoch_size| {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, 200)
+                .cache_dir(dir.path())
+                .cache_epoch_size(epoch_size)
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        let (pools, _) = pool_sync(150).sync_pools().await.unwrap();
+        assert_eq!(pools.len(), 3);
+
+        // the pools are split across the epoch boundary at block 150
+        let files = epoch_files(dir.path(), &PoolType::UniswapV3, Chain::Mantle).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "Mantle_UniswapV3_cache_0-149.json",
+                "Mantle_UniswapV3_cache_150-299.json"
+            ]
+        );
+        let epoch: Vec<Value> =
+            serde_json::from_str(&std::fs::read_to_string(&files[1]).unwrap()).unwrap();
+        assert_eq!(epoch.len(), 1);
+        assert_eq!(epoch[0]["UniswapV3"]["address"], json!(addr(12)));
+
+        // reading the cache brings the epochs back together
+        let cache = read_cache_file(&PoolType::UniswapV3, Chain::Mantle, dir.path()).unwrap();
+        assert_eq!(cache.last_synced_block, 200);
+        let mut addresses: Vec<_> = cache.pools.iter().map(|pool| pool.address()).collect();
+        addresses.sort();
+        assert_eq!(addresses, vec![addr(10), addr(11), addr(12)]);
+
+        // an archived epoch is no longer loaded
+        std::fs::remove_file(&files[0]).unwrap();
+        let pools = pool_sync(150).load_cached_pools().unwrap();
+        assert_eq!(pools.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_sample() {
+        let sqrt_price = U256::from(1u64) << 96;
+        // addr(11) reports less liquidity than was synced and addr(12) has no contract. The
+        // Agni pool addr(14) is read at its own cache's block 150, the others at 100
+        let full = MockRpc::start(move |method, params| {
+            assert_eq!(method, "eth_call");
+            let (Some(to), input) = call_target(params) else {
+                unreachable!()
+            };
+            assert_eq!(params[1], if to == addr(14) { "0x96" } else { "0x64" });
+            let selector: [u8; 4] = input[..4].try_into().unwrap();
+            let result = match selector {
+                _ if to == addr(12) => return MockResponse::Error(3, "execution reverted".into()),
+                V3PoolState::slot0Call::SELECTOR => call_result((
+                    sqrt_price,
+                    I256::try_from(-5).unwrap(),
+                    U256::ZERO,
+                    U256::from(1u64),
+                    U256::from(1u64),
+                    U256::ZERO,
+                    true,
+                )),
+                V3PoolState::liquidityCall::SELECTOR if to == addr(11) => {
+                    call_result(U256::from(4000u64))
+                }
+                V3PoolState::liquidityCall::SELECTOR => call_result(U256::from(5000u64)),
+                V3PoolState::tickSpacingCall::SELECTOR => call_result(U256::from(60u64)),
+                V3PoolState::feeCall::SELECTOR => call_result(U256::from(3000u64)),
+                _ => return MockResponse::Error(3, "execution reverted".into()),
+            };
+            MockResponse::Result(result)
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool = |pool_type, n| {
+            Pool::new_v3(
+                pool_type,
+                UniswapV3Pool {
+                    address: addr(n),
+                    sqrt_price,
+                    tick: -5,
+                    liquidity: 5000,
+                    tick_spacing: 60,
+                    fee: 3000,
+                    ..Default::default()
+                },
+            )
+        };
+        let mut pools: Vec<Pool> = (10..13).map(|n| pool(PoolType::UniswapV3, n)).collect();
+        let cache = PoolCache {
+            last_synced_block: 100,
+            pool_type: PoolType::UniswapV3,
+            pools: pools.clone(),
+            is_initial_sync: false,
+            next_creation_index: 3,
+            pending: Vec::new(),
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+        pools.push(pool(PoolType::Agni, 14));
+        // V2 pools are never sampled
+        pools.push(Pool::new_v2(
+            PoolType::MerchantMoe,
+            MerchantMoeV2Pool {
+                address: addr(13),
+                ..Default::default()
+            },
+        ));
+
+        let build = |rate_limit| {
+            PoolSync::builder()
+                .add_pools(&[PoolType::UniswapV3, PoolType::Agni])
+                .chain(Chain::Mantle)
+                .cache_dir(dir.path())
+                .rate_limit(rate_limit)
+                .rpc_config(RpcConfig {
+                    archive_url: Some(full.url()),
+                    full_url: Some(full.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+        let (pool_sync, paced) = (build(20), build(10));
+
+        // Agni has no cache yet, its pool is reported rather than sampled
+        let report = pool_sync.verify_sample(&pools, 10).await.unwrap();
+        assert_eq!(report.unsynced, vec![PoolType::Agni]);
+        assert_eq!(report.pools.len(), 3);
+        assert!(report.pools.iter().all(|pool| pool.block == 100));
+        let mut failed: Vec<Address> = report.failed().iter().map(|pool| pool.address).collect();
+        failed.sort();
+        assert_eq!(failed, vec![addr(11), addr(12)]);
+        let mismatch = report
+            .pools
+            .iter()
+            .find(|pool| pool.address == addr(11))
+            .unwrap();
+        assert_eq!(
+            mismatch.report.as_ref().unwrap().mismatches(),
+            vec!["liquidity"]
+        );
+        assert!(report
+            .pools
+            .iter()
+            .find(|pool| pool.address == addr(12))
+            .unwrap()
+            .report
+            .is_err());
+        assert_eq!(report.pass_rate(), Some(1.0 / 3.0));
+        let confidence = report.confidence();
+        assert!(confidence > 0.0 && confidence < 1.0 / 3.0, "{}", confidence);
+
+        // each protocol is compared at the block its own cache was synced to
+        let cache = PoolCache {
+            last_synced_block: 150,
+            pool_type: PoolType::Agni,
+            pools: vec![pool(PoolType::Agni, 14)],
+            is_initial_sync: false,
+            next_creation_index: 1,
+            pending: Vec::new(),
+            discovered_block: None,
+        };
+        write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+        let report = pool_sync.verify_sample(&pools, 10).await.unwrap();
+        assert!(report.unsynced.is_empty());
+        assert_eq!(report.pools.len(), 4);
+        let agni = report
+            .pools
+            .iter()
+            .find(|pool| pool.address == addr(14))
+            .unwrap();
+        assert_eq!((agni.pool_type, agni.block), (PoolType::Agni, 150));
+        assert!(agni.passed());
+
+        let report = pool_sync.verify_sample(&pools, 2).await.unwrap();
+        assert_eq!(report.pools.len(), 2);
+        let report = pool_sync.verify_sample(&pools, 0).await.unwrap();
+        assert_eq!(report.pass_rate(), None);
+        assert_eq!(report.confidence(), 0.0);
+
+        // the four reads of a pool are paced at the rate limit, 10 per second
+        let started = std::time::Instant::now();
+        let report = paced.verify_sample(&pools[..1], 1).await.unwrap();
+        assert!(report.pools[0].passed());
+        assert!(started.elapsed() >= std::time::Duration::from_millis(300));
+    }
+
+    /// Keeps the caches in memory, keyed by chain and protocol
+    #[derive(Default)]
+    struct MemoryCacheStore {
+        caches: Mutex<HashMap<(String, PoolType), PoolCache>>,
+        writes: AtomicUsize,
+        fail_writes: AtomicBool,
+    }
+
+    impl CacheStore for MemoryCacheStore {
+        fn read(
+            &self,
+            pool_type: &PoolType,
+            chain: Chain,
+        ) -> Result<Option<PoolCache>, PoolSyncError> {
+            let caches = self.caches.lock().unwrap();
+            Ok(caches.get(&(chain.to_string(), *pool_type)).cloned())
+        }
+
+        fn write(&self, cache: &PoolCache, chain: Chain) -> Result<(), PoolSyncError> {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            if self.fail_writes.load(Ordering::SeqCst) {
+                return Err(PoolSyncError::CacheError("store unavailable".into()));
+            }
+            let mut caches = self.caches.lock().unwrap();
+            caches.insert((chain.to_string(), cache.pool_type), cache.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_cache_store() {
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), addr(3), 130),
+        ]);
+        let archive = MockRpc::start(chain).await;
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(MemoryCacheStore::default());
+        let pool_sync = |end_block| {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, end_block)
+                .cache_dir(dir.path())
+                .cache_store(store.clone())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        let (pools, _) = pool_sync(200).sync_pools().await.unwrap();
+        assert_eq!(pools.len(), 2);
+        // nothing touches the cache directory
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        let cache = store
+            .read(&PoolType::UniswapV3, Chain::Mantle)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cache.last_synced_block, 200);
+        assert_eq!(cache.pools.len(), 2);
+        assert!(store.writes.load(Ordering::SeqCst) > 0);
+
+        // the next sync resumes from the store
+        let factory_queries = archive.calls("eth_getLogs");
+        let (pools, last_synced_block) = pool_sync(200).sync_pools().await.unwrap();
+        assert_eq!(pools.len(), 2);
+        assert_eq!(last_synced_block, 200);
+        assert_eq!(archive.calls("eth_getLogs"), factory_queries);
+        assert_eq!(pool_sync(200).load_cached_pools().unwrap().len(), 2);
+
+        // a failed write is reported instead of panicking
+        store.fail_writes.store(true, Ordering::SeqCst);
+        let result = pool_sync(200).sync_pools().await;
+        assert!(
+            matches!(result, Err(PoolSyncError::CacheError(ref e)) if e == "store unavailable"),
+            "{:?}",
+            result.map(|(pools, _)| pools.len())
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block_number_failures_are_retried() {
+        let failures = Arc::new(AtomicUsize::new(2));
+        let remaining = failures.clone();
+        let rpc = MockRpc::start(move |method, params| match method {
+            "eth_blockNumber" if remaining.load(Ordering::SeqCst) > 0 => {
+                remaining.fetch_sub(1, Ordering::SeqCst);
+                MockResponse::Error(-32000, "header not found".into())
+            }
+            _ => empty_chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = || {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .start_block(100)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(rpc.url()),
+                    full_url: Some(rpc.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        // a flaky tip lookup is retried
+        let (_, last_synced_block) = pool_sync().sync_pools().await.unwrap();
+        assert_eq!(last_synced_block, 200);
+
+        // one that keeps failing is reported instead of panicking
+        failures.store(usize::MAX, Ordering::SeqCst);
+        let calls = rpc.calls("eth_blockNumber");
+        let result = pool_sync().sync_pools().await;
+        assert!(
+            matches!(result, Err(PoolSyncError::RpcError(_))),
+            "{:?}",
+            result.err()
+        );
+        assert_eq!(rpc.calls("eth_blockNumber") - calls, 6);
+    }
+
+    #[tokio::test]
+    async fn test_new_pool_addresses() {
+        let archive = MockRpc::start(discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), addr(3), 250),
+            (addr(12), addr(2), addr(3), 280),
+        ]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = |end_block| {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, end_block)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        let (_, _, stats) = pool_sync(200).sync_pools_with_stats().await.unwrap();
+        assert_eq!(stats.new_pool_addresses, vec![addr(10)]);
+
+        // the cached pool is returned but not reported as new
+        let (pools, _, stats) = pool_sync(300).sync_pools_with_stats().await.unwrap();
+        assert_eq!(pools.len(), 3);
+        let mut new_pools = stats.new_pool_addresses;
+        new_pools.sort();
+        assert_eq!(new_pools, vec![addr(11), addr(12)]);
+
+        // nothing is new once the caches are up to date
+        let (_, _, stats) = pool_sync(300).sync_pools_with_stats().await.unwrap();
+        assert!(stats.new_pool_addresses.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_warmup_tokens() {
+        let token_calls = Arc::new(Mutex::new(Vec::new()));
+        let seen = token_calls.clone();
+        let chain = discovered_chain(vec![(addr(10), addr(1), addr(3), 120)]);
+        let rpc = MockRpc::start(move |method, params| match call_target(params) {
+            (Some(token), input) if method == "eth_call" && token != addr(3) => {
+                seen.lock().unwrap().push(token);
+                if token != addr(1) {
+                    MockResponse::Error(-32000, "internal error".into())
+                } else if input.starts_with(&ERC20::decimalsCall::SELECTOR) {
+                    MockResponse::Result(call_result(U256::from(6)))
+                } else {
+                    MockResponse::Result(call_result("WMNT".to_string()))
+                }
+            }
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .chain(Chain::Mantle)
+            .block_range(100, 200)
+            .token_name_attempts(1)
+            .cache_dir(dir.path())
+            .rpc_config(RpcConfig {
+                archive_url: Some(rpc.url()),
+                full_url: Some(rpc.url()),
+                ws_url: None,
+            })
+            .build()
+            .unwrap();
+
+        // the token that keeps failing is left out of the cache
+        pool_sync.warmup_tokens(&[addr(1), addr(2)]).await.unwrap();
+        let tokens = pool_sync.cache_store.read_tokens(Chain::Mantle).unwrap();
+        let metadata = TokenMetadata {
+            name: "WMNT".to_string(),
+            decimals: Some(6),
+        };
+        assert_eq!(
+            tokens.into_iter().collect::<Vec<_>>(),
+            vec![(addr(1), metadata)]
+        );
+        assert_eq!(
+            token_calls
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|t| **t == addr(1))
+                .count(),
+            2
+        );
+
+        // cached tokens are not called again, by a second warmup or by the sync
+        pool_sync.warmup_tokens(&[addr(1)]).await.unwrap();
+        let (pools, _) = pool_sync.sync_pools().await.unwrap();
+        assert_eq!(pools[0].token0_name(), "WMNT");
+        assert_eq!(pools[0].token1_name(), "TKN");
+        assert_eq!(
+            token_calls
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|t| **t == addr(1))
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_non_erc20_tokens_are_flagged() {
+        // addr(3) is a contract without `decimals()`, e.g. an NFT
+        let chain = discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), addr(3), 130),
+        ]);
+        let rpc = MockRpc::start(move |method, params| match call_target(params) {
+            (Some(token), input)
+                if method == "eth_call" && token != addr(10) && token != addr(11) =>
+            {
+                if input.starts_with(&ERC20::symbolCall::SELECTOR) {
+                    MockResponse::Result(call_result("TKN".to_string()))
+                } else if token == addr(3) && input.starts_with(&ERC20::decimalsCall::SELECTOR) {
+                    MockResponse::Error(3, "execution reverted".into())
+                } else {
+                    MockResponse::Result(call_result(U256::from(18)))
+                }
+            }
+            _ => chain(method, params),
+        })
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool_sync = |check| {
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, 200)
+                .check_erc20_compliance(check)
+                .cache_dir(dir.path())
+                .rpc_config(RpcConfig {
+                    archive_url: Some(rpc.url()),
+                    full_url: Some(rpc.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        let (pools, _, stats) = pool_sync(true).sync_pools_with_stats().await.unwrap();
+        assert_eq!(pools.len(), 2);
+        assert_eq!(stats.non_erc20_tokens, HashSet::from([addr(3)]));
+
+        // the probe is off by default
+        std::fs::remove_dir_all(dir.path()).unwrap();
+        let (_, _, stats) = pool_sync(false).sync_pools_with_stats().await.unwrap();
+        assert!(stats.non_erc20_tokens.is_empty());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_sync_metrics() {
+        use crate::{
+            METRIC_CHUNK_DURATION, METRIC_POOLS_DISCOVERED, METRIC_POOLS_POPULATED,
+            METRIC_RPC_CALLS, METRIC_RPC_ERRORS,
+        };
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        // the recorder is local to this thread, which runs the whole sync
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(async {
+                let rpc = MockRpc::start(discovered_chain(vec![
+                    (addr(10), addr(1), addr(2), 120),
+                    (addr(11), addr(1), addr(3), 130),
+                ]))
+                .await;
+                mock_sync(&rpc, &rpc, dir.path(), 100, 200)
+                    .sync_pools()
+                    .await
+                    .unwrap();
+            })
+        });
+
+        let metrics: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels: Vec<String> = key
+                    .labels()
+                    .map(|label| format!("{}={}", label.key(), label.value()))
+                    .collect();
+                (key.name().to_string(), labels.join(","), value)
+            })
+            .collect();
+        let counter = |name: &str, labels: &str| {
+            metrics
+                .iter()
+                .find(|(n, l, _)| n == name && l == labels)
+                .map(|(_, _, value)| match value {
+                    DebugValue::Counter(count) => *count,
+                    other => panic!("{} is not a counter: {:?}", name, other),
+                })
+        };
+
+        assert_eq!(
+            counter(METRIC_POOLS_DISCOVERED, "pool_type=UniswapV3"),
+            Some(2)
+        );
+        assert_eq!(
+            counter(METRIC_POOLS_POPULATED, "pool_type=UniswapV3"),
+            Some(2)
+        );
+        assert!(counter(METRIC_RPC_CALLS, "method=eth_getLogs").unwrap() >= 2);
+        assert_eq!(counter(METRIC_RPC_CALLS, "method=eth_call"), Some(1));
+        assert!(metrics.iter().all(|(name, _, _)| name != METRIC_RPC_ERRORS));
+        let chunks = metrics
+            .iter()
+            .find(|(name, _, _)| name == METRIC_CHUNK_DURATION)
+            .map(|(_, _, value)| value);
+        assert!(matches!(chunks, Some(DebugValue::Histogram(durations)) if !durations.is_empty()));
+    }
+
+    #[test]
+    fn test_cache_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = PoolCache::new(PoolType::UniswapV3, Chain::Mantle);
+        cache.last_synced_block = 150;
+        cache.pools = vec![Pool::new_v3(
+            PoolType::UniswapV3,
+            UniswapV3Pool {
+                address: addr(10),
+                created_at_block: Some(120),
+                ..Default::default()
+            },
+        )];
+        FileCacheStore {
+            dir: dir.path().to_path_buf(),
+            epoch_size: Some(1000),
+        }
+        .write(&cache, Chain::Mantle)
+        .unwrap();
+        let pool_sync = PoolSync::builder()
+            .add_pool(PoolType::UniswapV3)
+            .add_pool(PoolType::Agni)
+            .chain(Chain::Mantle)
+            .cache_dir(dir.path())
+            .cache_epoch_size(1000)
+            .build()
+            .unwrap();
+
+        let plan = pool_sync.cache_plan();
+        let path = |name: &str| dir.path().join(name);
+        assert_eq!(
+            plan,
+            vec![
+                CacheFileInfo {
+                    path: path("Mantle_UniswapV3_cache.json"),
+                    pool_type: PoolType::UniswapV3,
+                    exists: true,
+                    last_synced_block: Some(150),
+                },
+                CacheFileInfo {
+                    path: path("Mantle_UniswapV3_cache_0-999.json"),
+                    pool_type: PoolType::UniswapV3,
+                    exists: true,
+                    last_synced_block: Some(150),
+                },
+                // never synced, the file is created by the first sync
+                CacheFileInfo {
+                    path: path("Mantle_Agni_cache.json"),
+                    pool_type: PoolType::Agni,
+                    exists: false,
+                    last_synced_block: None,
+                },
+            ]
+        );
+        // the plan only reads
+        assert!(!path("Mantle_Agni_cache.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_cache_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        for (pool_type, block) in [(PoolType::UniswapV3, 150), (PoolType::Agni, 320)] {
+            let mut cache = PoolCache::new(pool_type, Chain::Mantle);
+            cache.last_synced_block = block;
+            cache.pools = vec![v3_pool(pool_type, addr(10), addr(1), addr(2))];
+            write_cache_file(&cache, Chain::Mantle, dir.path()).unwrap();
+        }
+        let pool_sync = PoolSync::builder()
+            .add_pools(&[PoolType::UniswapV3, PoolType::MerchantMoe, PoolType::Agni])
+            .chain(Chain::Mantle)
+            .cache_dir(dir.path())
+            .build()
+            .unwrap();
+
+        // MerchantMoe has no cache yet and reads as a fresh one
+        let pool_types = [PoolType::MerchantMoe, PoolType::Agni, PoolType::UniswapV3];
+        let concurrent = pool_sync.read_caches(&pool_types).await.unwrap();
+        let sequential: Vec<_> = pool_types
+            .iter()
+            .map(|pool_type| pool_sync.read_cache(pool_type).unwrap())
+            .collect();
+        assert_eq!(
+            serde_json::to_value(&concurrent).unwrap(),
+            serde_json::to_value(&sequential).unwrap()
+        );
+        let blocks: Vec<_> = concurrent
+            .iter()
+            .map(|cache| (cache.pool_type, cache.last_synced_block))
+            .collect();
+        assert_eq!(
+            blocks[1..],
+            [(PoolType::Agni, 320), (PoolType::UniswapV3, 150)]
+        );
+        assert!(concurrent[0].pools.is_empty());
+
+        // a corrupt cache is an error rather than a panic
+        std::fs::write(dir.path().join("Mantle_MerchantMoe_cache.json"), "{").unwrap();
+        assert!(matches!(
+            pool_sync.read_caches(&pool_types).await,
+            Err(PoolSyncError::CacheError(_))
+        ));
+    }
+
+    #[test]
+    fn test_sync_progress() {
+        let cache = |block| {
+            let mut cache = PoolCache::new(PoolType::UniswapV3, Chain::Mantle);
+            cache.last_synced_block = block;
+            cache
+        };
+        // fresh caches synced from block 100 to 300: untouched, half way, done and past
+        // the target
+        let started = [cache(0), cache(0), cache(0), cache(0)];
+        let caches = [cache(0), cache(200), cache(300), cache(350)];
+        let progress = SyncProgress::from_caches(&started, &caches, Some(100), None, 300);
+        assert_eq!(progress.blocks_done, 101 + 201 + 201);
+        assert_eq!(progress.blocks_total, 4 * 201);
+        assert!((progress.percent - 503.0 / 804.0 * 100.0).abs() < 1e-9);
+
+        // resumed caches count from the block they were synced to, not from the genesis or
+        // an earlier start block
+        let started = [cache(150), cache(250)];
+        let caches = [cache(200), cache(250)];
+        let progress = SyncProgress::from_caches(&started, &caches, None, None, 300);
+        assert_eq!((progress.blocks_done, progress.blocks_total), (50, 200));
+        assert_eq!(progress.percent, 25.0);
+        let progress = SyncProgress::from_caches(&started, &caches, Some(100), None, 300);
+        assert_eq!((progress.blocks_done, progress.blocks_total), (50, 200));
+
+        // a forced start counts from the forced block, whatever the start block
+        let started = [cache(0)];
+        let caches = [cache(200)];
+        let progress = SyncProgress::from_caches(&started, &caches, Some(100), Some(50), 300);
+        assert_eq!((progress.blocks_done, progress.blocks_total), (151, 251));
+
+        let progress = SyncProgress::from_caches(&[], &[], None, None, 300);
+        assert_eq!((progress.blocks_done, progress.blocks_total), (0, 0));
+        assert_eq!(progress.percent, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_progress_callback() {
+        let archive = MockRpc::start(discovered_chain(vec![
+            (addr(10), addr(1), addr(2), 120),
+            (addr(11), addr(1), addr(3), 180),
+        ]))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let pool_sync = |end_block| {
+            let reported = reported.clone();
+            PoolSync::builder()
+                .add_pool(PoolType::UniswapV3)
+                .chain(Chain::Mantle)
+                .block_range(100, end_block)
+                .cache_dir(dir.path())
+                .on_progress(Box::new(move |progress: &SyncProgress| {
+                    reported.lock().unwrap().push(*progress)
+                }))
+                .rpc_config(RpcConfig {
+                    archive_url: Some(archive.url()),
+                    full_url: Some(archive.url()),
+                    ws_url: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        pool_sync(150).sync_pools().await.unwrap();
+        reported.lock().unwrap().clear();
+
+        // the cache resumes half way, only the blocks after it are left to sync
+        pool_sync(200).sync_pools().await.unwrap();
+        let reported: Vec<_> = reported
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|progress| {
+                (
+                    progress.blocks_done,
+                    progress.blocks_total,
+                    progress.percent,
+                )
+            })
+            .collect();
+        assert_eq!(reported, vec![(0, 50, 0.0), (50, 50, 100.0)]);
+    }
+}
@@ -17,6 +17,7 @@ pub fn create_progress_bar(total_steps: u64, info: String) -> ProgressBar {
 }
 
 /// Creates a simpler progress bar without elapsed time for sub-tasks
+#[allow(dead_code)]
 pub fn create_simple_progress_bar(total_steps: u64, info: String) -> ProgressBar {
     let pb = ProgressBar::new(total_steps);
     pb.set_style(